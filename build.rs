@@ -0,0 +1,20 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "capi")]
+    write_c_header();
+}
+
+// into OUT_DIR, not the source tree; tests/capi_smoke.rs checks include/chip8.h against it
+#[cfg(feature = "capi")]
+fn write_c_header() {
+    println!("cargo:rerun-if-changed=src/capi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).expect("can't read cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(format!("{}/src/capi.rs", crate_dir))
+        .generate()
+        .expect("can't generate the C header")
+        .write_to_file(format!("{}/chip8.h", std::env::var("OUT_DIR").unwrap()));
+}
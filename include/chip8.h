@@ -0,0 +1,171 @@
+#ifndef CHIP8_H
+#define CHIP8_H
+
+/* Generated with cbindgen from src/capi.rs, don't edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+typedef struct Chip8 Chip8;
+
+#define CHIP8_EVENT_SOUND_ON 1
+
+#define CHIP8_EVENT_SOUND_OFF (1 << 1)
+
+#define CHIP8_EVENT_WAITING_FOR_KEY (1 << 2)
+
+#define CHIP8_EVENT_BREAKPOINT (1 << 3)
+
+#define CHIP8_EVENT_STOPPED (1 << 4)
+
+#define CHIP8_EVENT_SANITIZER (1 << 5)
+
+typedef enum Chip8Status {
+  CHIP8_STATUS_OK = 0,
+  CHIP8_STATUS_NULL_POINTER = 1,
+  CHIP8_STATUS_ROM_TOO_LARGE = 2,
+  CHIP8_STATUS_BAD_STATE = 3,
+  CHIP8_STATUS_DECODE_ERROR = 4,
+  CHIP8_STATUS_INVALID_ROM = 5,
+  CHIP8_STATUS_SANITIZER = 6,
+} Chip8Status;
+
+typedef struct Chip8Registers {
+  uint8_t v[16];
+  uint16_t i;
+  uint16_t pc;
+  uint8_t sp;
+  uint8_t delay_timer;
+  uint8_t sound_timer;
+  uint16_t stack[16];
+} Chip8Registers;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Emulator with the ROM loaded and default settings; null if data is null or the ROM doesn't fit.
+ * Free with chip8_free.
+ *
+ * # Safety
+ * `rom` must be valid for reading `len` bytes
+ */
+Chip8 *chip8_new(const uint8_t *rom, size_t len);
+
+/**
+ * # Safety
+ * `chip8` must come from chip8_new and not be used afterwards
+ */
+void chip8_free(Chip8 *chip8);
+
+/**
+ * Swaps the ROM and resets
+ *
+ * # Safety
+ * `chip8` from chip8_new; `rom` valid for reading `len` bytes
+ */
+enum Chip8Status chip8_load_rom(Chip8 *chip8, const uint8_t *rom, size_t len);
+
+/**
+ * # Safety
+ * `chip8` from chip8_new
+ */
+void chip8_reset(Chip8 *chip8);
+
+/**
+ * Seeds RND; the same seed gives the same numbers
+ *
+ * # Safety
+ * `chip8` from chip8_new
+ */
+void chip8_seed(Chip8 *chip8, uint64_t seed);
+
+/**
+ * Instructions per second, default 600
+ *
+ * # Safety
+ * `chip8` from chip8_new
+ */
+void chip8_set_clock(Chip8 *chip8, uint32_t instructions_per_second);
+
+/**
+ * Runs one 60 Hz frame; returns CHIP8_EVENT_* flags of what happened
+ *
+ * # Safety
+ * `chip8` from chip8_new
+ */
+uint32_t chip8_run_frame(Chip8 *chip8);
+
+/**
+ * Runs a single instruction without ticking the timers
+ *
+ * # Safety
+ * `chip8` from chip8_new
+ */
+enum Chip8Status chip8_step(Chip8 *chip8);
+
+/**
+ * Keypad key 0x0..=0xF
+ *
+ * # Safety
+ * `chip8` from chip8_new
+ */
+void chip8_key_down(Chip8 *chip8, uint8_t key);
+
+/**
+ * # Safety
+ * `chip8` from chip8_new
+ */
+void chip8_key_up(Chip8 *chip8, uint8_t key);
+
+uint32_t chip8_framebuffer_width(void);
+
+uint32_t chip8_framebuffer_height(void);
+
+/**
+ * Copies one byte per pixel (0 or 1), row-major, into out if it holds width * height bytes.
+ * Returns width * height either way
+ *
+ * # Safety
+ * `chip8` from chip8_new; `out` valid for writing `len` bytes
+ */
+size_t chip8_framebuffer(const Chip8 *chip8, uint8_t *out, size_t len);
+
+/**
+ * # Safety
+ * `chip8` from chip8_new; `out` a writable Chip8Registers
+ */
+enum Chip8Status chip8_registers(const Chip8 *chip8, struct Chip8Registers *out);
+
+/**
+ * Whether the tone should sound, i.e. the sound timer is non-zero
+ *
+ * # Safety
+ * `chip8` from chip8_new
+ */
+bool chip8_sound_on(const Chip8 *chip8);
+
+size_t chip8_state_size(void);
+
+/**
+ * Writes chip8_state_size() bytes to out; returns the number written, 0 if out is too small
+ *
+ * # Safety
+ * `chip8` from chip8_new; `out` valid for writing `len` bytes
+ */
+size_t chip8_save_state(const Chip8 *chip8, void *out, size_t len);
+
+/**
+ * # Safety
+ * `chip8` from chip8_new; `data` valid for reading `len` bytes
+ */
+enum Chip8Status chip8_load_state(Chip8 *chip8, const void *data, size_t len);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* CHIP8_H */
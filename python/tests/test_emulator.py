@@ -0,0 +1,106 @@
+"""pip install maturin pytest numpy && maturin develop && pytest"""
+import pytest
+
+from rust_wasm_chip8 import Emulator
+
+# draws the font sprite for 0 at (0, 0), beeps for 5 frames, then loops
+DRAW_ZERO = bytes([
+    0x60, 0x00,  # LD V0, 0
+    0x61, 0x00,  # LD V1, 0
+    0xF0, 0x29,  # LD F, V0
+    0xD0, 0x15,  # DRW V0, V1, 5
+    0x62, 0x05,  # LD V2, 5
+    0xF2, 0x18,  # LD ST, V2
+    0x12, 0x0C,  # JP 0x20C
+])
+
+# V0 = RND 0xFF, then loops
+RANDOM = bytes([0xC0, 0xFF, 0x12, 0x02])
+
+
+def test_step_frame_draws_and_beeps():
+    emu = Emulator(DRAW_ZERO)
+    events = emu.step_frame()
+    assert "frame" in events
+    assert "sound_on" in events
+    assert emu.sound_on
+    assert emu.pc == 0x20C
+    assert emu.registers[2] == 5
+    assert emu.sound_timer == 4
+    fb = emu.framebuffer()
+    assert (fb.width, fb.height) == (64, 32)
+    pixels = fb.tobytes()
+    assert pixels[0:5] == bytes([1, 1, 1, 1, 0])
+    assert pixels[64:69] == bytes([1, 0, 0, 1, 0])
+
+
+def test_framebuffer_as_numpy_array():
+    np = pytest.importorskip("numpy")
+    emu = Emulator(DRAW_ZERO)
+    emu.step_frame()
+    pixels = np.asarray(emu.framebuffer())
+    assert pixels.shape == (32, 64)
+    assert pixels.dtype == np.uint8
+    assert pixels[1, :5].tolist() == [1, 0, 0, 1, 0]
+    assert pixels.sum() == 14
+
+
+def test_keys_release_fx0a():
+    emu = Emulator(bytes([0xF3, 0x0A, 0x12, 0x02]))
+    assert "waiting_for_key" in emu.step_frame()
+    assert emu.waiting_for_key
+    emu.key_down(0xA)
+    emu.key_up(0xA)
+    assert emu.registers[3] == 0xA
+    assert not emu.waiting_for_key
+
+
+def test_memory_and_registers():
+    emu = Emulator(DRAW_ZERO)
+    assert emu.memory(0x200, 2) == bytes([0x60, 0x00])
+    assert len(emu.memory()) == 4096
+    # the font for 0
+    assert emu.memory(0, 5) == bytes([0xF0, 0x90, 0x90, 0x90, 0xF0])
+    emu.write_memory(0x201, bytes([0x07]))
+    emu.step()
+    assert emu.registers[0] == 7
+    emu.set_register(0xE, 0x42)
+    assert emu.registers[0xE] == 0x42
+    with pytest.raises(ValueError):
+        emu.write_memory(0xFFF, bytes([1, 2]))
+    with pytest.raises(ValueError):
+        emu.set_register(16, 0)
+
+
+def test_seed_control():
+    def first_number(**kwargs):
+        emu = Emulator(RANDOM, **kwargs)
+        emu.step_frame()
+        return emu.registers[0]
+
+    assert first_number(seed=7) == first_number(seed=7)
+    emu = Emulator(RANDOM)
+    emu.seed(7)
+    emu.step_frame()
+    assert emu.registers[0] == first_number(seed=7)
+
+
+def test_save_and_load_state():
+    emu = Emulator(DRAW_ZERO)
+    emu.step_frame()
+    state = emu.save_state()
+    emu.step_frames(5)
+    assert not emu.sound_on
+    emu.load_state(state)
+    assert emu.sound_on
+    assert emu.sound_timer == 4
+    with pytest.raises(ValueError):
+        emu.load_state(state[1:])
+
+
+def test_rejects_bad_input():
+    with pytest.raises(ValueError):
+        Emulator(bytes(4096))
+    with pytest.raises(ValueError):
+        Emulator(DRAW_ZERO, platform="xo-chip")
+    assert Emulator(DRAW_ZERO, platform="superchip", clock=1200).step_frames(2)
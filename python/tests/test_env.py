@@ -0,0 +1,40 @@
+from pathlib import Path
+
+import pytest
+
+from rust_wasm_chip8 import Env
+
+CATCH = (Path(__file__).parents[2] / "CATCH").read_bytes()
+
+
+def play(env, seed, action):
+    obs = env.reset(seed)
+    total, steps, done = 0, 0, False
+    while not done and steps < 10_000:
+        obs, reward, done = env.step(action)
+        total += reward
+        steps += 1
+    return total, steps, done
+
+
+def test_catch_episode_ends():
+    env = Env("catch", CATCH, frame_skip=4)
+    assert env.action_count == 3
+    assert env.actions == [0, 1 << 4, 1 << 6]
+    obs = env.reset(1)
+    assert (obs.width, obs.height) == (64, 32)
+    assert env.lives == 3
+    total, steps, done = play(env, 1, 0)
+    assert done
+    assert env.lives == 0
+    assert total == env.score
+    assert play(env, 1, 0) == (total, steps, done)
+
+
+def test_rejects_bad_input():
+    with pytest.raises(ValueError):
+        Env("pong", CATCH)
+    env = Env("catch", CATCH)
+    env.reset(0)
+    with pytest.raises(ValueError):
+        env.step(3)
@@ -0,0 +1,267 @@
+//! Static analysis of ROMs: which instructions are reachable from the entry point, and which
+//! instruction-set variant they need.
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::chip8::Platform;
+
+/**
+* Instruction sets, each a superset of the one before
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Variant {
+    Chip8,
+    // hires, scrolling, big font, flag registers
+    SuperChip,
+    // bitplanes, long I, audio patterns, register ranges
+    XoChip,
+}
+
+impl Variant {
+    /** The platform to run it on; only the CHIP-8 instruction set is emulated, later variants have none */
+    pub fn platform(&self) -> Option<Platform> {
+        match self {
+            Variant::Chip8 => Some(Platform::Chip8),
+            Variant::SuperChip | Variant::XoChip => None,
+        }
+    }
+
+    /** The variant that introduced the opcode; unknown opcodes count as CHIP-8 */
+    pub fn of(opcode: u16) -> Self {
+        match opcode {
+            // scroll up, i := long, audio
+            0x00D0..=0x00DF | 0xF000 | 0xF002 => Variant::XoChip,
+            // scroll down/right/left, exit, lores, hires
+            0x00C0..=0x00CF | 0x00FB..=0x00FF => Variant::SuperChip,
+            _ => match (opcode & 0xF000, opcode & 0x00FF, opcode & 0x000F) {
+                // plane n, pitch, save/load vx - vy
+                (0xF000, 0x01, _) | (0xF000, 0x3A, _) | (0x5000, _, 2) | (0x5000, _, 3) => Variant::XoChip,
+                // 16x16 sprite, big hex font, flag registers
+                (0xD000, _, 0) | (0xF000, 0x30, _) | (0xF000, 0x75, _) | (0xF000, 0x85, _) => Variant::SuperChip,
+                _ => Variant::Chip8,
+            },
+        }
+    }
+}
+
+/** Cowgod-style assembly for an opcode, e.g. "LD V0, 0x03"; anything unknown is "DW 0x...." */
+pub fn mnemonic(opcode: u16) -> String {
+    let x = (opcode & 0x0F00) >> 8;
+    let y = (opcode & 0x00F0) >> 4;
+    let (n, kk, nnn) = (opcode & 0xF, opcode & 0xFF, opcode & 0xFFF);
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => "CLS".into(),
+            0x00EE => "RET".into(),
+            0x00FB => "SCR".into(),
+            0x00FC => "SCL".into(),
+            0x00FD => "EXIT".into(),
+            0x00FE => "LOW".into(),
+            0x00FF => "HIGH".into(),
+            0x00C0..=0x00CF => format!("SCD {}", n),
+            _ => format!("SYS {:#05x}", nnn),
+        },
+        0x1000 => format!("JP {:#05x}", nnn),
+        0x2000 => format!("CALL {:#05x}", nnn),
+        0x3000 => format!("SE V{:X}, {:#04x}", x, kk),
+        0x4000 => format!("SNE V{:X}, {:#04x}", x, kk),
+        0x5000 if n == 0 => format!("SE V{:X}, V{:X}", x, y),
+        0x6000 => format!("LD V{:X}, {:#04x}", x, kk),
+        0x7000 => format!("ADD V{:X}, {:#04x}", x, kk),
+        0x8000 => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => format!("DW {:#06x}", opcode),
+        },
+        0x9000 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA000 => format!("LD I, {:#05x}", nnn),
+        0xB000 => format!("JP V0, {:#05x}", nnn),
+        0xC000 => format!("RND V{:X}, {:#04x}", x, kk),
+        0xD000 => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE000 if kk == 0x9E => format!("SKP V{:X}", x),
+        0xE000 if kk == 0xA1 => format!("SKNP V{:X}", x),
+        0xF000 => match kk {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x30 => format!("LD HF, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            0x75 => format!("LD R, V{:X}", x),
+            0x85 => format!("LD V{:X}, R", x),
+            _ => format!("DW {:#06x}", opcode),
+        },
+        _ => format!("DW {:#06x}", opcode),
+    }
+}
+
+// XO-CHIP's F000 nnnn takes 4 bytes
+pub(crate) fn length(opcode: u16) -> u16 {
+    if opcode == 0xF000 {
+        4
+    } else {
+        2
+    }
+}
+
+pub(crate) fn is_skip(opcode: u16) -> bool {
+    match opcode & 0xF000 {
+        0x3000 | 0x4000 => true,
+        0x5000 | 0x9000 => opcode & 0xF == 0,
+        0xE000 => matches!(opcode & 0xFF, 0x9E | 0xA1),
+        _ => false,
+    }
+}
+
+/**
+* A program as it sits in memory, for reading opcodes by address
+*/
+#[derive(Clone, Copy, Debug)]
+pub struct Image<'a> {
+    pub program: &'a [u8],
+    pub load_address: u16,
+}
+
+impl<'a> Image<'a> {
+    pub fn new(program: &'a [u8], load_address: u16) -> Self {
+        Image { program, load_address }
+    }
+
+    /** None outside the program */
+    pub fn opcode(&self, addr: u16) -> Option<u16> {
+        let offset = usize::from(addr.checked_sub(self.load_address)?);
+        let bytes = self.program.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /**
+     * Where execution can go after the instruction at addr. Jumps through V0 (Bnnn) only
+     * report their base address; returns (00EE) and exit (00FD) go nowhere
+     */
+    pub fn successors(&self, addr: u16) -> Vec<u16> {
+        let opcode = match self.opcode(addr) {
+            Some(opcode) => opcode,
+            None => return vec![],
+        };
+        let next = addr.wrapping_add(length(opcode));
+        let nnn = opcode & 0x0FFF;
+        match opcode & 0xF000 {
+            0x0000 if opcode == 0x00EE || opcode == 0x00FD => vec![],
+            0x1000 | 0xB000 => vec![nnn],
+            0x2000 => vec![nnn, next],
+            _ if is_skip(opcode) => {
+                // skipping over F000 nnnn takes 4 bytes
+                let skipped = self.opcode(next).map_or(2, length);
+                vec![next, next.wrapping_add(skipped)]
+            }
+            _ => vec![next],
+        }
+    }
+
+    /** Addresses of the instructions reachable from the load address, with their opcodes */
+    pub fn reachable(&self) -> BTreeMap<u16, u16> {
+        let mut seen = BTreeMap::new();
+        let mut todo = vec![self.load_address];
+        while let Some(addr) = todo.pop() {
+            if seen.contains_key(&addr) {
+                continue;
+            }
+            if let Some(opcode) = self.opcode(addr) {
+                seen.insert(addr, opcode);
+                todo.extend(self.successors(addr));
+            }
+        }
+        seen
+    }
+}
+
+/**
+* What detect found: the variant and the reachable instructions that need it
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Detection {
+    pub variant: Variant,
+    // (address, opcode), in address order
+    pub evidence: Vec<(u16, u16)>,
+}
+
+/**
+* Scans the code reachable from the load address for opcodes of later variants. Data is not
+* scanned, so sprites that happen to look like SUPER-CHIP opcodes don't count
+*/
+pub fn detect(program: &[u8], load_address: u16) -> Detection {
+    let reachable = Image::new(program, load_address).reachable();
+    let variant = reachable.values().map(|opcode| Variant::of(*opcode)).max().unwrap_or(Variant::Chip8);
+    let evidence = reachable.into_iter().filter(|(_, opcode)| variant > Variant::Chip8 && Variant::of(*opcode) == variant).collect();
+    Detection { variant, evidence }
+}
+
+/** The set of reachable instruction addresses */
+pub fn code_addresses(program: &[u8], load_address: u16) -> BTreeSet<u16> {
+    Image::new(program, load_address).reachable().into_keys().collect()
+}
+
+#[test]
+fn test_variant_of() {
+    assert_eq!(Variant::of(0x00E0), Variant::Chip8);
+    assert_eq!(Variant::of(0xD125), Variant::Chip8);
+    assert_eq!(Variant::of(0x00FF), Variant::SuperChip);
+    assert_eq!(Variant::of(0x00C4), Variant::SuperChip);
+    assert_eq!(Variant::of(0xD120), Variant::SuperChip);
+    assert_eq!(Variant::of(0xF330), Variant::SuperChip);
+    assert_eq!(Variant::of(0xF575), Variant::SuperChip);
+    assert_eq!(Variant::of(0xF000), Variant::XoChip);
+    assert_eq!(Variant::of(0xF201), Variant::XoChip);
+    assert_eq!(Variant::of(0x5122), Variant::XoChip);
+    assert_eq!(Variant::of(0x00D3), Variant::XoChip);
+}
+
+#[test]
+fn test_mnemonic() {
+    assert_eq!(mnemonic(0x6003), "LD V0, 0x03");
+    assert_eq!(mnemonic(0xD125), "DRW V1, V2, 5");
+    assert_eq!(mnemonic(0x2ABC), "CALL 0xabc");
+    assert_eq!(mnemonic(0x8AB6), "SHR VA, VB");
+    assert_eq!(mnemonic(0xF265), "LD V2, [I]");
+    assert_eq!(mnemonic(0x00FF), "HIGH");
+    assert_eq!(mnemonic(0xE1FF), "DW 0xe1ff");
+}
+
+#[test]
+fn test_detect_follows_code() {
+    let schip = [
+        0x22, 0x06, // CALL 0x206
+        0x12, 0x02, // JP 0x202
+        0x00, 0xFF, // data, never executed
+        0x00, 0xFF, // HIGH
+        0x00, 0xEE, // RET
+    ];
+    let detection = detect(&schip, 0x200);
+    assert_eq!(detection, Detection { variant: Variant::SuperChip, evidence: vec![(0x206, 0x00FF)] });
+    assert_eq!(code_addresses(&schip, 0x200).into_iter().collect::<Vec<_>>(), vec![0x200, 0x202, 0x206, 0x208]);
+
+    // a sprite after the loop looks like Dxy0, F000 and 00FF but isn't code
+    let chip8 = [0x60, 0x01, 0x12, 0x02, 0xD1, 0x20, 0xF0, 0x00, 0x00, 0xFF];
+    assert_eq!(detect(&chip8, 0x200).variant, Variant::Chip8);
+    assert!(detect(&chip8, 0x200).evidence.is_empty());
+
+    // SE skips the whole 4-byte F000 nnnn
+    let xo = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x12, 0x06];
+    let detection = detect(&xo, 0x200);
+    assert_eq!(detection.variant, Variant::XoChip);
+    assert_eq!(code_addresses(&xo, 0x200).into_iter().collect::<Vec<_>>(), vec![0x200, 0x202, 0x206]);
+}
@@ -0,0 +1,7 @@
+/**
+* Beeper; CHIP-8 has a single tone that sounds while the sound timer is non-zero
+*/
+pub trait Audio {
+    // called when the tone starts or stops, not every frame
+    fn set_tone(&mut self, on: bool);
+}
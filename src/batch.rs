@@ -0,0 +1,200 @@
+//! Runs many independent emulators on a pool of threads, e.g. for regression sweeps over ROMs and seeds.
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::chip8::{Chip8, Chip8Error, Platform};
+use crate::screen::ScreenState;
+
+/**
+* Key press or release at the start of a frame, as part of a Job's input script
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: usize,
+    pub key: u8,
+    pub down: bool,
+}
+
+/**
+* One emulator run: ROM, settings, how many frames and the keys to press along the way
+*/
+#[derive(Clone, Debug)]
+pub struct Job {
+    // shared, sweeps run the same ROM many times
+    rom: Arc<[u8]>,
+    platform: Platform,
+    clock: u32,
+    seed: u64,
+    frames: usize,
+    inputs: Vec<KeyEvent>,
+}
+
+impl Job {
+    /** 600 frames (10 s) of Platform::Chip8 at 600 instructions per second, seed 0, no keys */
+    pub fn new(rom: impl Into<Arc<[u8]>>) -> Self {
+        Job { rom: rom.into(), platform: Platform::Chip8, clock: 600, seed: 0, frames: 600, inputs: vec![] }
+    }
+
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    pub fn clock(mut self, instructions_per_second: u32) -> Self {
+        self.clock = instructions_per_second;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn frames(mut self, frames: usize) -> Self {
+        self.frames = frames;
+        self
+    }
+
+    pub fn key_down(mut self, frame: usize, key: u8) -> Self {
+        self.inputs.push(KeyEvent { frame, key, down: true });
+        self
+    }
+
+    pub fn key_up(mut self, frame: usize, key: u8) -> Self {
+        self.inputs.push(KeyEvent { frame, key, down: false });
+        self
+    }
+
+    fn run<M>(&self, metrics: &impl Fn(&Chip8) -> M) -> Result<Outcome<M>, Chip8Error> {
+        let mut chip8 = Chip8::builder().rom(&self.rom).platform(self.platform).clock(self.clock).seed(self.seed).build()?;
+        let mut inputs = self.inputs.clone();
+        inputs.sort_by_key(|input| input.frame);
+        let mut inputs = inputs.iter().peekable();
+        let mut frames = 0;
+        while frames < self.frames && !chip8.is_stopped() {
+            while let Some(input) = inputs.next_if(|input| input.frame <= frames) {
+                if input.down {
+                    chip8.key_down(input.key);
+                } else {
+                    chip8.key_up(input.key);
+                }
+            }
+            chip8.run_frame();
+            frames += 1;
+        }
+        Ok(Outcome { frames, state_hash: state_hash(&chip8), pixels: *chip8.pixels(), metrics: metrics(&chip8) })
+    }
+}
+
+/**
+* Where a Job ended up
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome<M> {
+    // fewer than asked for if the program stopped
+    pub frames: usize,
+    pub state_hash: u64,
+    pub pixels: ScreenState,
+    pub metrics: M,
+}
+
+/**
+* FNV-1a of the save state: stable across runs, machines and Rust versions, unlike std's hashers
+*/
+pub fn state_hash(chip8: &Chip8) -> u64 {
+    chip8.save_state().iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01B3))
+}
+
+pub struct Batch {
+    jobs: Vec<Job>,
+    threads: usize,
+}
+
+impl Batch {
+    /** As many threads as the machine has cores */
+    pub fn new(jobs: Vec<Job>) -> Self {
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        Batch { jobs, threads }
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /** Results in the order of the jobs */
+    pub fn run(&self) -> Vec<Result<Outcome<()>, Chip8Error>> {
+        self.run_with(|_| ())
+    }
+
+    /**
+     * Same as run, with metrics computed from each emulator after its last frame.
+     * A panic in metrics is passed on once the other threads are done
+     */
+    pub fn run_with<M: Send>(&self, metrics: impl Fn(&Chip8) -> M + Sync) -> Vec<Result<Outcome<M>, Chip8Error>> {
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<_> = (0..self.jobs.len()).map(|_| None).collect();
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads.min(self.jobs.len()))
+                .map(|_| {
+                    // jobs build their emulators on the worker, so no Chip8 crosses threads
+                    scope.spawn(|| {
+                        let mut done = vec![];
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            match self.jobs.get(index) {
+                                Some(job) => done.push((index, job.run(&metrics))),
+                                None => return done,
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                match worker.join() {
+                    Ok(done) => {
+                        for (index, result) in done {
+                            results[index] = Some(result);
+                        }
+                    }
+                    Err(e) => panic::resume_unwind(e),
+                }
+            }
+        });
+        results.into_iter().map(|result| result.expect("every job ran")).collect()
+    }
+}
+
+// draws a random digit at (0, 0) and waits for a key, then does it again
+#[cfg(test)]
+const RANDOM_DIGIT: [u8; 12] = [
+    0xC0, 0x0F, // RND V0, 0x0F
+    0xF0, 0x29, // LD F, V0
+    0x00, 0xE0, // CLS
+    0xD1, 0x15, // DRW V1, V1, 5
+    0xF2, 0x0A, // LD V2, K
+    0x12, 0x00, // JP 0x200
+];
+
+#[test]
+fn test_batch_matches_sequential_runs() {
+    let jobs: Vec<_> = (0..32u64)
+        .map(|seed| Job::new(&RANDOM_DIGIT[..]).seed(seed).frames(30).key_down(10, 5).key_up(12, 5))
+        .collect();
+    let parallel = Batch::new(jobs.clone()).threads(4).run_with(|chip8| chip8.registers()[0]);
+    let sequential = Batch::new(jobs).threads(1).run_with(|chip8| chip8.registers()[0]);
+    assert_eq!(parallel, sequential);
+    assert!(parallel.iter().all(|result| result.as_ref().unwrap().frames == 30));
+    // the digit after the key press depends on the seed
+    let digits: Vec<_> = parallel.iter().map(|result| result.as_ref().unwrap().metrics).collect();
+    assert!(digits.iter().any(|d| *d != digits[0]));
+}
+
+#[test]
+fn test_batch_reports_errors_per_job() {
+    let results = Batch::new(vec![Job::new(vec![0u8; 4096]), Job::new(&RANDOM_DIGIT[..]).frames(1)]).run();
+    assert!(matches!(results[0], Err(Chip8Error::RomTooLarge { .. })));
+    assert_eq!(results[1].as_ref().unwrap().frames, 1);
+}
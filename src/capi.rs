@@ -0,0 +1,291 @@
+//! C API over Chip8: `cargo build --release --no-default-features --features capi`,
+//! header in include/chip8.h (generated by build.rs with cbindgen, see tests/capi_smoke.rs).
+//!
+//! Every function accepts null pointers and does nothing (or returns CHIP8_STATUS_NULL_POINTER / 0).
+use std::os::raw::c_void;
+use std::ptr;
+use std::slice;
+
+use crate::chip8::{Chip8, Chip8Error};
+use crate::cpu::StepError;
+use crate::events::EmulatorEvent;
+use crate::screen::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::snapshot::SNAPSHOT_SIZE;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chip8Status {
+    Ok = 0,
+    NullPointer = 1,
+    RomTooLarge = 2,
+    BadState = 3,
+    DecodeError = 4,
+    InvalidRom = 5,
+    Sanitizer = 6,
+}
+
+// chip8_run_frame flags
+pub const CHIP8_EVENT_SOUND_ON: u32 = 1;
+pub const CHIP8_EVENT_SOUND_OFF: u32 = 1 << 1;
+pub const CHIP8_EVENT_WAITING_FOR_KEY: u32 = 1 << 2;
+pub const CHIP8_EVENT_BREAKPOINT: u32 = 1 << 3;
+pub const CHIP8_EVENT_STOPPED: u32 = 1 << 4;
+pub const CHIP8_EVENT_SANITIZER: u32 = 1 << 5;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Chip8Registers {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub stack: [u16; 16],
+}
+
+impl From<Chip8Error> for Chip8Status {
+    fn from(e: Chip8Error) -> Self {
+        match e {
+            Chip8Error::RomTooLarge { .. } => Chip8Status::RomTooLarge,
+            Chip8Error::InvalidRom(_) => Chip8Status::InvalidRom,
+        }
+    }
+}
+
+impl From<StepError> for Chip8Status {
+    fn from(e: StepError) -> Self {
+        match e {
+            StepError::Decode(_) => Chip8Status::DecodeError,
+            StepError::Sanitizer(_) => Chip8Status::Sanitizer,
+        }
+    }
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+/// Emulator with the ROM loaded and default settings; null if data is null or the ROM doesn't fit.
+/// Free with chip8_free.
+///
+/// # Safety
+/// `rom` must be valid for reading `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn chip8_new(rom: *const u8, len: usize) -> *mut Chip8 {
+    if rom.is_null() && len > 0 {
+        return ptr::null_mut();
+    }
+    match Chip8::builder().rom(bytes(rom, len)).build() {
+        Ok(chip8) => Box::into_raw(Box::new(chip8)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// `chip8` must come from chip8_new and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        drop(Box::from_raw(chip8));
+    }
+}
+
+/// Swaps the ROM and resets
+///
+/// # Safety
+/// `chip8` from chip8_new; `rom` valid for reading `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(chip8: *mut Chip8, rom: *const u8, len: usize) -> Chip8Status {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) if !rom.is_null() || len == 0 => chip8,
+        _ => return Chip8Status::NullPointer,
+    };
+    match chip8.load_rom(bytes(rom, len)) {
+        Ok(()) => Chip8Status::Ok,
+        Err(e) => e.into(),
+    }
+}
+
+/// # Safety
+/// `chip8` from chip8_new
+#[no_mangle]
+pub unsafe extern "C" fn chip8_reset(chip8: *mut Chip8) {
+    if let Some(chip8) = chip8.as_mut() {
+        chip8.reset();
+    }
+}
+
+/// Seeds RND; the same seed gives the same numbers
+///
+/// # Safety
+/// `chip8` from chip8_new
+#[no_mangle]
+pub unsafe extern "C" fn chip8_seed(chip8: *mut Chip8, seed: u64) {
+    if let Some(chip8) = chip8.as_mut() {
+        chip8.seed_rng(seed);
+    }
+}
+
+/// Instructions per second, default 600
+///
+/// # Safety
+/// `chip8` from chip8_new
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_clock(chip8: *mut Chip8, instructions_per_second: u32) {
+    if let Some(chip8) = chip8.as_mut() {
+        chip8.set_clock(instructions_per_second);
+    }
+}
+
+/// Runs one 60 Hz frame; returns CHIP8_EVENT_* flags of what happened
+///
+/// # Safety
+/// `chip8` from chip8_new
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(chip8: *mut Chip8) -> u32 {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) => chip8,
+        None => return 0,
+    };
+    chip8.run_frame().iter().fold(0, |flags, event| {
+        flags | match event {
+            EmulatorEvent::FrameCompleted => 0,
+            EmulatorEvent::SoundOn => CHIP8_EVENT_SOUND_ON,
+            EmulatorEvent::SoundOff => CHIP8_EVENT_SOUND_OFF,
+            EmulatorEvent::WaitingForKey => CHIP8_EVENT_WAITING_FOR_KEY,
+            EmulatorEvent::BreakpointHit(_) => CHIP8_EVENT_BREAKPOINT,
+            EmulatorEvent::Stopped(_) => CHIP8_EVENT_STOPPED,
+            EmulatorEvent::Sanitizer(_) => CHIP8_EVENT_SANITIZER,
+        }
+    })
+}
+
+/// Runs a single instruction without ticking the timers
+///
+/// # Safety
+/// `chip8` from chip8_new
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(chip8: *mut Chip8) -> Chip8Status {
+    match chip8.as_mut() {
+        Some(chip8) => match chip8.step() {
+            Ok(()) => Chip8Status::Ok,
+            Err(e) => e.into(),
+        },
+        None => Chip8Status::NullPointer,
+    }
+}
+
+/// Keypad key 0x0..=0xF
+///
+/// # Safety
+/// `chip8` from chip8_new
+#[no_mangle]
+pub unsafe extern "C" fn chip8_key_down(chip8: *mut Chip8, key: u8) {
+    if let Some(chip8) = chip8.as_mut() {
+        chip8.key_down(key);
+    }
+}
+
+/// # Safety
+/// `chip8` from chip8_new
+#[no_mangle]
+pub unsafe extern "C" fn chip8_key_up(chip8: *mut Chip8, key: u8) {
+    if let Some(chip8) = chip8.as_mut() {
+        chip8.key_up(key);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_framebuffer_width() -> u32 {
+    SCREEN_WIDTH as u32
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_framebuffer_height() -> u32 {
+    SCREEN_HEIGHT as u32
+}
+
+/// Copies one byte per pixel (0 or 1), row-major, into out if it holds width * height bytes.
+/// Returns width * height either way
+///
+/// # Safety
+/// `chip8` from chip8_new; `out` valid for writing `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(chip8: *const Chip8, out: *mut u8, len: usize) -> usize {
+    let size = SCREEN_WIDTH * SCREEN_HEIGHT;
+    if let (Some(chip8), false) = (chip8.as_ref(), out.is_null()) {
+        if len >= size {
+            ptr::copy_nonoverlapping(chip8.pixels().to_bytes().as_ptr(), out, size);
+        }
+    }
+    size
+}
+
+/// # Safety
+/// `chip8` from chip8_new; `out` a writable Chip8Registers
+#[no_mangle]
+pub unsafe extern "C" fn chip8_registers(chip8: *const Chip8, out: *mut Chip8Registers) -> Chip8Status {
+    match (chip8.as_ref(), out.as_mut()) {
+        (Some(chip8), Some(out)) => {
+            *out = Chip8Registers {
+                v: chip8.registers(),
+                i: chip8.i(),
+                pc: chip8.pc(),
+                sp: chip8.sp(),
+                delay_timer: chip8.delay_timer(),
+                sound_timer: chip8.sound_timer(),
+                stack: chip8.stack(),
+            };
+            Chip8Status::Ok
+        }
+        _ => Chip8Status::NullPointer,
+    }
+}
+
+/// Whether the tone should sound, i.e. the sound timer is non-zero
+///
+/// # Safety
+/// `chip8` from chip8_new
+#[no_mangle]
+pub unsafe extern "C" fn chip8_sound_on(chip8: *const Chip8) -> bool {
+    chip8.as_ref().map(|chip8| chip8.is_sound_on()).unwrap_or(false)
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_state_size() -> usize {
+    SNAPSHOT_SIZE
+}
+
+/// Writes chip8_state_size() bytes to out; returns the number written, 0 if out is too small
+///
+/// # Safety
+/// `chip8` from chip8_new; `out` valid for writing `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(chip8: *const Chip8, out: *mut c_void, len: usize) -> usize {
+    match chip8.as_ref() {
+        Some(chip8) if !out.is_null() && len >= SNAPSHOT_SIZE => {
+            let state = chip8.save_state();
+            ptr::copy_nonoverlapping(state.as_ptr(), out as *mut u8, state.len());
+            state.len()
+        }
+        _ => 0,
+    }
+}
+
+/// # Safety
+/// `chip8` from chip8_new; `data` valid for reading `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(chip8: *mut Chip8, data: *const c_void, len: usize) -> Chip8Status {
+    match chip8.as_mut() {
+        Some(chip8) if !data.is_null() => match chip8.load_state(bytes(data as *const u8, len)) {
+            Ok(()) => Chip8Status::Ok,
+            Err(_) => Chip8Status::BadState,
+        },
+        _ => Chip8Status::NullPointer,
+    }
+}
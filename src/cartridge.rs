@@ -0,0 +1,135 @@
+//! Octo cartridges: GIFs with the program's source and options hidden in the low bits of the pixels.
+//!
+//! The palette indices of all frames, in order, carry 2 bits each (most significant pair first, 4 per byte).
+//! The bytes are a 32-bit big-endian length, then that many bytes of JSON: `{"program": source, "options": {...}}`.
+use serde::Deserialize;
+
+use crate::cpu::CPUQuirks;
+use crate::gif;
+use crate::octo;
+use crate::rom::{LoadError, Rom, RomFormat, PROGRAM_START_ADDR};
+use crate::theme::{Rgba, Theme};
+
+#[derive(Deserialize)]
+struct Payload {
+    program: String,
+    #[serde(default)]
+    options: Options,
+}
+
+// the ones this emulator has a use for; Octo has more
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Options {
+    pub tickrate: Option<u32>,
+    pub shift_quirks: Option<bool>,
+    pub load_store_quirks: Option<bool>,
+    pub background_color: Option<String>,
+    pub fill_color: Option<String>,
+    pub fill_color2: Option<String>,
+    pub blend_color: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cartridge {
+    // Octo source
+    pub program: String,
+    pub options: Options,
+}
+
+fn bad(message: impl Into<String>) -> LoadError {
+    LoadError::BadCartridge(message.into())
+}
+
+fn payload(pixels: &[u8]) -> Vec<u8> {
+    pixels.chunks_exact(4).map(|p| p.iter().fold(0, |byte, pixel| (byte << 2) | (pixel & 3))).collect()
+}
+
+/** Extracts source and options from the GIF */
+pub fn read(data: &[u8]) -> Result<Cartridge, LoadError> {
+    let pixels: Vec<u8> = gif::frames(data).map_err(bad)?.concat();
+    let bytes = payload(&pixels);
+    let len = match bytes.get(..4) {
+        Some(len) => u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize,
+        None => return Err(bad("no payload")),
+    };
+    let json = bytes[4..].get(..len).ok_or_else(|| bad("payload longer than the image"))?;
+    let payload: Payload = serde_json::from_slice(json).map_err(|e| bad(e.to_string()))?;
+    Ok(Cartridge { program: payload.program, options: payload.options })
+}
+
+impl Options {
+    // Octo's quirks default to off
+    fn quirks(&self) -> CPUQuirks {
+        CPUQuirks { shift: self.shift_quirks.unwrap_or(false), load_store: self.load_store_quirks.unwrap_or(false) }
+    }
+
+    // palette index = bitplanes: 1 fillColor, 2 fillColor2, 3 blendColor
+    fn theme(&self) -> Option<Theme> {
+        let colors = [&self.background_color, &self.fill_color, &self.fill_color2, &self.blend_color];
+        if colors.iter().all(|color| color.is_none()) {
+            return None;
+        }
+        let mut theme = Theme::default();
+        for (index, color) in colors.iter().enumerate() {
+            if let Some(rgba) = color.as_deref().and_then(Rgba::from_hex) {
+                theme.palette[index] = rgba;
+            }
+        }
+        Some(theme)
+    }
+}
+
+impl Cartridge {
+    /** Assembles the source; the options become the Rom's quirks, tickrate and colours */
+    pub fn into_rom(self) -> Result<Rom, LoadError> {
+        let program = octo::assemble(&self.program)
+            .map_err(|e| LoadError::Assembly { line: e.line, message: e.message })?;
+        Ok(Rom {
+            program,
+            format: RomFormat::OctoCartridge,
+            load_address: PROGRAM_START_ADDR,
+            platform: None,
+            quirks: Some(self.options.quirks()),
+            tickrate: self.options.tickrate,
+            theme: self.options.theme(),
+        })
+    }
+}
+
+// payload bytes back to 2-bit pixels, padded to whole 32x32 frames
+#[cfg(test)]
+pub(crate) fn encode(json: &str) -> Vec<u8> {
+    let mut bytes = (json.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(json.as_bytes());
+    let mut pixels: Vec<u8> = bytes.iter().flat_map(|b| [b >> 6, (b >> 4) & 3, (b >> 2) & 3, b & 3]).collect();
+    pixels.resize(pixels.len().div_ceil(1024) * 1024, 0);
+    let frames: Vec<Vec<u8>> = pixels.chunks(1024).map(|frame| frame.to_vec()).collect();
+    gif::encode(32, 32, &frames)
+}
+
+#[test]
+fn test_read_cartridge() {
+    let json = r##"{"program": ": main\n  v0 := 1\n  loop again", "options": {"tickrate": 20, "shiftQuirks": true,
+        "fillColor": "#FF0000", "backgroundColor": "#000000", "screenRotation": 0}}"##;
+    let rom = Rom::parse(Some("game.gif"), &encode(json)).unwrap();
+    assert_eq!(rom.format, RomFormat::OctoCartridge);
+    assert_eq!(rom.program, vec![0x12, 0x02, 0x60, 0x01, 0x12, 0x04]);
+    assert_eq!(rom.quirks, Some(CPUQuirks { shift: true, load_store: false }));
+    assert_eq!(rom.tickrate, Some(20));
+    let theme = rom.theme.unwrap();
+    assert_eq!((theme.background(), theme.foreground()), (Rgba::BLACK, Rgba(0xFF, 0, 0, 0xFF)));
+    // untouched entries keep the default palette
+    assert_eq!(theme.color(2), Theme::default().color(2));
+}
+
+#[test]
+fn test_bad_cartridges() {
+    let broken = encode(r#"{"program": ": main\n  v0 := 256"}"#);
+    assert_eq!(Rom::parse(None, &broken), Err(LoadError::Assembly { line: 2, message: "256 doesn't fit in a byte".into() }));
+    assert!(matches!(Rom::parse(None, &encode("[]")), Err(LoadError::BadCartridge(_))));
+    assert!(matches!(Rom::parse(None, b"GIF89a"), Err(LoadError::BadCartridge(_))));
+    // a length past the end of the pixels
+    let truncated = gif::encode(4, 4, &[vec![3; 16]]);
+    assert!(matches!(Rom::parse(None, &truncated), Err(LoadError::BadCartridge(_))));
+}
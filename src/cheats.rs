@@ -0,0 +1,233 @@
+//! Cheats: narrowing down where a game keeps a value by comparing memory snapshots,
+//! freezing bytes to a value every frame, one-shot pokes, and a text format to keep them per ROM.
+//!
+//! One cheat per line, `#` starts a comment:
+//! ```text
+//! # Brix
+//! 3A0=09 lives
+//! 3A1:05 level
+//! ```
+//! `addr=value` freezes, `addr:value` pokes once; both in hex, with an optional name after them.
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Decreased,
+    Increased,
+}
+
+impl Comparison {
+    /** "equal" (with value), "changed", "unchanged", "decreased" or "increased" */
+    pub fn from_name(name: &str, value: u8) -> Option<Self> {
+        match name {
+            "equal" => Some(Comparison::Equal(value)),
+            "changed" => Some(Comparison::Changed),
+            "unchanged" => Some(Comparison::Unchanged),
+            "decreased" => Some(Comparison::Decreased),
+            "increased" => Some(Comparison::Increased),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, previous: u8, current: u8) -> bool {
+        match self {
+            Comparison::Equal(value) => current == *value,
+            Comparison::Changed => current != previous,
+            Comparison::Unchanged => current == previous,
+            Comparison::Decreased => current < previous,
+            Comparison::Increased => current > previous,
+        }
+    }
+}
+
+/**
+* Addresses still in the running, and the snapshot the next one is compared with
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemorySearch {
+    previous: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl MemorySearch {
+    /** Every address is a candidate; memory is the first snapshot */
+    pub fn new(memory: &[u8]) -> Self {
+        MemorySearch { previous: memory.to_vec(), candidates: (0..memory.len() as u16).collect() }
+    }
+
+    /** Keeps the candidates whose byte compares as asked with the last snapshot, then memory becomes the last one */
+    pub fn narrow(&mut self, memory: &[u8], comparison: Comparison) -> &[u16] {
+        let previous = &self.previous;
+        self.candidates.retain(|addr| {
+            let addr = usize::from(*addr);
+            memory.get(addr).is_some_and(|current| comparison.matches(previous[addr], *current))
+        });
+        self.previous = memory.to_vec();
+        &self.candidates
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatKind {
+    // written at the start of every frame
+    Freeze,
+    // written once, when added
+    Poke,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub kind: CheatKind,
+    pub addr: u16,
+    pub value: u8,
+    pub name: String,
+}
+
+impl Cheat {
+    pub fn freeze(addr: u16, value: u8) -> Self {
+        Cheat { kind: CheatKind::Freeze, addr, value, name: String::new() }
+    }
+
+    pub fn poke(addr: u16, value: u8) -> Self {
+        Cheat { kind: CheatKind::Poke, addr, value, name: String::new() }
+    }
+
+    /** One line of the cheat format, e.g. "3A0=09 lives" */
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let line = line.trim();
+        let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let (kind, (addr, value)) = match (code.split_once('='), code.split_once(':')) {
+            (Some(parts), None) => (CheatKind::Freeze, parts),
+            (None, Some(parts)) => (CheatKind::Poke, parts),
+            _ => return Err(format!("{} isn't addr=value or addr:value", code)),
+        };
+        let addr = u16::from_str_radix(addr, 16).ok().filter(|addr| *addr < 0x1000).ok_or_else(|| format!("bad address {}", addr))?;
+        let value = u8::from_str_radix(value, 16).map_err(|_| format!("bad value {}", value))?;
+        Ok(Cheat { kind, addr, value, name: name.trim().to_string() })
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let separator = if self.kind == CheatKind::Freeze { '=' } else { ':' };
+        write!(f, "{:03X}{}{:02X}", self.addr, separator, self.value)?;
+        if !self.name.is_empty() {
+            write!(f, " {}", self.name)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheatError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cheats, line {}: {}", self.line, self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CheatError {}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheatList {
+    pub cheats: Vec<Cheat>,
+}
+
+impl CheatList {
+    pub fn new() -> Self {
+        CheatList::default()
+    }
+
+    /** A whole cheat file; lines are numbered from 1 in errors */
+    pub fn parse(text: &str) -> Result<Self, CheatError> {
+        let mut cheats = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if !line.is_empty() {
+                cheats.push(Cheat::parse(line).map_err(|message| CheatError { line: index + 1, message })?);
+            }
+        }
+        Ok(CheatList { cheats })
+    }
+
+    pub(crate) fn freezes(&self) -> impl Iterator<Item = &Cheat> {
+        self.cheats.iter().filter(|cheat| cheat.kind == CheatKind::Freeze)
+    }
+}
+
+// the file format, to save what parse reads
+impl fmt::Display for CheatList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for cheat in &self.cheats {
+            writeln!(f, "{}", cheat)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_cheat_format() {
+    let list = CheatList::parse("# Brix\n3a0=09 lives\n\n3A1:5  level # skips ahead\n").unwrap();
+    assert_eq!(list.cheats, vec![
+        Cheat { name: "lives".into(), ..Cheat::freeze(0x3A0, 9) },
+        Cheat { name: "level".into(), ..Cheat::poke(0x3A1, 5) },
+    ]);
+    assert_eq!(list.to_string(), "3A0=09 lives\n3A1:05 level\n");
+    assert_eq!(CheatList::parse(&list.to_string()), Ok(list));
+    assert_eq!(CheatList::parse("3A0=09\n1000=01"), Err(CheatError { line: 2, message: "bad address 1000".into() }));
+    assert_eq!(Cheat::parse("3A0"), Err("3A0 isn't addr=value or addr:value".into()));
+    assert!(Cheat::parse("3A0=100").is_err());
+}
+
+#[test]
+fn test_search() {
+    let mut memory = vec![0u8; 8];
+    memory[3] = 3;
+    memory[5] = 3;
+    let mut search = MemorySearch::new(&memory);
+    assert_eq!(search.narrow(&memory, Comparison::Equal(3)), &[3, 5]);
+    // a life lost
+    memory[3] = 2;
+    assert_eq!(search.narrow(&memory, Comparison::Decreased), &[3]);
+    assert_eq!(search.narrow(&memory, Comparison::Unchanged), &[3]);
+    assert_eq!(Comparison::from_name("increased", 0), Some(Comparison::Increased));
+}
+
+#[test]
+fn test_queued_poke_follows_queued_rom() {
+    use crate::command_queue::Command;
+    use crate::coverage::WRITTEN;
+    use crate::cpu::CPU;
+    use crate::headless_screen::HeadlessScreen;
+    use crate::provenance::CHEAT;
+    use crate::rom::Rom;
+
+    let mut cpu = CPU::new(Box::new(HeadlessScreen::new()));
+    cpu.load_program(vec![0x12, 0x00]).unwrap();
+    cpu.enable_coverage();
+    cpu.enable_provenance();
+    let commands = cpu.command_queue();
+    commands.push(Command::LoadRom(Rom::new(vec![0x12, 0x00, 0x01])));
+    commands.push(Command::Poke(0x202, 0x09));
+    cpu.run_frame_now();
+    // the load didn't wipe it out
+    assert_eq!(cpu.state.mem[0x202].0, 0x09);
+    assert_eq!(cpu.coverage().unwrap().flags(0x202), WRITTEN);
+    let write = cpu.last_writer(0x202).unwrap();
+    assert_eq!((write.pc, write.opcode, write.value), (CHEAT, CHEAT, 0x09));
+}
@@ -0,0 +1,477 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "romdb")]
+use std::sync::Arc;
+
+use crate::audio::Audio;
+use crate::cheats::{Cheat, CheatList};
+#[cfg(feature = "scripting")]
+use crate::scripting::{Script, ScriptError};
+use crate::command_queue::CommandQueue;
+use crate::cpu::{CPUQuirks, StepError, StepTrace, CPU, STEPS_PER_CYCLE};
+use crate::events::EmulatorEvent;
+use crate::headless_screen::HeadlessScreen;
+#[cfg(feature = "romdb")]
+use crate::rom_db::{RomDatabase, RomInfo};
+use crate::rom::{LoadError, Rom};
+use crate::coverage::Coverage;
+use crate::profiler::Profiler;
+use crate::provenance::MemoryWrite;
+use crate::sanitizer::SanitizerOptions;
+use crate::screen::{Screen, ScreenState};
+use crate::snapshot::SnapshotError;
+
+const FRAMES_PER_SECOND: u32 = 60;
+
+/**
+* Which interpreter's behaviour to follow where they disagree
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    // COSMAC VIP: shifts read VY, Fx55/Fx65 advance I
+    Chip8,
+    // HP48 SUPER-CHIP: shifts work on VX in place, Fx55/Fx65 leave I alone
+    SuperChip,
+}
+
+impl Platform {
+    pub fn quirks(&self) -> CPUQuirks {
+        match self {
+            Platform::Chip8 => CPUQuirks { shift: false, load_store: false },
+            Platform::SuperChip => CPUQuirks { shift: true, load_store: true },
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Chip8Error {
+    RomTooLarge { size: usize, max: usize },
+    // bad load address, unreadable cartridge...
+    InvalidRom(LoadError),
+}
+
+impl From<LoadError> for Chip8Error {
+    fn from(e: LoadError) -> Self {
+        match e {
+            LoadError::TooLarge { size, max } => Chip8Error::RomTooLarge { size, max },
+            e => Chip8Error::InvalidRom(e),
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::RomTooLarge { size, max } => write!(f, "ROM takes {} bytes, at most {} fit", size, max),
+            Chip8Error::InvalidRom(e) => e.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Chip8Error {}
+
+fn steps_per_frame(instructions_per_second: u32) -> usize {
+    let per_frame = (instructions_per_second + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND;
+    per_frame.max(1) as usize
+}
+
+/**
+* Configures a Chip8; every setting has a default, so `Chip8::builder().rom(rom).build()` is enough
+*/
+pub struct Chip8Builder {
+    // None picks the platform from the ROM's code
+    quirks: Option<CPUQuirks>,
+    seed: Option<u64>,
+    steps_per_frame: usize,
+    screen: Option<Box<dyn Screen>>,
+    audio: Option<Box<dyn Audio>>,
+    sanitizer: Option<SanitizerOptions>,
+    #[cfg(feature = "romdb")]
+    rom_db: Option<Arc<RomDatabase>>,
+    rom: Rom,
+}
+
+impl Chip8Builder {
+    fn new() -> Self {
+        Chip8Builder {
+            quirks: None,
+            seed: None,
+            steps_per_frame: STEPS_PER_CYCLE,
+            screen: None,
+            audio: None,
+            sanitizer: None,
+            #[cfg(feature = "romdb")]
+            rom_db: None,
+            rom: Rom::new(Vec::new()),
+        }
+    }
+
+    /**
+     * Sets the quirks of a platform. By default the ROM file picks (.sc8 means Platform::SuperChip),
+     * Platform::Chip8 otherwise
+     */
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.quirks = Some(platform.quirks());
+        self
+    }
+
+    /** Individual quirks, overriding platform() */
+    pub fn quirks(mut self, quirks: CPUQuirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    /** Runs with CPU::enable_sanitizer; with options.randomize, RAM and registers are noise from the seed */
+    pub fn sanitizer(mut self, options: SanitizerOptions) -> Self {
+        self.sanitizer = Some(options);
+        self
+    }
+
+    /** Seed for RND (Cxkk); default is random with std, fixed without */
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /** Instructions per second, rounded to whole instructions per 60 Hz frame; default 600 */
+    pub fn clock(mut self, instructions_per_second: u32) -> Self {
+        self.steps_per_frame = steps_per_frame(instructions_per_second);
+        self
+    }
+
+    /** Where frames are presented; default is no screen, read pixels() instead */
+    pub fn screen(mut self, screen: Box<dyn Screen>) -> Self {
+        self.screen = Some(screen);
+        self
+    }
+
+    pub fn audio(mut self, audio: Box<dyn Audio>) -> Self {
+        self.audio = Some(audio);
+        self
+    }
+
+    /** Recognised ROMs get the database's quirks, clock and keys, over the ones set here */
+    #[cfg(feature = "romdb")]
+    pub fn rom_database(mut self, db: Arc<RomDatabase>) -> Self {
+        self.rom_db = Some(db);
+        self
+    }
+
+    /** Plain program bytes */
+    pub fn rom(mut self, rom: &[u8]) -> Self {
+        self.rom.program = rom.to_vec();
+        self
+    }
+
+    /** A ROM file from Rom::parse; its own settings win over the ones set here, except platform() and quirks() */
+    pub fn rom_file(mut self, rom: Rom) -> Self {
+        self.rom = rom;
+        self
+    }
+
+    /** Where the program goes, default 0x200; see rom::ETI_660_LOAD_ADDRESS and friends */
+    pub fn load_address(mut self, addr: u16) -> Self {
+        self.rom.load_address = addr;
+        self
+    }
+
+    pub fn build(self) -> Result<Chip8, Chip8Error> {
+        let screen = self.screen.unwrap_or_else(|| Box::new(HeadlessScreen::new()));
+        let mut cpu = CPU::new(screen);
+        let mut rom = self.rom;
+        match self.quirks {
+            Some(quirks) => {
+                cpu.set_quirks(quirks);
+                rom.platform = None;
+                rom.quirks = None;
+            }
+            None => {
+                cpu.set_quirks(Platform::Chip8.quirks());
+                if rom.platform.is_none() && rom.quirks.is_none() {
+                    rom.platform = rom.detect().variant.platform();
+                }
+            }
+        }
+        cpu.set_steps_per_frame(self.steps_per_frame);
+        if let Some(seed) = self.seed {
+            cpu.seed_rng(seed);
+        }
+        if let Some(audio) = self.audio {
+            cpu.set_audio(audio);
+        }
+        if let Some(options) = self.sanitizer {
+            cpu.enable_sanitizer(options);
+        }
+        #[cfg(feature = "romdb")]
+        {
+            if let Some(db) = self.rom_db {
+                cpu.set_rom_database(db);
+            }
+        }
+        cpu.load(rom)?;
+        Ok(Chip8 { cpu, auto_platform: self.quirks.is_none() })
+    }
+}
+
+/**
+* An emulator for embedding: the caller drives it, one run_frame() per 60 Hz tick
+*/
+pub struct Chip8 {
+    cpu: CPU,
+    // no platform or quirks given to the builder: every ROM picks its own
+    auto_platform: bool,
+}
+
+impl Chip8 {
+    pub fn builder() -> Chip8Builder {
+        Chip8Builder::new()
+    }
+
+    /**
+     * Applies queued commands, runs a frame's worth of instructions and ticks the timers.
+     * Returns what happened during the frame
+     */
+    pub fn run_frame(&mut self) -> Vec<EmulatorEvent> {
+        self.cpu.run_frame_now();
+        self.cpu.take_events()
+    }
+
+    /** Same as run_frame, calling observer after every instruction; see CPU::run_frame_observed */
+    pub fn run_frame_observed(&mut self, mut observer: impl FnMut(&mut CPU, &StepTrace)) -> Vec<EmulatorEvent> {
+        self.cpu.run_frame_observed(&mut observer);
+        self.cpu.take_events()
+    }
+
+    /** Same as run_frame, with the script's hooks; see scripting */
+    #[cfg(feature = "scripting")]
+    pub fn run_script_frame(&mut self, script: &mut Script) -> Result<Vec<EmulatorEvent>, ScriptError> {
+        script.run_frame(&mut self.cpu)
+    }
+
+    /** One instruction, without ticking the timers */
+    pub fn step(&mut self) -> Result<(), StepError> {
+        self.cpu.step_instruction()
+    }
+
+    /** Keypad key 0x0..=0xF */
+    pub fn key_down(&mut self, key: u8) {
+        self.cpu.press_key(key);
+    }
+
+    pub fn key_up(&mut self, key: u8) {
+        self.cpu.release_key(key);
+    }
+
+    pub fn is_key_pressed(&self, key: u8) -> bool {
+        self.cpu.state.keyboard.is_key_pressed(&(key & 0xF))
+    }
+
+    /** Restarts the current ROM; quirks, clock, screen and audio are kept */
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    /** Swaps the ROM and resets; the bytes go where the previous program went */
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        let mut rom = Rom { load_address: self.cpu.load_address(), ..Rom::new(rom.to_vec()) };
+        if self.auto_platform {
+            rom.platform = rom.detect().variant.platform();
+        }
+        Ok(self.cpu.load_rom(rom)?)
+    }
+
+    /** Swaps in a ROM file from Rom::parse and resets */
+    pub fn load_rom_file(&mut self, mut rom: Rom) -> Result<(), Chip8Error> {
+        if !self.auto_platform {
+            rom.platform = None;
+            rom.quirks = None;
+        }
+        Ok(self.cpu.load_rom(rom)?)
+    }
+
+    pub fn stop(&mut self) {
+        self.cpu.stop();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.cpu.is_done()
+    }
+
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.cpu.is_waiting_for_key()
+    }
+
+    pub fn is_sound_on(&self) -> bool {
+        self.cpu.state.st.0 > 0
+    }
+
+    /** V0..VF */
+    pub fn registers(&self) -> [u8; 16] {
+        let mut v = [0; 16];
+        for (r, value) in v.iter_mut().zip(self.cpu.state.v.iter()) {
+            *r = value.0;
+        }
+        v
+    }
+
+    pub fn i(&self) -> u16 {
+        self.cpu.state.i.0.into()
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.cpu.state.pc.0.into()
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.cpu.state.sp.0.into()
+    }
+
+    pub fn stack(&self) -> [u16; 16] {
+        let mut stack = [0; 16];
+        for (s, value) in stack.iter_mut().zip(self.cpu.state.stack.iter()) {
+            *s = (*value).into();
+        }
+        stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.cpu.state.dt.0
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.cpu.state.st.0
+    }
+
+    /** Byte at addr, wrapping around the 4 KiB address space */
+    pub fn peek(&self, addr: u16) -> u8 {
+        let mem = &self.cpu.state.mem;
+        mem[addr as usize % mem.len()].0
+    }
+
+    /** Writes a byte at addr, wrapping like peek */
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.cpu.poke(addr, value);
+    }
+
+    /** Pokes now, freezes at the start of every frame; see cheats for the file format */
+    pub fn set_cheats(&mut self, cheats: CheatList) {
+        self.cpu.set_cheats(cheats);
+    }
+
+    pub fn cheats(&self) -> &CheatList {
+        self.cpu.cheats()
+    }
+
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        self.cpu.add_cheat(cheat);
+    }
+
+    pub fn remove_cheats(&mut self, addr: u16) {
+        self.cpu.remove_cheats(addr);
+    }
+
+    /** Sets V0..VF; index is taken modulo 16 */
+    pub fn set_register(&mut self, index: usize, value: u8) {
+        self.cpu.state.v[index % 16].0 = value;
+    }
+
+    /** Copy of the whole 4 KiB memory, fonts included */
+    pub fn memory(&self) -> Vec<u8> {
+        self.cpu.state.mem.iter().map(|m| m.0).collect()
+    }
+
+    pub fn pixels(&self) -> &ScreenState {
+        self.cpu.state.framebuffer.pixels()
+    }
+
+    pub fn quirks(&self) -> CPUQuirks {
+        self.cpu.state.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: CPUQuirks) {
+        self.cpu.set_quirks(quirks);
+    }
+
+    /** Same as Chip8Builder::seed, while running */
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.cpu.seed_rng(seed);
+    }
+
+    /** Same as Chip8Builder::clock, while running */
+    pub fn set_clock(&mut self, instructions_per_second: u32) {
+        self.cpu.set_steps_per_frame(steps_per_frame(instructions_per_second));
+    }
+
+    /** Instructions per second, rounded to whole instructions per frame */
+    pub fn clock(&self) -> u32 {
+        self.cpu.steps_per_frame() as u32 * FRAMES_PER_SECOND
+    }
+
+    /** Counts instructions from now on, see CPU::enable_profiler */
+    pub fn enable_profiler(&mut self) {
+        self.cpu.enable_profiler();
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.cpu.profiler()
+    }
+
+    /** Records executed, read and written bytes from now on, see CPU::enable_coverage */
+    pub fn enable_coverage(&mut self) {
+        self.cpu.enable_coverage();
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.cpu.coverage()
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.cpu.take_coverage()
+    }
+
+    /** Records who writes memory from now on, see CPU::enable_provenance */
+    pub fn enable_provenance(&mut self) {
+        self.cpu.enable_provenance();
+    }
+
+    /** The instruction that last wrote addr; None without provenance or if it wasn't written since loading */
+    pub fn last_writer(&self, addr: u16) -> Option<MemoryWrite> {
+        self.cpu.last_writer(addr)
+    }
+
+    /** Keeps a history of the writes to start..end, for write_history */
+    pub fn watch_writes(&mut self, start: u16, end: u16) {
+        self.cpu.watch_writes(start, end);
+    }
+
+    pub fn write_history(&self, start: u16, end: u16) -> Vec<MemoryWrite> {
+        self.cpu.write_history(start, end)
+    }
+
+    /** Title, authors and settings of the ROM, if the database knows it */
+    #[cfg(feature = "romdb")]
+    pub fn rom_info(&self) -> Option<&RomInfo> {
+        self.cpu.rom_info()
+    }
+
+    /** Machine state as snapshot::SNAPSHOT_SIZE bytes */
+    pub fn save_state(&self) -> Vec<u8> {
+        self.cpu.save_state()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SnapshotError> {
+        self.cpu.load_state(data)
+    }
+
+    /** Commands for the next run_frame(), e.g. from another event source */
+    pub fn command_queue(&self) -> CommandQueue {
+        self.cpu.command_queue()
+    }
+
+    /** The underlying CPU, e.g. for CPU::run or command_queue() */
+    pub fn into_cpu(self) -> CPU {
+        self.cpu
+    }
+}
@@ -0,0 +1,95 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use crate::cheats::{Cheat, CheatList};
+use crate::coverage::Coverage;
+use crate::rom::Rom;
+#[cfg(feature = "romdb")]
+use crate::rom_db::RomDatabase;
+
+/**
+* Requests from the front-end, applied by the run loop in the order they were sent
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    // keys are PC key codes, see keyboard::pc_key_to_chip8
+    KeyDown(usize),
+    KeyUp(usize),
+    Stop,
+    Pause,
+    Resume,
+    AdvanceFrame,
+    Reset,
+    LoadRom(Rom),
+    AddBreakpoint(u16),
+    RemoveBreakpoint(u16),
+    #[cfg(feature = "romdb")]
+    SetRomDatabase(Arc<RomDatabase>),
+    EnableProfiler,
+    DisableProfiler,
+    EnableCoverage,
+    MergeCoverage(Coverage),
+    EnableProvenance,
+    // start..end
+    WatchWrites(u16, u16),
+    UnwatchWrites(u16, u16),
+    SetCheats(CheatList),
+    AddCheat(Cheat),
+    RemoveCheats(u16),
+    // addr, value
+    Poke(u16, u8),
+}
+
+/**
+* Shared handle, also between threads: the front-end pushes and the CPU drains between frames.
+* The lock is only held while pushing or draining, so a push never waits for a running frame and calls from JS stay in order
+*/
+#[derive(Clone, Debug, Default)]
+pub struct CommandQueue(Arc<Mutex<VecDeque<Command>>>);
+
+impl CommandQueue {
+    pub fn new() -> Self {
+        CommandQueue::default()
+    }
+    pub fn push(&self, command: Command) {
+        self.0.lock().push_back(command);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().is_empty()
+    }
+    /**
+     * Takes the commands to apply before the next frame, stopping at the release of a key pressed earlier
+     * in the batch: applied together, the press and release would cancel out before the program could see the key
+     */
+    pub fn take_frame_batch(&self) -> Vec<Command> {
+        let mut queue = self.0.lock();
+        let mut batch = vec![];
+        let mut pressed = vec![];
+        while let Some(command) = queue.front() {
+            match command {
+                Command::KeyDown(k) => pressed.push(*k),
+                Command::KeyUp(k) if pressed.contains(k) => break,
+                _ => {}
+            }
+            batch.push(queue.pop_front().unwrap());
+        }
+        batch
+    }
+}
+
+#[test]
+fn test_command_queue_keeps_taps_for_a_frame() {
+    let queue = CommandQueue::new();
+    queue.push(Command::KeyDown(49));
+    queue.push(Command::KeyUp(50));
+    queue.push(Command::KeyUp(49));
+    queue.push(Command::Stop);
+    assert_eq!(queue.take_frame_batch(), vec![Command::KeyDown(49), Command::KeyUp(50)]);
+    assert_eq!(queue.take_frame_batch(), vec![Command::KeyUp(49), Command::Stop]);
+    assert!(queue.is_empty());
+    assert_eq!(queue.take_frame_batch(), vec![]);
+}
@@ -1,28 +1,27 @@
+use std::cell::{Cell, RefCell};
 use std::time::Duration;
 
 use futures::{FutureExt};
 use futures::future::LocalBoxFuture;
 use tokio::time::{sleep};
 
-use crate::cpu_instructions::{X, Y};
-use crate::screen::{IsCollision, make_zero_screen_state, Screen, SCREEN_HEIGHT, SCREEN_WIDTH, ScreenDraw, ScreenState, toggle_pixel};
+use crate::display_filter::{DisplayFilter, DisplayMode, INTENSITY_MAX};
+use crate::framebuffer::Frame;
+use crate::screen::{make_zero_screen_state, Screen, SCREEN_HEIGHT, SCREEN_WIDTH, ScreenDraw, ScreenState};
 
 pub struct ConsoleScreen {
-    drawn: bool,
+    drawn: Cell<bool>,
     state: ScreenState,
+    filter: RefCell<DisplayFilter>,
 }
 
 impl ScreenDraw for ConsoleScreen {
-    fn toggle_pixel(&mut self, x: X, y: Y) -> IsCollision {
-        toggle_pixel(&mut self.state, x, y)
-    }
-
-    fn repaint(&mut self) {
-        self.draw_console();
-    }
-    fn clear(&mut self) {
-        self.state = make_zero_screen_state();
-        self.flush_console();
+    fn present(&mut self, frame: &Frame) {
+        self.state = *frame.pixels;
+        self.filter.get_mut().on_draw(&self.state);
+        if self.filter.get_mut().presents_on_draw() {
+            self.draw_console();
+        }
     }
 
     fn get_width(&self) -> usize {
@@ -34,38 +33,59 @@ impl ScreenDraw for ConsoleScreen {
     }
 }
 
+impl Default for ConsoleScreen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ConsoleScreen {
     pub fn new() -> Self {
+        ConsoleScreen::with_display_mode(DisplayMode::Off)
+    }
+    pub fn with_display_mode(display_mode: DisplayMode) -> Self {
         Self {
-            drawn: false,
+            drawn: Cell::new(false),
             state: make_zero_screen_state(),
+            filter: RefCell::new(DisplayFilter::new(display_mode)),
         }
     }
-    fn flush_console(&mut self) {
-        if !self.drawn {return;}
+    fn flush_console(&self) {
+        if !self.drawn.get() {return;}
         // https://stackoverflow.com/a/34837038/2123547
         print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
-        self.drawn = false;
+        self.drawn.set(false);
     }
-    fn draw_console(&mut self) {
-        if self.drawn {
+    fn draw_console(&self) {
+        if self.drawn.get() {
             self.flush_console();
         }
-        print!("{}", self.state.iter().enumerate().map(|(y, _)| {
-            self.state[0].iter().enumerate().map(|(x, _)| {
-                if self.state[y][x] {
-                    "*"
-                } else {
-                    " "
+        let filter = self.filter.borrow();
+        print!("{}", (0..SCREEN_HEIGHT).map(|y| {
+            (0..SCREEN_WIDTH).map(|x| {
+                match filter.intensity_at(x, y) {
+                    INTENSITY_MAX => "*",
+                    0 => " ",
+                    _ => ".",
                 }
             }).collect::<Vec<_>>().join("")
         }).collect::<Vec<_>>().join("\n"));
-        self.drawn = true;
+        self.drawn.set(true);
     }
 }
 
 impl Screen for ConsoleScreen {
-    fn request_animation_frame(&self) -> LocalBoxFuture<()> {
+    fn request_animation_frame(&self) -> LocalBoxFuture<'static, ()> {
         sleep(Duration::new(0, 10000)).boxed_local()
     }
-}
\ No newline at end of file
+    fn vblank(&self) {
+        let presents_on_draw = {
+            let mut filter = self.filter.borrow_mut();
+            filter.on_vblank(&self.state);
+            filter.presents_on_draw()
+        };
+        if !presents_on_draw {
+            self.draw_console();
+        }
+    }
+}
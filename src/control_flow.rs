@@ -0,0 +1,364 @@
+//! Control-flow graph of a ROM: basic blocks, subroutines, computed jumps and unreachable bytes,
+//! with suspicious patterns flagged. Exports to Graphviz DOT and JSON.
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::analysis::{is_skip, length, mnemonic, Image};
+use crate::cpu_decoder::decode;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    // the next instruction, also where a CALL returns to
+    Next,
+    Jump,
+    // past the next instruction when a skip's condition holds
+    Skip,
+    Call,
+    // JP V0, nnn: only the base address is known
+    Computed,
+}
+
+impl EdgeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Next => "next",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Skip => "skip",
+            EdgeKind::Call => "call",
+            EdgeKind::Computed => "computed",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub to: u16,
+    pub kind: EdgeKind,
+}
+
+/**
+* Straight-line code: only the first instruction is a target, only the last one branches
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: u16,
+    // (address, opcode)
+    pub instructions: Vec<(u16, u16)>,
+    pub edges: Vec<Edge>,
+}
+
+impl BasicBlock {
+    /** First address after the block */
+    pub fn end(&self) -> u16 {
+        self.instructions.last().map_or(self.start, |(addr, opcode)| addr + length(*opcode))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Subroutine {
+    pub entry: u16,
+    // start addresses of the blocks reachable from the entry without following calls
+    pub blocks: Vec<u16>,
+    // addresses of the CALLs to it
+    pub callers: Vec<u16>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Issue {
+    // CALL/JP to an address that's loaded into I somewhere, i.e. sprite or other data
+    CallIntoData { from: u16, to: u16 },
+    JumpIntoData { from: u16, to: u16 },
+    // to the middle of another instruction
+    Misaligned { from: u16, to: u16 },
+    OutsideProgram { from: u16, to: u16 },
+    // reachable code the emulator can't run
+    Undecodable { addr: u16, opcode: u16 },
+}
+
+impl Issue {
+    pub fn describe(&self) -> String {
+        match self {
+            Issue::CallIntoData { from, to } => format!("{:#05x}: CALL into data at {:#05x}", from, to),
+            Issue::JumpIntoData { from, to } => format!("{:#05x}: jump into data at {:#05x}", from, to),
+            Issue::Misaligned { from, to } => format!("{:#05x}: target {:#05x} is inside another instruction", from, to),
+            Issue::OutsideProgram { from, to } => format!("{:#05x}: target {:#05x} is outside the program", from, to),
+            Issue::Undecodable { addr, opcode } => format!("{:#05x}: can't decode {:#06x}", addr, opcode),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ControlFlow {
+    pub load_address: u16,
+    pub blocks: BTreeMap<u16, BasicBlock>,
+    pub subroutines: Vec<Subroutine>,
+    // addresses of JP V0, nnn
+    pub computed_jumps: Vec<u16>,
+    // (start, end) ranges of bytes no reachable instruction covers: data, or code only reached in ways not followed
+    pub unreachable: Vec<(u16, u16)>,
+    pub issues: Vec<Issue>,
+}
+
+fn edges(opcode: u16, successors: &[u16]) -> Vec<Edge> {
+    let kind = |i: usize| match (opcode & 0xF000, i) {
+        (0x1000, _) => EdgeKind::Jump,
+        (0xB000, _) => EdgeKind::Computed,
+        (0x2000, 0) => EdgeKind::Call,
+        _ if is_skip(opcode) && i == 1 => EdgeKind::Skip,
+        _ => EdgeKind::Next,
+    };
+    successors.iter().enumerate().map(|(i, to)| Edge { to: *to, kind: kind(i) }).collect()
+}
+
+fn branches(opcode: u16) -> bool {
+    matches!(opcode & 0xF000, 0x1000 | 0x2000 | 0xB000) || opcode == 0x00EE || opcode == 0x00FD || is_skip(opcode)
+}
+
+/** Follows jumps, calls, skips and returns from the load address */
+pub fn analyse(program: &[u8], load_address: u16) -> ControlFlow {
+    let image = Image::new(program, load_address);
+    let code = image.reachable();
+    let end = load_address as usize + program.len();
+
+    // blocks start at the entry, at targets and after branches
+    // nothing is reachable from a program shorter than an instruction
+    let mut leaders: BTreeSet<u16> = code.keys().next().copied().into_iter().collect();
+    for (addr, opcode) in &code {
+        if branches(*opcode) {
+            leaders.extend(image.successors(*addr).into_iter().filter(|to| code.contains_key(to)));
+        }
+    }
+    let mut blocks = BTreeMap::new();
+    for leader in &leaders {
+        let mut block = BasicBlock { start: *leader, instructions: vec![], edges: vec![] };
+        let mut addr = *leader;
+        loop {
+            let opcode = code[&addr];
+            block.instructions.push((addr, opcode));
+            let next = addr + length(opcode);
+            if branches(opcode) || leaders.contains(&next) || !code.contains_key(&next) {
+                block.edges = edges(opcode, &image.successors(addr));
+                break;
+            }
+            addr = next;
+        }
+        blocks.insert(*leader, block);
+    }
+
+    // bytes covered by reachable instructions, and the addresses loaded into I
+    let mut covered = BTreeSet::new();
+    for (addr, opcode) in &code {
+        for offset in 0..length(*opcode) {
+            covered.insert(addr + offset);
+        }
+    }
+    let data: BTreeSet<u16> = code.values().filter(|opcode| *opcode & 0xF000 == 0xA000).map(|opcode| opcode & 0xFFF).collect();
+
+    let mut issues = vec![];
+    let mut computed_jumps = vec![];
+    let mut callers: BTreeMap<u16, Vec<u16>> = BTreeMap::new();
+    for (addr, opcode) in &code {
+        if decode(*opcode).is_err() {
+            issues.push(Issue::Undecodable { addr: *addr, opcode: *opcode });
+        }
+        let target = opcode & 0xFFF;
+        match opcode & 0xF000 {
+            0x1000 | 0x2000 => {
+                let call = opcode & 0xF000 == 0x2000;
+                if call {
+                    callers.entry(target).or_default().push(*addr);
+                }
+                if !(load_address as usize..end).contains(&(target as usize)) {
+                    issues.push(Issue::OutsideProgram { from: *addr, to: target });
+                } else if data.contains(&target) {
+                    issues.push(if call { Issue::CallIntoData { from: *addr, to: target } } else { Issue::JumpIntoData { from: *addr, to: target } });
+                } else if code.range(..target).next_back().is_some_and(|(start, opcode)| start + length(*opcode) > target) {
+                    issues.push(Issue::Misaligned { from: *addr, to: target });
+                }
+            }
+            0xB000 => computed_jumps.push(*addr),
+            _ => {}
+        }
+    }
+
+    let subroutines = callers
+        .into_iter()
+        .filter(|(entry, _)| blocks.contains_key(entry))
+        .map(|(entry, callers)| Subroutine { entry, blocks: body(&blocks, entry), callers })
+        .collect();
+
+    let mut unreachable = vec![];
+    let mut start = None;
+    for addr in load_address..end as u16 {
+        match (covered.contains(&addr), start) {
+            (false, None) => start = Some(addr),
+            (true, Some(s)) => {
+                unreachable.push((s, addr));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        unreachable.push((s, end as u16));
+    }
+
+    ControlFlow { load_address, blocks, subroutines, computed_jumps, unreachable, issues }
+}
+
+// blocks from entry up to the RETs, stepping over calls
+fn body(blocks: &BTreeMap<u16, BasicBlock>, entry: u16) -> Vec<u16> {
+    let mut seen = BTreeSet::new();
+    let mut todo = vec![entry];
+    while let Some(start) = todo.pop() {
+        if let Some(block) = blocks.get(&start) {
+            if seen.insert(start) {
+                todo.extend(block.edges.iter().filter(|edge| edge.kind != EdgeKind::Call).map(|edge| edge.to));
+            }
+        }
+    }
+    seen.into_iter().collect()
+}
+
+impl ControlFlow {
+    /** Graphviz: one box per block, subroutines as clusters, calls dashed */
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph rom {\n  node [shape=box fontname=\"monospace\"];\n");
+        let mut placed = BTreeSet::new();
+        for sub in &self.subroutines {
+            let _ = writeln!(dot, "  subgraph cluster_{:03x} {{\n    label=\"sub {:#05x}\";", sub.entry, sub.entry);
+            for start in &sub.blocks {
+                if placed.insert(*start) {
+                    let _ = writeln!(dot, "    {}", self.dot_node(*start));
+                }
+            }
+            dot.push_str("  }\n");
+        }
+        for start in self.blocks.keys() {
+            if placed.insert(*start) {
+                let _ = writeln!(dot, "  {}", self.dot_node(*start));
+            }
+        }
+        for block in self.blocks.values() {
+            for edge in &block.edges {
+                let style = match edge.kind {
+                    EdgeKind::Call => " style=dashed",
+                    EdgeKind::Computed => " style=dotted",
+                    _ => "",
+                };
+                let _ = writeln!(dot, "  b{:03x} -> b{:03x} [label=\"{}\"{}];", block.start, edge.to, edge.kind.name(), style);
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn dot_node(&self, start: u16) -> String {
+        let mut label = String::new();
+        for (addr, opcode) in &self.blocks[&start].instructions {
+            let _ = write!(label, "{:03X}  {:04X}  {}\\l", addr, opcode, mnemonic(*opcode));
+        }
+        let flagged = self.issues.iter().any(|issue| match issue {
+            Issue::Undecodable { addr, .. } => self.blocks[&start].instructions.iter().any(|(a, _)| a == addr),
+            _ => false,
+        });
+        format!("b{:03x} [label=\"{}\"{}];", start, label, if flagged { " color=red" } else { "" })
+    }
+
+    /** The same data as JSON; addresses and opcodes are numbers */
+    pub fn to_json(&self) -> String {
+        let mut json = format!("{{\"loadAddress\":{},\"blocks\":[", self.load_address);
+        for (i, block) in self.blocks.values().enumerate() {
+            let instructions: Vec<String> = block.instructions.iter()
+                .map(|(addr, opcode)| format!("{{\"addr\":{},\"opcode\":{},\"asm\":\"{}\"}}", addr, opcode, mnemonic(*opcode)))
+                .collect();
+            let edges: Vec<String> = block.edges.iter().map(|edge| format!("{{\"to\":{},\"kind\":\"{}\"}}", edge.to, edge.kind.name())).collect();
+            let _ = write!(
+                json,
+                "{}{{\"start\":{},\"end\":{},\"instructions\":[{}],\"edges\":[{}]}}",
+                if i > 0 { "," } else { "" },
+                block.start,
+                block.end(),
+                instructions.join(","),
+                edges.join(",")
+            );
+        }
+        let subroutines: Vec<String> = self.subroutines.iter()
+            .map(|sub| format!("{{\"entry\":{},\"blocks\":{:?},\"callers\":{:?}}}", sub.entry, sub.blocks, sub.callers))
+            .collect();
+        let unreachable: Vec<String> = self.unreachable.iter().map(|(start, end)| format!("{{\"start\":{},\"end\":{}}}", start, end)).collect();
+        let issues: Vec<String> = self.issues.iter().map(|issue| format!("\"{}\"", issue.describe())).collect();
+        let _ = write!(
+            json,
+            "],\"subroutines\":[{}],\"computedJumps\":{:?},\"unreachable\":[{}],\"issues\":[{}]}}",
+            subroutines.join(","),
+            self.computed_jumps,
+            unreachable.join(","),
+            issues.join(",")
+        );
+        json
+    }
+}
+
+#[test]
+fn test_blocks_and_subroutines() {
+    let program = [
+        0xA2, 0x0E, // LD I, 0x20E
+        0x22, 0x0A, // CALL 0x20A
+        0x30, 0x01, // SE V0, 0x01
+        0x12, 0x04, // JP 0x204
+        0x12, 0x02, // JP 0x202
+        0x70, 0x01, // ADD V0, 0x01
+        0x00, 0xEE, // RET
+        0xF0, 0x90, // sprite
+    ];
+    let flow = analyse(&program, 0x200);
+    assert_eq!(flow.blocks.keys().copied().collect::<Vec<_>>(), vec![0x200, 0x202, 0x204, 0x206, 0x208, 0x20A]);
+    assert_eq!(flow.blocks[&0x202].edges, vec![Edge { to: 0x20A, kind: EdgeKind::Call }, Edge { to: 0x204, kind: EdgeKind::Next }]);
+    assert_eq!(flow.blocks[&0x204].edges, vec![Edge { to: 0x206, kind: EdgeKind::Next }, Edge { to: 0x208, kind: EdgeKind::Skip }]);
+    let sub = &flow.blocks[&0x20A];
+    assert_eq!((sub.instructions.len(), sub.end(), sub.edges.len()), (2, 0x20E, 0));
+    assert_eq!(flow.subroutines, vec![Subroutine { entry: 0x20A, blocks: vec![0x20A], callers: vec![0x202] }]);
+    assert_eq!(flow.unreachable, vec![(0x20E, 0x210)]);
+    assert!(flow.issues.is_empty());
+
+    let dot = flow.to_dot();
+    assert!(dot.contains("subgraph cluster_20a"));
+    assert!(dot.contains("b202 -> b20a [label=\"call\" style=dashed];"));
+    assert!(dot.contains("202  220A  CALL 0x20a\\l"));
+    let json = flow.to_json();
+    assert!(json.starts_with("{\"loadAddress\":512,\"blocks\":[{\"start\":512,\"end\":514,"));
+    assert!(json.ends_with("\"subroutines\":[{\"entry\":522,\"blocks\":[522],\"callers\":[514]}],\"computedJumps\":[],\"unreachable\":[{\"start\":526,\"end\":528}],\"issues\":[]}"));
+}
+
+#[test]
+fn test_suspicious_patterns() {
+    let program = [
+        0xA2, 0x0A, // LD I, 0x20A
+        0x22, 0x0A, // CALL 0x20A, which is data
+        0x30, 0x00, // SE V0, 0x00
+        0x13, 0x00, // JP 0x300, past the end
+        0xB2, 0x0C, // JP V0, 0x20C
+        0x00, 0xEE,
+        0x12, 0x0D, // JP 0x20D, the middle of itself
+        0xE1, 0xFF, // 0x20D runs 0DE1, then FFFF at 0x20F
+        0xFF,
+    ];
+    let flow = analyse(&program, 0x200);
+    assert_eq!(flow.computed_jumps, vec![0x208]);
+    assert_eq!(
+        flow.issues,
+        vec![
+            Issue::CallIntoData { from: 0x202, to: 0x20A },
+            Issue::OutsideProgram { from: 0x206, to: 0x300 },
+            Issue::Misaligned { from: 0x20C, to: 0x20D },
+            Issue::Undecodable { addr: 0x20F, opcode: 0xFFFF },
+        ]
+    );
+    assert_eq!(flow.issues[0].describe(), "0x202: CALL into data at 0x20a");
+    assert!(analyse(&[0x12], 0x200).blocks.is_empty());
+}
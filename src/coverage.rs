@@ -0,0 +1,212 @@
+//! Coverage of the 4 KiB address space: which bytes were executed, read and written during a session,
+//! see CPU::enable_coverage. Runs can be merged, e.g. one per replayed movie.
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::analysis::{length, mnemonic};
+
+const MEM_SIZE: usize = 4096;
+pub const HEATMAP_SIZE: usize = 64;
+
+pub const EXECUTED: u8 = 1;
+// by Dxyn and Fx65
+pub const READ: u8 = 1 << 1;
+// by Fx55 and Fx33
+pub const WRITTEN: u8 = 1 << 2;
+// an opcode was fetched from here, not just executed as its second byte
+const OPCODE: u8 = 1 << 3;
+
+const MAGIC: [u8; 3] = *b"C8C";
+const VERSION: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Coverage {
+    flags: Vec<u8>,
+    // accesses of any kind
+    hits: Vec<u32>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage { flags: vec![0; MEM_SIZE], hits: vec![0; MEM_SIZE] }
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    pub(crate) fn record(&mut self, addr: usize, flag: u8) {
+        if let Some(flags) = self.flags.get_mut(addr) {
+            *flags |= flag;
+            self.hits[addr] = self.hits[addr].saturating_add(1);
+        }
+    }
+
+    pub(crate) fn record_opcode(&mut self, pc: usize) {
+        self.record(pc, EXECUTED | OPCODE);
+        self.record(pc + 1, EXECUTED);
+    }
+
+    /** EXECUTED, READ and WRITTEN bits of an address */
+    pub fn flags(&self, addr: u16) -> u8 {
+        self.flags.get(usize::from(addr)).map_or(0, |flags| flags & (EXECUTED | READ | WRITTEN))
+    }
+
+    pub fn hits(&self, addr: u16) -> u32 {
+        self.hits.get(usize::from(addr)).copied().unwrap_or(0)
+    }
+
+    /** Bytes in the range with any of the flag bits */
+    pub fn count(&self, start: u16, end: u16, flag: u8) -> usize {
+        (start..end).filter(|addr| self.flags(*addr) & flag != 0).count()
+    }
+
+    /** Adds another run: flags are combined, hit counts summed */
+    pub fn merge(&mut self, other: &Coverage) {
+        for addr in 0..MEM_SIZE {
+            self.flags[addr] |= other.flags[addr];
+            self.hits[addr] = self.hits[addr].saturating_add(other.hits[addr]);
+        }
+    }
+
+    /** For merging runs from different processes: a header, the flags, then the hits as little-endian u32s */
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend_from_slice(&self.flags);
+        out.extend(self.hits.iter().flat_map(|hits| hits.to_le_bytes()));
+        out
+    }
+
+    /** None unless it came from to_bytes */
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != 4 + MEM_SIZE * 5 || data[..3] != MAGIC || data[3] != VERSION {
+            return None;
+        }
+        let flags = data[4..4 + MEM_SIZE].to_vec();
+        let hits = data[4 + MEM_SIZE..].chunks_exact(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        Some(Coverage { flags, hits })
+    }
+
+    /**
+     * The program as listed from load_address, each line prefixed with the xrw flags seen.
+     * Executed opcodes are disassembled; the bytes never reached as code are listed as data
+     */
+    pub fn disassembly(&self, program: &[u8], load_address: u16) -> String {
+        let end = load_address + program.len() as u16;
+        let executed = self.count(load_address, end, EXECUTED);
+        let mut out = format!(
+            "; {} of {} bytes executed, {} read, {} written\n",
+            executed,
+            program.len(),
+            self.count(load_address, end, READ),
+            self.count(load_address, end, WRITTEN)
+        );
+        let byte = |addr: u16| program[usize::from(addr - load_address)];
+        let mut addr = load_address;
+        while addr < end {
+            let flags = self.flags(addr);
+            let marks: String = [(EXECUTED, 'x'), (READ, 'r'), (WRITTEN, 'w')]
+                .iter()
+                .map(|(flag, mark)| if flags & flag != 0 { *mark } else { '-' })
+                .collect();
+            if self.flags[usize::from(addr)] & OPCODE != 0 && addr + 1 < end {
+                let opcode = u16::from_be_bytes([byte(addr), byte(addr + 1)]);
+                let _ = writeln!(out, "{} {:#05x}  {:04X}  {:<16} ; {}", marks, addr, opcode, mnemonic(opcode), self.hits(addr));
+                addr += length(opcode).min(end - addr);
+            } else {
+                let _ = writeln!(out, "{} {:#05x}  {:02X}    DB {:#04x}", marks, addr, byte(addr), byte(addr));
+                addr += 1;
+            }
+        }
+        out
+    }
+
+    /**
+     * The address space as a 64x64 RGBA image, one pixel per byte from 0x000 at the top left.
+     * Green is executed, blue read and red written, brighter for more hits; untouched bytes are black
+     */
+    pub fn heatmap(&self) -> Vec<u8> {
+        // log2 scale, without floats
+        let bits = |hits: u32| 32 - hits.leading_zeros();
+        let max = bits(self.hits.iter().copied().max().unwrap_or(0)).max(1);
+        let mut pixels = Vec::with_capacity(MEM_SIZE * 4);
+        for addr in 0..MEM_SIZE {
+            let flags = self.flags[addr];
+            let level = if flags == 0 { 0 } else { (64 + 191 * bits(self.hits[addr]) / max) as u8 };
+            let channel = |flag: u8| if flags & flag != 0 { level } else { 0 };
+            pixels.extend_from_slice(&[channel(WRITTEN), channel(EXECUTED), channel(READ), 0xFF]);
+        }
+        pixels
+    }
+
+    /** heatmap() as a binary PPM file */
+    pub fn heatmap_ppm(&self) -> Vec<u8> {
+        let mut out = format!("P6\n{} {}\n255\n", HEATMAP_SIZE, HEATMAP_SIZE).into_bytes();
+        for rgba in self.heatmap().chunks_exact(4) {
+            out.extend_from_slice(&rgba[..3]);
+        }
+        out
+    }
+}
+
+#[test]
+fn test_disassembly() {
+    let program = [0x60, 0x03, 0x12, 0x02, 0xF0, 0x90];
+    let mut coverage = Coverage::new();
+    coverage.record_opcode(0x200);
+    coverage.record_opcode(0x202);
+    coverage.record_opcode(0x202);
+    coverage.record(0x204, READ);
+    assert_eq!(
+        coverage.disassembly(&program, 0x200),
+        "; 4 of 6 bytes executed, 1 read, 0 written\n\
+         x-- 0x200  6003  LD V0, 0x03      ; 1\n\
+         x-- 0x202  1202  JP 0x202         ; 2\n\
+         -r- 0x204  F0    DB 0xf0\n\
+         --- 0x205  90    DB 0x90\n"
+    );
+}
+
+#[test]
+fn test_merge_and_heatmap() {
+    let mut first = Coverage::new();
+    first.record_opcode(0x200);
+    let mut second = Coverage::new();
+    second.record(0x300, WRITTEN);
+    second.record_opcode(0x200);
+    first.merge(&Coverage::from_bytes(&second.to_bytes()).unwrap());
+    assert_eq!((first.flags(0x200), first.hits(0x200)), (EXECUTED, 2));
+    assert_eq!(first.flags(0x300), WRITTEN);
+    let heatmap = first.heatmap();
+    assert_eq!(heatmap.len(), HEATMAP_SIZE * HEATMAP_SIZE * 4);
+    // 0x200 is row 8; 2 hits is the brightest
+    assert_eq!(&heatmap[0x200 * 4..0x200 * 4 + 4], &[0, 255, 0, 255]);
+    assert_eq!(&heatmap[0x300 * 4..0x300 * 4 + 4], &[159, 0, 0, 255]);
+    assert_eq!(&heatmap[..4], &[0, 0, 0, 255]);
+    assert!(first.heatmap_ppm().starts_with(b"P6\n64 64\n255\n"));
+    assert_eq!(Coverage::from_bytes(&[0; 8]), None);
+}
+
+#[test]
+fn test_queued_coverage() {
+    use crate::command_queue::Command;
+    use crate::cpu::CPU;
+    use crate::headless_screen::HeadlessScreen;
+
+    let mut cpu = CPU::new(Box::new(HeadlessScreen::new()));
+    cpu.load_program(vec![0x12, 0x00]).unwrap();
+    let mut saved = Coverage::new();
+    saved.record(0x300, READ);
+    cpu.command_queue().push(Command::EnableCoverage);
+    cpu.command_queue().push(Command::MergeCoverage(saved));
+    assert!(cpu.coverage().is_none());
+    cpu.run_frame_now();
+    let coverage = cpu.coverage().unwrap();
+    assert_eq!((coverage.flags(0x200), coverage.flags(0x300)), (EXECUTED, READ));
+}
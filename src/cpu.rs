@@ -1,36 +1,58 @@
-use std::{println};
-use std::borrow::BorrowMut;
-use std::convert::{TryFrom};
-use std::future::Future;
-use std::ops::{Add, DerefMut, Sub};
-use std::sync::{Arc, Mutex};
-
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Add;
+#[cfg(feature = "runner")]
+use std::cell::RefCell;
+#[cfg(feature = "runner")]
+use std::rc::Rc;
+#[cfg(feature = "romdb")]
+use std::sync::Arc;
+#[cfg(feature = "runner")]
 use std::time::Duration;
-use anyhow::Result;
-use fluvio_wasm_timer::{Delay};
-use futures::future::BoxFuture;
-use crate::keyboard::PC_KEY_MAP;
 
+#[cfg(feature = "runner")]
+use fluvio_wasm_timer::Delay;
 
+#[cfg(feature = "std")]
+use rand::thread_rng;
 use rand::{Rng, RngCore, SeedableRng};
 use rand_chacha::ChaCha8Rng;
 use ux::{u12, u4};
 
 
-use crate::cpu_decoder::{decode};
+use crate::command_queue::{Command, CommandQueue};
+use crate::audio::Audio;
+use crate::cpu_decoder::{decode, DecodeError};
+use crate::cpu_instructions::X;
+use crate::events::{EmulatorEvent, StopReason};
 use crate::macros::newtype_copy;
-use crate::screen::{Screen};
-use crate::keyboard::{KeyboardState};
+use crate::framebuffer::Framebuffer;
+use crate::screen::{Screen, ScreenDraw};
+use crate::snapshot::{self, SnapshotError};
+use crate::keyboard::{KeyMap, KeyboardState};
+use crate::rom::{check_fits, LoadError, Rom, PROGRAM_START_ADDR};
+use crate::coverage::{self, Coverage};
+use crate::profiler::Profiler;
+use crate::provenance::{self, MemoryWrite, Provenance};
+use crate::cheats::{Cheat, CheatKind, CheatList};
+use crate::sanitizer::{Report, Sanitizer, SanitizerOptions};
+use crate::theme::Theme;
+#[cfg(feature = "romdb")]
+use crate::rom_db::{RomDatabase, RomInfo};
 
 const MEM_SIZE: usize = 4096;
-const PROGRAM_START_ADDR: u16 = 0x0200;
+pub const MAX_PROGRAM_SIZE: usize = MEM_SIZE - PROGRAM_START_ADDR as usize;
 const STACK_SIZE: usize = 16;
 const REGISTERS_SIZE: usize = 16;
 
-const STEPS_PER_CYCLE: usize = 10;
+pub(crate) const STEPS_PER_CYCLE: usize = 10;
+#[cfg(feature = "runner")]
 const SPEED: u64 = 60; // herz
 
-const FONTS_LENGTH: usize = 80;
+pub(crate) const FONTS_LENGTH: usize = 80;
 const FONTS: [MemPrimitive; FONTS_LENGTH] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -104,6 +126,13 @@ pub struct CPUState {
     // not in spec
     pub(crate) rng_seed: u64,
     pub(crate) keyboard: KeyboardState,
+    pub(crate) framebuffer: Framebuffer,
+    // kept across resets, see CPU::enable_coverage
+    pub(crate) coverage: Option<Box<Coverage>>,
+    // see CPU::enable_provenance
+    pub(crate) provenance: Option<Box<Provenance>>,
+    // memory written by the running instruction, while a frame is observed
+    pub(crate) write_log: Option<Vec<(u16, MemPrimitive)>>,
 }
 
 /**
@@ -111,10 +140,10 @@ pub struct CPUState {
 * @property {boolean} shift - If enabled, VX is shifted and VY remains unchanged (default: false)
 * @property {boolean} loadStore - If enabled, I is not incremented during load/store (default: false)
 */
-#[derive(Clone, Debug)]
-pub(crate) struct CPUQuirks {
-    pub(crate) shift: bool,
-    pub(crate) load_store: bool,
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CPUQuirks {
+    pub shift: bool,
+    pub load_store: bool,
 }
 
 impl CPUQuirks {
@@ -124,9 +153,62 @@ impl CPUQuirks {
 }
 
 impl CPUState {
-    fn fetch(&self) -> u16 {
+    pub(crate) fn new() -> Self {
+        let mut mem = [MemValue(0); MEM_SIZE];
+        load_font_set(&mut mem);
+        CPUState {
+            mem,
+            v: [V(0); REGISTERS_SIZE],
+            pc: PC(u12::new(PROGRAM_START_ADDR)),
+            i: I(u12::new(0)),
+            stack: [u12::new(0); STACK_SIZE],
+            sp: SP(u4::new(0)),
+            repaint: Repaint(false),
+            halted: Halted(false),
+            waiting_kb: WaitingKb(false),
+            waiting_kb_x: None,
+            dt: DT(0),
+            st: ST(0),
+            quirks: CPUQuirks::new(),
+            rng_seed: initial_rng_seed(),
+            keyboard: KeyboardState::new(),
+            framebuffer: Framebuffer::new(),
+            coverage: None,
+            provenance: None,
+            write_log: None,
+        }
+    }
+    pub(crate) fn fetch(&self) -> u16 {
         u16::from_be_bytes([self.mem[self.pci()].0, self.mem[self.pci() + 1].0])
     }
+    // instructions access memory through these two, for coverage and provenance
+    pub(crate) fn read_mem(&mut self, addr: usize) -> MemPrimitive {
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(addr, coverage::READ);
+        }
+        self.mem[addr].0
+    }
+    pub(crate) fn write_mem(&mut self, addr: usize, value: MemPrimitive) {
+        if let Some(log) = self.write_log.as_mut() {
+            log.push((addr as u16, value));
+        }
+        // the instruction is still at PC while it runs
+        let writer = self.provenance.is_some().then(|| (self.pci() as u16, self.fetch()));
+        self.store(addr, value, writer);
+    }
+    // pokes and freezes; they aren't part of any instruction's StepTrace
+    pub(crate) fn write_mem_by_cheat(&mut self, addr: usize, value: MemPrimitive) {
+        self.store(addr, value, Some((provenance::CHEAT, provenance::CHEAT)));
+    }
+    fn store(&mut self, addr: usize, value: MemPrimitive, writer: Option<(u16, u16)>) {
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(addr, coverage::WRITTEN);
+        }
+        if let (Some(provenance), Some((pc, opcode))) = (self.provenance.as_mut(), writer) {
+            provenance.record(addr as u16, value, pc, opcode);
+        }
+        self.mem[addr].0 = value;
+    }
     pub(crate) fn pci(&self) -> usize {
         let r: u16 = self.pc.0.into();
         r.into()
@@ -153,13 +235,49 @@ impl CPUState {
     // }
 }
 
-use wasm_bindgen::prelude::*;
-use crate::cpu_instructions::X;
+// without an OS to ask, every run is the same; use CPU::seed_rng to vary it
+#[cfg(feature = "std")]
+fn initial_rng_seed() -> u64 {
+    thread_rng().next_u64()
+}
+
+#[cfg(not(feature = "std"))]
+fn initial_rng_seed() -> u64 {
+    0x5EED_C8C8_5EED_C8C8
+}
 
 pub struct CPU {
     pub(crate) state: CPUState,
     stopped: bool,
+    paused: bool,
+    // frames to run while paused
+    frames_to_advance: usize,
+    // kept for reset
+    program: Vec<u8>,
+    load_address: u16,
     screen: Box<dyn Screen>,
+    // presented alongside the screen, e.g. for recording
+    presenters: Vec<Box<dyn ScreenDraw>>,
+    commands: CommandQueue,
+    events: Vec<EmulatorEvent>,
+    breakpoints: Vec<u16>,
+    // the breakpoint we stopped at, so that resuming doesn't hit it again right away
+    resumed_breakpoint: Option<u16>,
+    sound_on: bool,
+    audio: Option<Box<dyn Audio>>,
+    sanitizer: Option<Sanitizer>,
+    profiler: Option<Profiler>,
+    cheats: CheatList,
+    steps_per_frame: usize,
+    keymap: KeyMap,
+    // the loaded program's bindings from rom_db, used instead of keymap
+    #[cfg(feature = "romdb")]
+    rom_keymap: Option<KeyMap>,
+    #[cfg(feature = "romdb")]
+    rom_db: Option<Arc<RomDatabase>>,
+    // what rom_db knows about the loaded program
+    #[cfg(feature = "romdb")]
+    rom_info: Option<RomInfo>,
 }
 
 fn load_font_set(mem: &mut Mem) {
@@ -170,58 +288,300 @@ fn load_font_set(mem: &mut Mem) {
 
 impl CPU {
     pub fn new(screen: Box<dyn Screen>) -> Self {
-        let mut mem = [MemValue(0); MEM_SIZE];
-        load_font_set(&mut mem);
         Self {
-            state: CPUState {
-                mem,
-                v: [V(0); REGISTERS_SIZE],
-                pc: PC(u12::new(PROGRAM_START_ADDR)),
-                i: I(u12::new(0)),
-                stack: [u12::new(0); STACK_SIZE],
-                sp: SP(u4::new(0)),
-                repaint: Repaint(false),
-                halted: Halted(false),
-                waiting_kb: WaitingKb(false),
-                waiting_kb_x: None,
-                dt: DT(0),
-                st: ST(0),
-                quirks: CPUQuirks::new(),
-                rng_seed: rand::thread_rng().next_u64(),
-                keyboard: KeyboardState::new(),
-            },
+            state: CPUState::new(),
             screen,
+            presenters: vec![],
+            commands: CommandQueue::new(),
             stopped: false,
+            paused: false,
+            frames_to_advance: 0,
+            program: vec![],
+            load_address: PROGRAM_START_ADDR,
+            events: vec![],
+            breakpoints: vec![],
+            resumed_breakpoint: None,
+            sound_on: false,
+            audio: None,
+            sanitizer: None,
+            profiler: None,
+            cheats: CheatList::new(),
+            steps_per_frame: STEPS_PER_CYCLE,
+            keymap: KeyMap::new(),
+            #[cfg(feature = "romdb")]
+            rom_keymap: None,
+            #[cfg(feature = "romdb")]
+            rom_db: None,
+            #[cfg(feature = "romdb")]
+            rom_info: None,
+        }
+    }
+    /**
+     * Copies the program to the load address, 0x200 unless a Rom said otherwise. With a ROM database,
+     * a recognised program also brings its quirks, speed, keys and colours
+     */
+    pub fn load_program(&mut self, data: Vec<u8>) -> Result<(), LoadError> {
+        check_fits(data.len(), self.load_address)?;
+        #[cfg(feature = "romdb")]
+        self.identify(&data);
+        self.write_program(data);
+        Ok(())
+    }
+
+    /**
+     * Loads a parsed ROM file at its address, with the settings it carries; a ROM database has the last word
+     */
+    pub fn load(&mut self, rom: Rom) -> Result<(), LoadError> {
+        self.use_rom(&rom)?;
+        self.load_program(rom.program)
+    }
+
+    pub fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    /** The loaded program as it was before running */
+    pub fn program(&self) -> &[u8] {
+        &self.program
+    }
+
+    fn use_rom(&mut self, rom: &Rom) -> Result<(), LoadError> {
+        check_fits(rom.program.len(), rom.load_address)?;
+        rom.check_variant()?;
+        self.load_address = rom.load_address;
+        if let Some(platform) = rom.platform {
+            self.set_quirks(platform.quirks());
+        }
+        if let Some(quirks) = rom.quirks {
+            self.set_quirks(quirks);
         }
+        if let Some(tickrate) = rom.tickrate {
+            self.set_steps_per_frame(tickrate as usize);
+        }
+        if let Some(theme) = rom.theme.as_ref() {
+            self.set_theme(theme);
+        }
+        Ok(())
     }
-    pub fn load_program(&mut self, data: Vec<u8>) {
-        assert!(u12::max_value().sub(u12::new(PROGRAM_START_ADDR)) >= u12::new(u16::try_from(data.len()).expect("Data len takes more than u16")));
+
+    // callers check the size
+    fn write_program(&mut self, data: Vec<u8>) {
+        let start = usize::from(self.load_address);
         for (i, x) in data.iter().enumerate() {
-            self.state.mem[usize::from(PROGRAM_START_ADDR) + i].0 = *x;
+            self.state.mem[start + i].0 = *x;
+        }
+        self.state.pc = PC(u12::new(self.load_address));
+        if let Some(sanitizer) = self.sanitizer.as_mut() {
+            sanitizer.power_on(&mut self.state, self.load_address, data.len());
+        }
+        self.program = data;
+    }
+
+    /**
+     * Power cycle: fresh CPUState with fonts and the current program reloaded; quirks are kept
+     */
+    pub fn reset(&mut self) {
+        let quirks = self.state.quirks;
+        let coverage = self.state.coverage.take();
+        let mut provenance = self.state.provenance.take();
+        let write_log = self.state.write_log.take();
+        self.state = CPUState::new();
+        self.state.quirks = quirks;
+        self.state.coverage = coverage;
+        self.state.write_log = write_log;
+        if let Some(provenance) = provenance.as_mut() {
+            provenance.power_on();
+        }
+        self.state.provenance = provenance;
+        let program = core::mem::take(&mut self.program);
+        self.write_program(program);
+        self.state.framebuffer.clear();
+        self.present();
+    }
+
+    /**
+     * Swaps the game; same as reset with another program
+     */
+    pub fn load_rom(&mut self, rom: Rom) -> Result<(), LoadError> {
+        self.use_rom(&rom)?;
+        #[cfg(feature = "romdb")]
+        self.identify(&rom.program);
+        self.program = rom.program;
+        self.reset();
+        Ok(())
+    }
+
+    fn set_theme(&mut self, theme: &Theme) {
+        self.screen.set_theme(theme);
+        for presenter in self.presenters.iter_mut() {
+            presenter.set_theme(theme);
+        }
+    }
+
+    /**
+     * Settings for the programs it knows; applies to the loaded program right away
+     */
+    #[cfg(feature = "romdb")]
+    pub fn set_rom_database(&mut self, db: Arc<RomDatabase>) {
+        self.rom_db = Some(db);
+        let program = core::mem::take(&mut self.program);
+        self.identify(&program);
+        self.program = program;
+    }
+
+    #[cfg(feature = "romdb")]
+    pub fn rom_info(&self) -> Option<&RomInfo> {
+        self.rom_info.as_ref()
+    }
+
+    // settings the database doesn't have for the program are left as they are
+    #[cfg(feature = "romdb")]
+    fn identify(&mut self, program: &[u8]) {
+        let info = match self.rom_db.as_ref().and_then(|db| db.identify(program)) {
+            Some(info) => info.clone(),
+            None => {
+                self.rom_info = None;
+                self.rom_keymap = None;
+                return;
+            }
+        };
+        if let Some(quirks) = info.quirks {
+            self.set_quirks(quirks);
+        }
+        if let Some(tickrate) = info.tickrate {
+            self.set_steps_per_frame(tickrate as usize);
+        }
+        self.rom_keymap = info.keys.clone();
+        if let Some(theme) = info.theme.as_ref() {
+            self.set_theme(theme);
         }
+        log::info!("recognised {}", info.describe());
+        self.rom_info = Some(info);
     }
 
     // for spawning locally
     // wasm compatible - awaits to free the thread instead of blocking
     // todo maybe get delay constructor as an argument
-    // TODO do something with wasm_mutex::Mutex requirement\
-    pub async fn run(this: Arc<wasm_mutex::Mutex<CPU>>) {
-        let arc = this.clone();
+    // the CPU is only borrowed between awaits; front-ends talk to it through command_queue()
+    #[cfg(feature = "runner")]
+    pub async fn run(this: Rc<RefCell<CPU>>) {
+        CPU::run_with_listener(this, |_| {}).await;
+    }
+
+    /**
+     * Same as run; the listener gets the events of every frame, called when the CPU isn't borrowed,
+     * so it's free to use the CPU again
+     */
+    #[cfg(feature = "runner")]
+    pub async fn run_with_listener(this: Rc<RefCell<CPU>>, mut listener: impl FnMut(EmulatorEvent)) {
         loop {
-            Delay::new(Duration::new(1 / SPEED, 0)).await;
-            let mut guard = arc.lock().await;
-            let guard: &mut CPU = guard.deref_mut(); // for compiler to understand splitting borrow later
-            if guard.is_done() {
+            // the timer can only fail if its backend went away; run the frame regardless
+            let _ = Delay::new(Duration::new(1 / SPEED, 0)).await;
+            let frame = {
+                let cpu = this.borrow();
+                cpu.screen.vblank();
+                cpu.screen.request_animation_frame()
+            };
+            frame.await;
+            let (events, done) = {
+                let mut cpu = this.borrow_mut();
+                cpu.run_frame();
+                (cpu.take_events(), cpu.is_done())
+            };
+            for event in events {
+                listener(event);
+            }
+            if done {
                 break;
             }
-            // TODO locks for too long?
-            guard.screen.request_animation_frame().await;
-            match CPU::cycle(&mut guard.state, &mut *guard.screen) {
-                Ok(()) => (),
-                Err(e) => {
-                    println!("Error during cycle, {}. STOPPING", e);
-                    guard.stop();
+        }
+    }
+
+    /**
+     * Runs a frame right away, without waiting for the screen; for headless use.
+     * Events pile up until take_events
+     */
+    pub fn run_frame_now(&mut self) {
+        self.screen.vblank();
+        self.run_frame();
+    }
+
+    /**
+     * Same as run_frame_now, calling observer after every instruction with what it did;
+     * the observer may change the CPU before the next one runs
+     */
+    pub fn run_frame_observed(&mut self, observer: &mut dyn FnMut(&mut CPU, &StepTrace)) {
+        self.screen.vblank();
+        self.state.write_log = Some(vec![]);
+        self.run_frame_with(Some(observer));
+        self.state.write_log = None;
+    }
+
+    fn run_frame(&mut self) {
+        self.run_frame_with(None);
+    }
+
+    fn run_frame_with(&mut self, observer: Option<Observer>) {
+        self.apply_commands();
+        if self.is_done() {
+            return;
+        }
+        if self.paused {
+            if self.frames_to_advance == 0 {
+                return;
+            }
+            self.frames_to_advance -= 1;
+        }
+        match self.cycle(observer) {
+            Ok(()) => (),
+            Err(e) => {
+                log::error!("Error during cycle, {}. STOPPING", e);
+                self.stop_with(StopReason::Error(e.to_string()));
+            }
+        }
+    }
+
+    pub fn take_events(&mut self) -> Vec<EmulatorEvent> {
+        core::mem::take(&mut self.events)
+    }
+
+    /**
+     * Handle for sending commands to the run loop
+     */
+    pub fn command_queue(&self) -> CommandQueue {
+        self.commands.clone()
+    }
+
+    fn apply_commands(&mut self) {
+        for command in self.commands.take_frame_batch() {
+            match command {
+                Command::KeyDown(k) => self.key_down(k),
+                Command::KeyUp(k) => self.key_up(k),
+                Command::Stop => self.stop(),
+                Command::Pause => self.pause(),
+                Command::Resume => self.resume(),
+                Command::AdvanceFrame => self.advance_frame(),
+                Command::Reset => self.reset(),
+                Command::LoadRom(rom) => {
+                    if let Err(e) = self.load_rom(rom) {
+                        self.stop_with(StopReason::Error(e.to_string()));
+                    }
                 }
+                Command::AddBreakpoint(addr) => self.add_breakpoint(addr),
+                Command::RemoveBreakpoint(addr) => self.remove_breakpoint(addr),
+                #[cfg(feature = "romdb")]
+                Command::SetRomDatabase(db) => self.set_rom_database(db),
+                Command::EnableProfiler => self.enable_profiler(),
+                Command::DisableProfiler => self.disable_profiler(),
+                Command::EnableCoverage => self.enable_coverage(),
+                Command::MergeCoverage(other) => self.merge_coverage(&other),
+                Command::EnableProvenance => self.enable_provenance(),
+                Command::WatchWrites(start, end) => self.watch_writes(start, end),
+                Command::UnwatchWrites(start, end) => self.unwatch_writes(start, end),
+                Command::SetCheats(cheats) => self.set_cheats(cheats),
+                Command::AddCheat(cheat) => self.add_cheat(cheat),
+                Command::RemoveCheats(addr) => self.remove_cheats(addr),
+                Command::Poke(addr, value) => self.poke(addr, value),
             }
         }
     }
@@ -231,74 +591,426 @@ impl CPU {
     }
 
     pub fn is_paused(&self) -> bool {
-        self.state.halted.0
+        self.paused
+    }
+
+    // halted on Fx0A
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.state.waiting_kb.0
     }
 
     pub fn stop(&mut self) {
-        self.stopped = true;
+        self.stop_with(StopReason::Requested);
     }
 
-    fn cycle(state: &mut CPUState, screen_draw: &mut dyn Screen) -> Result<()> {
-        if state.halted.0 {
-            return Ok(());
+    fn stop_with(&mut self, reason: StopReason) {
+        if !self.stopped {
+            self.stopped = true;
+            self.events.push(EmulatorEvent::Stopped(reason));
         }
-        for _ in 0..STEPS_PER_CYCLE {
-            CPU::step(state, screen_draw)?;
+    }
+
+    /**
+     * Pauses before the instruction at addr is executed
+     */
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.retain(|b| *b != addr);
+    }
+
+    fn hit_breakpoint(&mut self) -> bool {
+        let pc = self.state.pci() as u16;
+        if self.resumed_breakpoint.take() == Some(pc) || !self.breakpoints.contains(&pc) {
+            return false;
         }
-        state.update_timers();
+        self.pause();
+        self.resumed_breakpoint = Some(pc);
+        self.events.push(EmulatorEvent::BreakpointHit(pc));
+        true
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.frames_to_advance = 0;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    // runs one more frame while paused
+    pub fn advance_frame(&mut self) {
+        if self.paused {
+            self.frames_to_advance += 1;
+        }
+    }
+
+    pub fn add_presenter(&mut self, presenter: Box<dyn ScreenDraw>) {
+        self.presenters.push(presenter);
+    }
+
+    /**
+     * Save state, snapshot::SNAPSHOT_SIZE bytes
+     */
+    pub fn save_state(&self) -> Vec<u8> {
+        snapshot::save(&self.state)
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> core::result::Result<(), SnapshotError> {
+        snapshot::restore(&mut self.state, data)?;
+        self.present();
         Ok(())
-        // if (this.st > 0) {
-        //     this.audio.play();
-        // } else {
-        //     this.audio.stop();
-        // }
     }
 
-    pub(crate) fn step(state: &mut CPUState, screen_draw: &mut dyn Screen) -> StepResult {
+    /**
+     * Plays the tone while the sound timer runs
+     */
+    pub fn set_audio(&mut self, audio: Box<dyn Audio>) {
+        self.audio = Some(audio);
+    }
+
+    pub fn set_quirks(&mut self, quirks: CPUQuirks) {
+        self.state.quirks = quirks;
+    }
+
+    /**
+     * Instructions per 60 Hz frame, at least 1; timers always tick once per frame
+     */
+    pub fn set_steps_per_frame(&mut self, steps: usize) {
+        self.steps_per_frame = steps.max(1);
+    }
+
+    pub fn steps_per_frame(&self) -> usize {
+        self.steps_per_frame
+    }
+
+    /**
+     * Extra PC key bindings for key_down/key_up, on top of the default layout;
+     * a ROM recognised by the database brings its own until the next call
+     */
+    pub fn set_keymap(&mut self, keymap: KeyMap) {
+        self.keymap = keymap;
+        #[cfg(feature = "romdb")]
+        {
+            self.rom_keymap = None;
+        }
+    }
+
+    fn active_keymap(&self) -> &KeyMap {
+        #[cfg(feature = "romdb")]
+        if let Some(keymap) = self.rom_keymap.as_ref() {
+            return keymap;
+        }
+        &self.keymap
+    }
+
+    /**
+     * Checks every instruction before it runs, see sanitizer::Finding. Findings come as
+     * EmulatorEvent::Sanitizer; the ones the CPU can't carry on from (all of them with stop_on_finding)
+     * stop it with StepError::Sanitizer instead. Randomising RAM waits for the next load or reset
+     */
+    pub fn enable_sanitizer(&mut self, options: SanitizerOptions) {
+        let mut sanitizer = Sanitizer::new(options);
+        sanitizer.forget(self.load_address, self.program.len());
+        self.sanitizer = Some(sanitizer);
+    }
+
+    pub fn disable_sanitizer(&mut self) {
+        self.sanitizer = None;
+    }
+
+    /**
+     * Starts counting executed instructions from scratch, see profiler::Profiler
+     */
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn disable_profiler(&mut self) {
+        self.profiler = None;
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /**
+     * Flags every byte the program executes, reads or writes from now on, across resets and ROM swaps;
+     * keeps what was recorded if already enabled
+     */
+    pub fn enable_coverage(&mut self) {
+        self.state.coverage.get_or_insert_with(Default::default);
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.state.coverage.as_deref()
+    }
+
+    /** Adds another run's coverage, e.g. of a replayed movie; enables coverage if it was off */
+    pub fn merge_coverage(&mut self, other: &Coverage) {
+        self.state.coverage.get_or_insert_with(Default::default).merge(other);
+    }
+
+    /** Stops recording and hands over what was recorded */
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.state.coverage.take().map(|coverage| *coverage)
+    }
+
+    /**
+     * Records the PC, opcode and frame of every write to memory from now on, for last_writer
+     * and watch_writes
+     */
+    pub fn enable_provenance(&mut self) {
+        self.state.provenance.get_or_insert_with(Default::default);
+    }
+
+    pub fn disable_provenance(&mut self) {
+        self.state.provenance = None;
+    }
+
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.state.provenance.as_deref()
+    }
+
+    /** The instruction that last wrote addr, with provenance enabled */
+    pub fn last_writer(&self, addr: u16) -> Option<MemoryWrite> {
+        self.provenance().and_then(|provenance| provenance.last_writer(addr))
+    }
+
+    /** Keeps a history of the writes to start..end; enables provenance */
+    pub fn watch_writes(&mut self, start: u16, end: u16) {
+        self.state.provenance.get_or_insert_with(Default::default).watch(start, end);
+    }
+
+    pub fn unwatch_writes(&mut self, start: u16, end: u16) {
+        if let Some(provenance) = self.state.provenance.as_mut() {
+            provenance.unwatch(start, end);
+        }
+    }
+
+    /** Writes to start..end since it was watched, oldest first */
+    pub fn write_history(&self, start: u16, end: u16) -> Vec<MemoryWrite> {
+        self.provenance().map(|provenance| provenance.history_of(start, end)).unwrap_or_default()
+    }
+
+    /**
+     * Replaces the cheats: the pokes are written now, the freezes at the start of every frame
+     */
+    pub fn set_cheats(&mut self, cheats: CheatList) {
+        self.cheats = CheatList::new();
+        for cheat in cheats.cheats {
+            self.add_cheat(cheat);
+        }
+    }
+
+    pub fn cheats(&self) -> &CheatList {
+        &self.cheats
+    }
+
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        if cheat.kind == CheatKind::Poke {
+            self.poke(cheat.addr, cheat.value);
+        }
+        self.cheats.cheats.push(cheat);
+    }
+
+    /** Drops the cheats at addr; their bytes keep the last value written */
+    pub fn remove_cheats(&mut self, addr: u16) {
+        self.cheats.cheats.retain(|cheat| cheat.addr != addr);
+    }
+
+    /** Writes a byte, wrapping at the end of memory; coverage and provenance see it as a cheat's write */
+    pub fn poke(&mut self, addr: u16, value: MemPrimitive) {
+        let len = self.state.mem.len();
+        self.state.write_mem_by_cheat(usize::from(addr) % len, value);
+    }
+
+    fn apply_freezes(&mut self) {
+        let freezes: Vec<(u16, u8)> = self.cheats.freezes().map(|cheat| (cheat.addr, cheat.value)).collect();
+        for (addr, value) in freezes {
+            self.poke(addr, value);
+        }
+    }
+
+    /**
+     * Seeds RND (Cxkk); the same seed replays the same numbers
+     */
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.state.rng_seed = seed;
+    }
+
+    fn cycle(&mut self, observer: Option<Observer>) -> StepResult {
+        self.apply_freezes();
+        if !self.state.halted.0 {
+            self.run_steps(observer)?;
+        }
+        self.update_sound();
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.end_frame();
+        }
+        if let Some(provenance) = self.state.provenance.as_mut() {
+            provenance.end_frame();
+        }
+        self.events.push(EmulatorEvent::FrameCompleted);
+        Ok(())
+    }
+
+    fn run_steps(&mut self, mut observer: Option<Observer>) -> StepResult {
+        for _ in 0..self.steps_per_frame {
+            if self.hit_breakpoint() {
+                return Ok(());
+            }
+            let at = observer.is_some().then(|| (self.state.pci() as u16, self.state.fetch()));
+            self.step_instruction()?;
+            if let (Some(observer), Some((pc, opcode))) = (observer.as_mut(), at) {
+                let writes = self.state.write_log.as_mut().map(core::mem::take).unwrap_or_default();
+                observer(self, &StepTrace { pc, opcode, writes });
+            }
+        }
+        self.state.update_timers();
+        Ok(())
+    }
+
+    /**
+     * Runs one instruction outside of the frame loop: no breakpoints, no timers.
+     * Draws are presented right away
+     */
+    pub fn step_instruction(&mut self) -> StepResult {
+        let was_waiting_kb = self.state.waiting_kb.0;
+        if let Some(sanitizer) = self.sanitizer.as_mut() {
+            for report in sanitizer.check(&self.state) {
+                if report.finding.is_fatal() || sanitizer.options().stop_on_finding {
+                    return Err(StepError::Sanitizer(report));
+                }
+                self.events.push(EmulatorEvent::Sanitizer(report));
+            }
+        }
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(self.state.pci() as u16, self.state.fetch());
+        }
+        CPU::step(&mut self.state)?;
+        if !was_waiting_kb && self.state.waiting_kb.0 {
+            self.events.push(EmulatorEvent::WaitingForKey);
+        }
+        if self.state.repaint.0 {
+            self.present();
+            self.state.repaint.0 = false;
+        }
+        Ok(())
+    }
+
+    fn update_sound(&mut self) {
+        let sound_on = self.state.st.0 > 0;
+        if sound_on != self.sound_on {
+            self.sound_on = sound_on;
+            if let Some(audio) = self.audio.as_mut() {
+                audio.set_tone(sound_on);
+            }
+            self.events.push(if sound_on { EmulatorEvent::SoundOn } else { EmulatorEvent::SoundOff });
+        }
+    }
+
+    fn present(&mut self) {
+        let frame = self.state.framebuffer.take_frame();
+        self.screen.present(&frame);
+        for presenter in self.presenters.iter_mut() {
+            presenter.present(&frame);
+        }
+    }
+
+    // runs a single instruction; doesn't need a screen
+    pub(crate) fn step(state: &mut CPUState) -> StepResult {
         let opcode = state.fetch();
         let op = decode(opcode)?;
-        // TODO result type, error type
-        CPU::execute(state, screen_draw, op);
-        if state.repaint.0 {
-            screen_draw.repaint();
-            state.repaint.0 = false;
+        let pc = state.pci();
+        if let Some(coverage) = state.coverage.as_mut() {
+            coverage.record_opcode(pc);
         }
+        // TODO result type, error type
+        CPU::execute(state, op);
         StepResult::Ok(())
     }
 
-    fn execute(state: &mut CPUState, screen_draw: &mut dyn Screen, op: impl Fn(&mut CPUState, &mut dyn Screen)) {
-        op(state, screen_draw);
+    fn execute(state: &mut CPUState, op: impl Fn(&mut CPUState)) {
+        op(state);
     }
 
+    // PC key codes, see keyboard::pc_key_to_chip8 and set_keymap
     pub fn key_down(&mut self, kbk: usize) {
-        
-        let k = PC_KEY_MAP.get(&kbk);
-        if k.is_none() {
-            return;
+        if let Some(k) = self.active_keymap().to_chip8(kbk) {
+            self.press_key(k);
+        }
+    }
+
+    pub fn key_up(&mut self, k: usize) {
+        if let Some(k) = self.active_keymap().to_chip8(k) {
+            self.release_key(k);
         }
-        let k = k.unwrap();
-        self.state.keyboard.key_down(k);
+    }
+
+    /**
+     * Keypad key 0x0..=0xF; releases a pending Fx0A with that key
+     */
+    pub fn press_key(&mut self, k: u8) {
+        let k = k & 0xF;
+        self.state.keyboard.key_down(&k);
         // todo or keyup?
         if self.state.waiting_kb.0 {
             let x = self.state.waiting_kb_x.as_ref().expect("waiting for kb but no X");
-            self.state.v[x.0] = V(b'6');
+            self.state.v[x.0] = V(k);
             self.state.inc_pc_2();
             self.state.halted.0 = false;
             self.state.waiting_kb.0 = false;
             self.state.waiting_kb_x = None;
-
         }
     }
 
-    pub fn key_up(&mut self, k: usize) {
-        let k = PC_KEY_MAP.get(&k);
-        if k.is_none() {
-            return;
-        }
-        let k = k.unwrap();
-        self.state.keyboard.key_up(k);
+    pub fn release_key(&mut self, k: u8) {
+        self.state.keyboard.key_up(&(k & 0xF));
     }
 
 }
 
-type StepResult = Result<()>;
\ No newline at end of file
+/**
+* An instruction run by CPU::run_frame_observed
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepTrace {
+    pub pc: u16,
+    pub opcode: u16,
+    // (address, value) in the order written
+    pub writes: Vec<(u16, u8)>,
+}
+
+type Observer<'a> = &'a mut dyn FnMut(&mut CPU, &StepTrace);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepError {
+    Decode(DecodeError),
+    Sanitizer(Report),
+}
+
+impl From<DecodeError> for StepError {
+    fn from(e: DecodeError) -> Self {
+        StepError::Decode(e)
+    }
+}
+
+impl fmt::Display for StepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepError::Decode(e) => e.fmt(f),
+            StepError::Sanitizer(report) => report.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for StepError {}
+
+type StepResult = core::result::Result<(), StepError>;
\ No newline at end of file
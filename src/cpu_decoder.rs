@@ -1,11 +1,12 @@
-use std::fmt;
+use alloc::boxed::Box;
+use core::fmt;
 
 use ux::u12;
 
 use crate::cpu_instructions::*;
 
-pub type DecodeResult = std::result::Result<Box<Instruction>, DecodeError>;
-#[derive(Debug, Clone)]
+pub type DecodeResult = core::result::Result<Box<Instruction>, DecodeError>;
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DecodeError(u16);
 
 impl fmt::Display for DecodeError {
@@ -14,6 +15,7 @@ impl fmt::Display for DecodeError {
     }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for DecodeError {
 
 }
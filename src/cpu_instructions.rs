@@ -1,11 +1,12 @@
-use std::ops::Rem;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::Rem;
 use ux::{u12, u4};
 
-use crate::screen::{Screen, ScreenDraw};
 use crate::cpu::{CPUState, I, V};
 
 
-pub type Instruction = dyn Fn(&mut CPUState, &mut dyn Screen);
+pub type Instruction = dyn Fn(&mut CPUState);
 
 #[derive(Clone, Copy, Debug)]
 pub struct X(pub usize);
@@ -32,6 +33,7 @@ impl Rem for Y {
 #[derive(Clone, Debug)]
 pub struct KK(pub u8);
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Debug)]
 pub struct NNN(pub u12);
 
@@ -45,7 +47,7 @@ pub struct N(pub u16);
 * It is ignored by modern interpreters.
 */
 pub fn sys() -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.inc_pc_2();
     })
 }
@@ -64,8 +66,8 @@ fn test_sys() {
  * Clears the display.
  */
 pub fn cls() -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, screen_draw: &mut dyn Screen| {
-        screen_draw.clear();
+    Box::new(move |state: &mut CPUState| {
+        state.framebuffer.clear();
         state.repaint.0 = true;
         state.inc_pc_2();
     })
@@ -76,11 +78,13 @@ fn test_cls() {
     use super::test_utils::*;
     test_cycle(TestCycleParams {
         op_code: 0x00E0,
-        expectations: |s| {
-            let screen_draw = s.screen_draw;
-            screen_draw.expect_clear().return_const(());
-            screen_draw.expect_repaint().return_const(());
-        },
+        pre_fn: Some(|cpu, _args| {
+            cpu.state.framebuffer.toggle_pixel(X(1), Y(2));
+        }),
+        post_fn: Some(|state, _s, _args| {
+            assert!(!state.framebuffer.get_pixel(X(1), Y(2)));
+            assert!(state.repaint.0);
+        }),
         ..Default::default()
     });
 }
@@ -90,7 +94,7 @@ fn test_cls() {
  * Set Vx = Vy
  */
 pub fn ld_vx_vy(x: X, y: Y) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.v[x.0].0 = state.v[y.0].0;
         state.inc_pc_2();
     })
@@ -100,7 +104,7 @@ pub fn ld_vx_vy(x: X, y: Y) -> Box<Instruction> {
 fn test_ld_vx_vy() {
     use super::test_utils::*;
     test_cycle(TestCycleParams {
-        op_code: 0x8000 | 0 << 8 | 1 << 4,
+        op_code: 0x8000 | 1 << 4,
         op_args: Option::Some(TestCycleOpArgs {
             x: X(0),
             y: Y(1),
@@ -113,9 +117,9 @@ fn test_ld_vx_vy() {
             cpu.state.v[args.x.0] = args.x_val;
             cpu.state.v[args.y.0] = args.y_val;
         }),
-        post_fn: Some(|state, s, args| {
+        post_fn: Some(|state, _s, args| {
             let args = args.unwrap();
-            let state_vy = state.v[args.y.0].0.clone();
+            let state_vy = state.v[args.y.0].0;
             assert_eq!(state.v[args.x.0].0, state_vy);
             assert_eq!(state_vy, args.y_val.0);
         }),
@@ -128,7 +132,7 @@ fn test_ld_vx_vy() {
  * Set Vx = kk
  */
 pub fn ld_vx_kk(x: X, kk: KK) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.v[x.0].0 = kk.0;
         state.inc_pc_2();
     })
@@ -157,7 +161,7 @@ fn test_ld_vx_kk() {
  * Set Vx = Vx OR Vy.
  */
 pub fn or_vx_vy(x: X, y: Y) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.v[x.0].0 |= state.v[y.0].0;
         state.inc_pc_2();
     })
@@ -195,7 +199,7 @@ fn test_or_vx_vy() {
  * Set Vx = Vx AND Vy.
  */
 pub fn and_vx_vy(x: X, y: Y) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.v[x.0].0 &= state.v[y.0].0;
         state.inc_pc_2();
     })
@@ -233,7 +237,7 @@ fn test_and_vx_vy() {
  * Set Vx = Vx XOR Vy.
  */
 pub fn xor_vx_vy(x: X, y: Y) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.v[x.0].0 ^= state.v[y.0].0;
         state.inc_pc_2();
     })
@@ -248,7 +252,7 @@ fn test_xor_vx_vy() {
 fn test_xor_vx_vy_inner(x: u16, y: u16) {
     use super::test_utils::*;
     test_cycle(TestCycleParams {
-        op_code: 0x8003 | x.clone() << 8 | y.clone() << 4,
+        op_code: 0x8003 | x << 8 | y << 4,
         op_args: Option::Some(TestCycleOpArgs {
             x: X(x as usize),
             y: Y(y as usize),
@@ -276,7 +280,7 @@ fn test_xor_vx_vy_inner(x: u16, y: u16) {
  * Set Vx = Vx + Vy, set VF = carry.
  */
 pub fn add_vx_vy(x: X, y: Y) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         let (sum, is_carry) = state.v[x.0].0.overflowing_add(state.v[y.0].0);
         let carry: u8 = match is_carry {
             true => 1,
@@ -298,7 +302,7 @@ fn test_add_vx_vy() {
 fn test_add_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, carry: bool, result: u16) {
     use super::test_utils::*;
     test_cycle(TestCycleParams {
-        op_code: 0x8004 | x.clone() << 8 | y.clone() << 4,
+        op_code: 0x8004 | x << 8 | y << 4,
         op_args: Option::Some(TestCycleOpArgs {
             x: X(x as usize),
             y: Y(y as usize),
@@ -331,7 +335,7 @@ fn test_add_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, carry: bool, resul
  * Set Vx = Vx - Vy, set VF = NOT borrow.
  */
 pub fn sub_vx_vy(x: X, y: Y) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         let (sum, is_carry) = state.v[x.0].0.overflowing_sub(state.v[y.0].0);
         let not_borrow: u8 = match !is_carry {
             true => 1,
@@ -359,7 +363,7 @@ fn test_sub_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, no_borrow: bool, r
 fn test_subx_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, no_borrow: bool, result: u16, op_code: u16) {
     use super::test_utils::*;
     test_cycle(TestCycleParams {
-        op_code: op_code | x.clone() << 8 | y.clone() << 4,
+        op_code: op_code | x << 8 | y << 4,
         op_args: Option::Some(TestCycleOpArgs {
             x: X(x as usize),
             y: Y(y as usize),
@@ -392,7 +396,7 @@ fn test_subx_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, no_borrow: bool,
  * Set Vx = Vy - Vx, set VF = NOT borrow.
  */
 pub fn subn_vx_vy(x: X, y: Y) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         let (sum, is_carry) = state.v[y.0].0.overflowing_sub(state.v[x.0].0);
         let not_borrow: u8 = match !is_carry {
             true => 1,
@@ -422,7 +426,7 @@ fn test_subn_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, no_borrow: bool,
  * If the least-significant bit of shifted value is 1, then VF is set to 1, otherwise 0.
  */
 pub fn shr_vx_vy(x: X, y: Y) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         let y = match state.quirks.shift {
             true => x.0,
             false => y.0,
@@ -446,6 +450,7 @@ fn test_shr_vx_vy_quirks_enabled() {
 }
 
 #[cfg(test)]
+#[allow(clippy::too_many_arguments)]
 fn test_shr_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, exp_x: u8, exp_y: u8, reg_f: u8, quirks_enabled: bool) {
     test_shx_vx_vy_inner(x, y, x_val, y_val, exp_x, exp_y, reg_f, quirks_enabled, 0x8006);
 }
@@ -457,7 +462,7 @@ fn test_shr_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, exp_x: u8, exp_y:
  * If the most-significant bit of shifted value is 1, then VF is set to 1, otherwise to 0.
  */
 pub fn shl_vx_vy(x: X, y: Y) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         let y = match state.quirks.shift {
             true => x.0,
             false => y.0,
@@ -485,15 +490,17 @@ fn test_shl_vx_vy_quirks_enabled() {
 }
 
 #[cfg(test)]
+#[allow(clippy::too_many_arguments)]
 fn test_shl_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, exp_x: u8, exp_y: u8, reg_f: u8, quirks_enabled: bool) {
     test_shx_vx_vy_inner(x, y, x_val, y_val, exp_x, exp_y, reg_f, quirks_enabled, 0x800E);
 }
 
 #[cfg(test)]
+#[allow(clippy::too_many_arguments)]
 fn test_shx_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, exp_x: u8, exp_y: u8, reg_f: u8, quirks_enabled: bool, op_code: u16) {
     use super::test_utils::*;
     test_cycle(TestCycleParams {
-        op_code: op_code | x.clone() << 8 | y.clone() << 4,
+        op_code: op_code | x << 8 | y << 4,
         op_args: Option::Some(TestCycleOpArgs {
             x: X(x as usize),
             y: Y(y as usize),
@@ -526,7 +533,7 @@ fn test_shx_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, exp_x: u8, exp_y:
  * Set Vx = Vx + kk.
  */
 pub fn add_vx_kk(x: X, kk: KK) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         // game could overflow it
         state.v[x.0].0 = state.v[x.0].0.wrapping_add(kk.0);
         state.inc_pc_2();
@@ -562,7 +569,7 @@ fn test_add_vx_kk() {
  * Skip next instruction if Vx != Vy.
  */
 pub fn sne_vx_vy(x: X, y: Y) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         if state.v[x.0].0 != state.v[y.0].0 {
             state.inc_pc_2();
         }
@@ -612,7 +619,7 @@ fn test_sne_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, pc_offset: u16) {
  * Skip next instruction if Vx != kk.
  */
 pub fn sne_vx_kk(x: X, kk: KK) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         if state.v[x.0].0 != kk.0 {
             state.inc_pc_2();
         }
@@ -663,7 +670,7 @@ fn test_snx_vx_kk_inner(x: u16, x_val: u8, byte: u8, pc_offset: u16, op_code: u1
  * Skip next instruction if Vx = kk.
  */
 pub fn se_vx_kk(x: X, kk: KK) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         if state.v[x.0].0 == kk.0 {
             state.inc_pc_2();
         }
@@ -687,7 +694,7 @@ fn test_se_vx_kk_inner(x: u16, x_val: u8, byte: u8, pc_offset: u16) {
  * Skip next instruction if Vx = Vy.
  */
 pub fn se_vx_vy(x: X, y: Y) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         if state.v[x.0].0 == state.v[y.0].0 {
             state.inc_pc_2();
         }
@@ -706,7 +713,7 @@ fn test_se_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, pc_offset: u16) {
     use super::test_utils::*;
     test_cycle(TestCycleParams {
         expect_inc: false,
-        op_code: 0x5000 | x.clone() << 8 | y.clone() << 4,
+        op_code: 0x5000 | x << 8 | y << 4,
         op_args: Option::Some(TestCycleOpArgs {
             x: X(x as usize),
             y: Y(y as usize),
@@ -736,7 +743,7 @@ fn test_se_vx_vy_inner(x: u16, y: u16, x_val: u8, y_val: u8, pc_offset: u16) {
  * Jump to location nnn.
  */
 pub fn jp_nnn(nnn: NNN) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.pc.0 = nnn.0;
     })
 }
@@ -757,7 +764,7 @@ fn test_jp_nnn_inner(addr: u16) {
             addr: PC(u12::new(addr)),
             ..Default::default()
         }),
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, args| {
             let args = args.unwrap();
             assert_eq!(state.pc.0, args.addr.0);
         }),
@@ -770,7 +777,7 @@ fn test_jp_nnn_inner(addr: u16) {
  * Return from a subroutine.
  */
 pub fn ret() -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.pc.0 = state.stack[(u8::from(state.sp.0) - 1) as usize];
         state.sp.0 = state.sp.0 - u4::new(1);
         state.inc_pc_2();
@@ -791,19 +798,19 @@ fn test_ret_inner(stack: Vec<u16>, sp: u8, expected_pc: u16) {
         op_code: 0x00EE,
         op_args: Option::Some(TestCycleOpArgs {
             addr: PC(u12::new(expected_pc)),
-            stack: stack.iter().map(|x| u12::new(x.clone())).collect(),
+            stack: stack.iter().map(|x| u12::new(*x)).collect(),
             sp: SP(u4::new(sp)),
             ..Default::default()
         }),
         pre_fn: Some(|cpu, args| {
             let args = args.unwrap();
             for (i, x) in args.stack.iter().enumerate() {
-                cpu.state.stack[i] = x.clone();
+                cpu.state.stack[i] = *x;
             }
             cpu.state.sp = args.sp;
 
         }),
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, args| {
             let args = args.unwrap();
             assert_eq!(state.pc.0, args.addr.0);
             assert_eq!(state.sp.0, args.sp.0 - u4::new(1));
@@ -817,7 +824,7 @@ fn test_ret_inner(stack: Vec<u16>, sp: u8, expected_pc: u16) {
  * Call subroutine at nnn.
  */
 pub fn call_nnn(nnn: NNN) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.stack[u8::from(state.sp.0) as usize] = state.pc.0;
         state.sp.0 = (state.sp.0 + u4::new(1)) & u4::new((state.stack.len() - 1) as u8);
         state.pc.0 = nnn.0;
@@ -856,7 +863,7 @@ fn test_call_nnn_inner(addr: u16) {
  * Set I = nnn.
  */
 pub fn ld_i_nnn(nnn: NNN) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.i.0 = nnn.0;
         state.inc_pc_2();
     })
@@ -867,7 +874,7 @@ fn test_ld_i_nnn() {
     use super::test_utils::*;
     test_cycle(TestCycleParams {
         op_code: 0xA000 | 0xCCC,
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, _args| {
             assert_eq!(state.i.0, u12::new(0xCCC));
         }),
         ..Default::default()
@@ -879,7 +886,7 @@ fn test_ld_i_nnn() {
  * Jump to location nnn + V0.
  */
 pub fn jp_v0_nnn(nnn: NNN) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         // not sure if wrapping add but https://github.com/mir3z/chip8-emu/blob/master/test/spec/is.spec.js would pass in that case
         state.pc.0 = nnn.0.wrapping_add(u12::new(state.v[0].0 as u16));
     })
@@ -907,7 +914,7 @@ fn test_jp_v0_nnn_inner(v0: u8, addr: u16, expected_pc: u16) {
            let args = args.unwrap();
             cpu.state.v[0] = args.v0;
         }),
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, args| {
             let args = args.unwrap();
             assert_eq!(state.pc.0, args.expected_pc.0);
             assert_eq!(state.v[0].0, args.v0.0);
@@ -921,7 +928,7 @@ fn test_jp_v0_nnn_inner(v0: u8, addr: u16, expected_pc: u16) {
  * Set Vx = random byte AND kk.
  */
 pub fn rnd_vx_kk(x: X, kk: KK) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         let r: u8 = state.run_rng(); // 0..255
         state.v[x.0].0 = r & kk.0;
         state.inc_pc_2();
@@ -942,7 +949,7 @@ fn test_rnd_vx_kk_inner(x: u16) {
             x: X(x as usize),
             ..Default::default()
         }),
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, args| {
             let args = args.unwrap();
             assert!([0u8, 1u8].contains(&state.v[args.x.0].0));
         }),
@@ -955,10 +962,11 @@ fn test_rnd_vx_kk_inner(x: u16) {
  * Store BCD representation of Vx in memory locations I, I+1, and I+2.
  */
 pub fn ld_b_vx(x: X) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
-        state.mem[u16::from(state.i.0) as usize].0 = state.v[x.0].0 / 100;
-        state.mem[u16::from(state.i.0 + u12::new(1)) as usize].0 = state.v[x.0].0 % 100 / 10;
-        state.mem[u16::from(state.i.0 + u12::new(2)) as usize].0 = state.v[x.0].0 % 10;
+    Box::new(move |state: &mut CPUState| {
+        let vx = state.v[x.0].0;
+        state.write_mem(u16::from(state.i.0) as usize, vx / 100);
+        state.write_mem(u16::from(state.i.0 + u12::new(1)) as usize, vx % 100 / 10);
+        state.write_mem(u16::from(state.i.0 + u12::new(2)) as usize, vx % 10);
         state.inc_pc_2();
     })
 }
@@ -977,7 +985,7 @@ fn test_ld_b_vx_inner(x: u16, x_val: u8, i: u16, digits: Vec<u16>) {
             x: X(x as usize),
             x_val: V(x_val),
             i: I(u12::new(i)),
-            digits: digits.iter().map(|d| d.clone() as u8).collect(),
+            digits: digits.iter().map(|d| *d as u8).collect(),
             ..Default::default()
         }),
         pre_fn: Some(|cpu, args| {
@@ -985,7 +993,7 @@ fn test_ld_b_vx_inner(x: u16, x_val: u8, i: u16, digits: Vec<u16>) {
             cpu.state.i = args.i;
             cpu.state.v[args.x.0] = args.x_val;
         }),
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, args| {
             let args = args.unwrap();
             assert_eq!(state.i.0, args.i.0);
             let i = u16::from(state.i.clone().0) as usize;
@@ -1002,7 +1010,7 @@ fn test_ld_b_vx_inner(x: u16, x_val: u8, i: u16, digits: Vec<u16>) {
  * Set Vx = delay timer value.
  */
 pub fn ld_vx_dt(x: X) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.v[x.0].0 = state.dt.0;
         state.inc_pc_2();
     })
@@ -1028,7 +1036,7 @@ fn test_ld_vx_dt_inner(x: u16, dt: u8) {
             let args = args.unwrap();
             cpu.state.dt = args.dt;
         }),
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, args| {
             let args = args.unwrap();
             assert_eq!(state.v[args.x.0].0, args.dt.0);
             assert_eq!(state.dt.0, args.dt.0 - 1);
@@ -1042,7 +1050,7 @@ fn test_ld_vx_dt_inner(x: u16, dt: u8) {
  * Set delay timer = Vx.
  */
 pub fn ld_dt_vx(x: X) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.dt.0 = state.v[x.0].0;
         state.inc_pc_2();
     })
@@ -1065,9 +1073,9 @@ fn test_ld_dt_vx_inner(x: u16, x_val: u8) {
         }),
         pre_fn: Some(|cpu, args| {
             let args = args.unwrap();
-            cpu.state.v[args.x.0 as usize] = args.x_val;
+            cpu.state.v[args.x.0] = args.x_val;
         }),
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, args| {
             let args = args.unwrap();
             assert_eq!(state.v[args.x.0].0, args.x_val.0);
         }),
@@ -1080,7 +1088,7 @@ fn test_ld_dt_vx_inner(x: u16, x_val: u8) {
  * Set sound timer = Vx.
  */
 pub fn ld_st_vx(x: X) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.st.0 = state.v[x.0].0;
         state.inc_pc_2();
     })
@@ -1103,9 +1111,9 @@ fn test_ld_st_vx_inner(x: u16, x_val: u8) {
         }),
         pre_fn: Some(|cpu, args| {
             let args = args.unwrap();
-            cpu.state.v[args.x.0 as usize] = args.x_val;
+            cpu.state.v[args.x.0] = args.x_val;
         }),
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, args| {
             let args = args.unwrap();
             assert_eq!(state.v[args.x.0].0, args.x_val.0);
             assert_eq!(state.st.0, args.x_val.0 - 1);
@@ -1119,7 +1127,7 @@ fn test_ld_st_vx_inner(x: u16, x_val: u8) {
  * Set I = I + Vx.
  */
 pub fn add_i_vx(x: X) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         // TODO wrapping?
         state.i.0 = state.i.0.wrapping_add(u12::new(state.v[x.0].0 as u16));
         state.inc_pc_2();
@@ -1148,9 +1156,9 @@ fn test_add_i_vx_inner(x: u16, x_val: u8, i: u16, result: u16) {
         pre_fn: Some(|cpu, args| {
             let args = args.unwrap();
             cpu.state.i.0 = args.i.0;
-            cpu.state.v[args.x.0 as usize] = args.x_val;
+            cpu.state.v[args.x.0] = args.x_val;
         }),
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, args| {
             let args = args.unwrap();
             assert_eq!(state.i.0, u12::new(args.result));
             assert_eq!(state.v[args.x.0].0, args.x_val.0);
@@ -1165,9 +1173,9 @@ fn test_add_i_vx_inner(x: u16, x_val: u8, i: u16, result: u16) {
  * The value of the I register will be incremented by X + 1, if load/store quirks are disabled.
  */
 pub fn ld_i_vx(x: X) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         for i in 0..=x.0 { // inclusive
-            state.mem[u16::from(state.i.0) as usize + i].0 = state.v[i].0;
+            state.write_mem(u16::from(state.i.0) as usize + i, state.v[i].0);
         }
         if !state.quirks.load_store {
             state.i.0 = state.i.0 + u12::new(x.0 as u16) + u12::new(1);
@@ -1197,7 +1205,7 @@ fn test_ld_i_vx_inner(x: u16, i_val: u16, i_expected: u16, regs: Vec<u8>, quirks
             x: X(x as usize),
             i_val: u12::new(i_val),
             i_expected: I(u12::new(i_expected)),
-            regs: regs.iter().map(|r| V(r.clone())).collect(),
+            regs: regs.iter().map(|r| V(*r)).collect(),
             quirks_enabled,
             ..Default::default()
         }),
@@ -1206,13 +1214,13 @@ fn test_ld_i_vx_inner(x: u16, i_val: u16, i_expected: u16, regs: Vec<u8>, quirks
             cpu.state.i.0 = args.i_val;
             cpu.state.quirks.load_store = args.quirks_enabled;
             for (i, x) in args.regs.iter().enumerate() {
-                cpu.state.v[i] = x.clone();
+                cpu.state.v[i] = *x;
             }
         }),
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, args| {
             let args = args.unwrap();
             assert_eq!(state.i.0, args.i_expected.0);
-            for (i, x) in (0..=args.x.0).enumerate() { // inclusive
+            for (i, _x) in (0..=args.x.0).enumerate() { // inclusive
                 assert_eq!(state.mem[i + u16::from(args.i_val) as usize].0, state.v[i].0);
             }
         }),
@@ -1226,9 +1234,9 @@ fn test_ld_i_vx_inner(x: u16, i_val: u16, i_expected: u16, regs: Vec<u8>, quirks
  * The value of the I register will be incremented by X + 1, if load/store quirks are disabled.
  */
 pub fn ld_vx_i(x: X) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         for i in 0..=x.0 { // inclusive
-            state.v[i].0 = state.mem[u16::from(state.i.0) as usize + i].0;
+            state.v[i].0 = state.read_mem(u16::from(state.i.0) as usize + i);
         }
         if !state.quirks.load_store {
             state.i.0 = state.i.0 + u12::new(x.0 as u16) + u12::new(1);
@@ -1258,7 +1266,7 @@ fn test_ld_vx_i_inner(x: u16, i_val: u16, i_expected: u16, mem: Vec<u8>, quirks_
             x: X(x as usize),
             i_val: u12::new(i_val),
             i_expected: I(u12::new(i_expected)),
-            mem: mem.iter().map(|r| MemValue(r.clone())).collect(),
+            mem: mem.iter().map(|r| MemValue(*r)).collect(),
             quirks_enabled,
             ..Default::default()
         }),
@@ -1267,13 +1275,13 @@ fn test_ld_vx_i_inner(x: u16, i_val: u16, i_expected: u16, mem: Vec<u8>, quirks_
             cpu.state.i.0 = args.i_val;
             cpu.state.quirks.load_store = args.quirks_enabled;
             for (i, x) in args.mem.iter().enumerate() {
-                cpu.state.mem[u16::from(cpu.state.i.0) as usize + i] = x.clone();
+                cpu.state.mem[u16::from(cpu.state.i.0) as usize + i] = *x;
             }
         }),
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, args| {
             let args = args.unwrap();
             assert_eq!(state.i.0, args.i_expected.0);
-            for (i, x) in (0..args.x.0).enumerate() { // inclusive
+            for (i, _x) in (0..args.x.0).enumerate() { // inclusive
                 assert_eq!(state.mem[i + u16::from(args.i_val) as usize].0, state.v[i].0);
             }
         }),
@@ -1286,7 +1294,7 @@ fn test_ld_vx_i_inner(x: u16, i_val: u16, i_expected: u16, mem: Vec<u8>, quirks_
  * Wait for a key press, store the value of the key in Vx.
  */
 pub fn ld_vx_k(x: X) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.halted.0 = true;
         state.waiting_kb.0 = true;
         state.waiting_kb_x = Some(x);
@@ -1296,19 +1304,20 @@ pub fn ld_vx_k(x: X) -> Box<Instruction> {
 #[test]
 fn test_ld_vx_k() {
     let x = 0x5;
-    let key = '6';
     use super::test_utils::*;
+    // the key is stored by CPU::press_key; until then the CPU halts on the instruction
     test_cycle(TestCycleParams {
+        expect_inc: false,
         op_code: 0xF00A | x << 8,
         op_args: Option::Some(TestCycleOpArgs {
             x: X(x as usize),
-            key,
             ..Default::default()
         }),
-        post_fn: Some(|state, scope, args| {
+        post_fn: Some(|state, _scope, args| {
             let args = args.unwrap();
-            assert_eq!(state.v[args.x.0].0, args.key as u8);
-            assert!(!state.halted.0);
+            assert!(state.halted.0);
+            assert!(state.waiting_kb.0);
+            assert_eq!(state.waiting_kb_x.map(|x| x.0), Some(args.x.0));
         }),
         ..Default::default()
     });
@@ -1319,7 +1328,7 @@ fn test_ld_vx_k() {
  * Skip next instruction if key with the value of Vx is pressed.
  */
 pub fn skp_vx(_x: X) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         if state.keyboard.is_key_pressed(&state.v[_x.0].0) {
             state.inc_pc_2();
         }
@@ -1351,16 +1360,16 @@ fn test_skp_vx_inner(x: u16, x_val: u8, pressed: bool, key: char, should_skip: b
         pre_fn: Some(move |cpu, args| {
             let args = args.unwrap();
             cpu.state.v[args.x.0] = args.x_val;
-            if (args.pressed) {
-                cpu.state.keyboard.key_down(&args.key as &u8)
+            if args.pressed {
+                cpu.state.keyboard.key_down(&(args.key as u8))
             }
         }),
         post_fn: Some(|state, scope, args| {
             let args = args.unwrap();
-            if (args.should_skip) {
-                assert_eq!(state.pc.0, scope.old_cpu_state.pc.0 + u12::new(4 as u16));
+            if args.should_skip {
+                assert_eq!(state.pc.0, scope.old_cpu_state.pc.0 + u12::new(4_u16));
             } else {
-                assert_eq!(state.pc.0, scope.old_cpu_state.pc.0 + u12::new(2 as u16));
+                assert_eq!(state.pc.0, scope.old_cpu_state.pc.0 + u12::new(2_u16));
             }
         }),
         ..Default::default()
@@ -1372,7 +1381,7 @@ fn test_skp_vx_inner(x: u16, x_val: u8, pressed: bool, key: char, should_skip: b
  * Skip next instruction if key with the value of Vx is not pressed.
  */
 pub fn sknp_vx(_x: X) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         if !state.keyboard.is_key_pressed(&state.v[_x.0].0) {
             state.inc_pc_2();
         }
@@ -1405,15 +1414,15 @@ fn test_sknp_vx_inner(x: u16, x_val: u8, pressed: bool, key: char, should_skip:
             let args = args.unwrap();
             cpu.state.v[args.x.0] = args.x_val;
             if args.pressed {
-                cpu.state.keyboard.key_down(&args.key as &u8)
+                cpu.state.keyboard.key_down(&(args.key as u8))
             }
         }),
         post_fn: Some(|state, scope, args| {
             let args = args.unwrap();
             if args.should_skip {
-                assert_eq!(state.pc.0, scope.old_cpu_state.pc.0 + u12::new(4 as u16));
+                assert_eq!(state.pc.0, scope.old_cpu_state.pc.0 + u12::new(4_u16));
             } else {
-                assert_eq!(state.pc.0, scope.old_cpu_state.pc.0 + u12::new(2 as u16));
+                assert_eq!(state.pc.0, scope.old_cpu_state.pc.0 + u12::new(2_u16));
             }
         }),
         ..Default::default()
@@ -1425,14 +1434,15 @@ fn test_sknp_vx_inner(x: u16, x_val: u8, pressed: bool, key: char, should_skip:
  * Set I = location of sprite for digit Vx.
  */
 pub fn ld_f_vx(x: X) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, _screen_draw: &mut dyn Screen| {
+    Box::new(move |state: &mut CPUState| {
         state.i = I(u12::new(state.v[x.0].0 as u16 * 5));
         state.inc_pc_2();
     })
 }
 
 #[cfg(test)]
-fn test_ld_f_vx_inner(x: u16, x_val: u8, pressed: bool, should_skip: bool) {
+#[allow(dead_code)]
+fn test_ld_f_vx_inner(x: u16, x_val: u8, _pressed: bool, _should_skip: bool) {
     use super::test_utils::*;
     test_cycle(TestCycleParams {
         expect_inc: false, // test this explicitly
@@ -1460,7 +1470,7 @@ fn test_ld_f_vx_inner(x: u16, x_val: u8, pressed: bool, should_skip: bool) {
     });
 }
 
-/**
+/*
 test_LD_F_Vx: function (cpu, params) {
             testCycle(cpu, {
                 opCode: 0xF029 | (params.x << 8),
@@ -1482,22 +1492,39 @@ test_LD_F_Vx: function (cpu, params) {
  * Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
  */
 pub fn drw_vx_vy_n(x: X, y: Y, n: N) -> Box<Instruction> {
-    Box::new(move |state: &mut CPUState, screen_draw: &mut dyn Screen| {
-        state.v[0xF] = V(0);
-        for hline in 0..n.0 {
-            let membyte = state.mem[u16::from(state.i.0 + u12::new(hline)) as usize];
-            for vline in 0..8 {
-                if (membyte.0 & (0x80 >> vline)) != 0 {
-                    let nx = X(u16::from(state.v[x.0].0) as usize + vline) % X(screen_draw.get_width());
-                    let ny = Y(u16::from(state.v[y.0].0) as usize + hline as usize) % Y(screen_draw.get_height());
-                    let coll = screen_draw.toggle_pixel(nx, ny);
-                    if coll.0 {
-                        state.v[0xF] = V(1);
-                    }
-                }
-            }
-        }
+    Box::new(move |state: &mut CPUState| {
+        let rows: Vec<u8> = (0..n.0).map(|hline| {
+            state.read_mem(u16::from(state.i.0 + u12::new(hline)) as usize)
+        }).collect();
+        let coll = state.framebuffer.draw_sprite(X(state.v[x.0].0 as usize), Y(state.v[y.0].0 as usize), &rows);
+        state.v[0xF] = V(coll.0 as u8);
         state.repaint.0 = true;
         state.inc_pc_2();
     })
 }
+
+#[test]
+fn test_drw_vx_vy_n() {
+    use super::test_utils::*;
+    use crate::cpu::MemValue;
+    test_cycle(TestCycleParams {
+        op_code: 0xD000 | 1 << 8 | 2 << 4 | 2,
+        pre_fn: Some(|cpu, _args| {
+            cpu.state.v[1] = V(62); // wraps around horizontally
+            cpu.state.v[2] = V(4);
+            cpu.state.i = I(u12::new(0x300));
+            cpu.state.mem[0x300] = MemValue(0b1010_0000);
+            cpu.state.mem[0x301] = MemValue(0b0100_0000);
+            cpu.state.framebuffer.toggle_pixel(X(63), Y(5));
+        }),
+        post_fn: Some(|state, _s, _args| {
+            assert!(state.framebuffer.get_pixel(X(62), Y(4)));
+            assert!(state.framebuffer.get_pixel(X(0), Y(4)));
+            assert!(!state.framebuffer.get_pixel(X(63), Y(4)));
+            assert!(!state.framebuffer.get_pixel(X(63), Y(5)));
+            assert_eq!(state.v[0xF].0, 1);
+            assert!(state.repaint.0);
+        }),
+        ..Default::default()
+    });
+}
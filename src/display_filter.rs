@@ -0,0 +1,196 @@
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::screen::{make_zero_screen_state, ScreenState, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const INTENSITY_MAX: u8 = 0xFF;
+
+/**
+* Post-processing applied between the screen state and the presenter, to hide XOR flicker
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayMode {
+    // every draw is shown as is
+    Off,
+    // lit pixels go to full intensity and fade out over decay_frames frames once unlit
+    Phosphor { decay_frames: u8 },
+    // average of the last two frames
+    Blend,
+    // the screen state is only shown as it was at vblank
+    DisplayWait,
+}
+
+pub type IntensityBuffer = [u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+pub struct DisplayFilter {
+    mode: DisplayMode,
+    intensity: IntensityBuffer,
+    previous: ScreenState,
+}
+
+fn lit(state: &ScreenState, i: usize) -> bool {
+    state.get(i % SCREEN_WIDTH, i / SCREEN_WIDTH)
+}
+
+fn full(on: bool) -> u8 {
+    if on { INTENSITY_MAX } else { 0 }
+}
+
+impl DisplayFilter {
+    pub fn new(mode: DisplayMode) -> Self {
+        Self {
+            mode,
+            intensity: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            previous: make_zero_screen_state(),
+        }
+    }
+    pub fn mode(&self) -> DisplayMode {
+        self.mode
+    }
+    /**
+     * Whether the presenter should show every draw, or wait for vblank
+     */
+    pub fn presents_on_draw(&self) -> bool {
+        self.mode == DisplayMode::Off
+    }
+    /**
+     * Intensity per pixel, row-major, 0 (background) to INTENSITY_MAX (foreground)
+     */
+    pub fn intensity(&self) -> &IntensityBuffer {
+        &self.intensity
+    }
+    pub fn intensity_at(&self, x: usize, y: usize) -> u8 {
+        self.intensity[y * SCREEN_WIDTH + x]
+    }
+    // called whenever the screen state changes
+    pub fn on_draw(&mut self, state: &ScreenState) {
+        match self.mode {
+            DisplayMode::Off => self.latch(state),
+            // sprites erased before vblank should still leave a trace
+            DisplayMode::Phosphor { .. } => {
+                for (i, intensity) in self.intensity.iter_mut().enumerate() {
+                    if lit(state, i) {
+                        *intensity = INTENSITY_MAX;
+                    }
+                }
+            }
+            DisplayMode::Blend | DisplayMode::DisplayWait => {}
+        }
+    }
+    // called once per frame
+    pub fn on_vblank(&mut self, state: &ScreenState) {
+        match self.mode {
+            DisplayMode::Off | DisplayMode::DisplayWait => self.latch(state),
+            DisplayMode::Phosphor { decay_frames } => {
+                let step = (INTENSITY_MAX as u16).div_ceil(decay_frames.max(1) as u16);
+                for (i, intensity) in self.intensity.iter_mut().enumerate() {
+                    *intensity = if lit(state, i) {
+                        INTENSITY_MAX
+                    } else {
+                        intensity.saturating_sub(step as u8)
+                    };
+                }
+            }
+            DisplayMode::Blend => {
+                for (i, intensity) in self.intensity.iter_mut().enumerate() {
+                    *intensity = ((full(lit(state, i)) as u16 + full(lit(&self.previous, i)) as u16) / 2) as u8;
+                }
+                self.previous = *state;
+            }
+        }
+    }
+    fn latch(&mut self, state: &ScreenState) {
+        for (i, intensity) in self.intensity.iter_mut().enumerate() {
+            *intensity = full(lit(state, i));
+        }
+    }
+}
+
+/**
+* Display mode settings, for the wasm API
+*/
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Copy, Debug)]
+pub struct DisplayOptions {
+    pub(crate) mode: DisplayMode,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl DisplayOptions {
+    pub fn off() -> DisplayOptions {
+        DisplayOptions { mode: DisplayMode::Off }
+    }
+    pub fn phosphor(decay_frames: u8) -> DisplayOptions {
+        DisplayOptions { mode: DisplayMode::Phosphor { decay_frames } }
+    }
+    pub fn blend() -> DisplayOptions {
+        DisplayOptions { mode: DisplayMode::Blend }
+    }
+    pub fn display_wait() -> DisplayOptions {
+        DisplayOptions { mode: DisplayMode::DisplayWait }
+    }
+}
+
+// not exported: DisplayMode has no JS representation
+impl DisplayOptions {
+    pub fn mode(&self) -> DisplayMode {
+        self.mode
+    }
+}
+
+#[cfg(test)]
+fn state_with(pixels: &[(usize, usize)]) -> ScreenState {
+    let mut state = make_zero_screen_state();
+    for (x, y) in pixels {
+        state.set(*x, *y, true);
+    }
+    state
+}
+
+#[test]
+fn test_display_filter_off() {
+    let mut filter = DisplayFilter::new(DisplayMode::Off);
+    filter.on_draw(&state_with(&[(1, 2)]));
+    assert_eq!(filter.intensity_at(1, 2), INTENSITY_MAX);
+    filter.on_draw(&state_with(&[]));
+    assert_eq!(filter.intensity_at(1, 2), 0);
+    assert!(filter.presents_on_draw());
+}
+
+#[test]
+fn test_display_filter_phosphor() {
+    let mut filter = DisplayFilter::new(DisplayMode::Phosphor { decay_frames: 3 });
+    // drawn and erased within the same frame
+    filter.on_draw(&state_with(&[(0, 0)]));
+    filter.on_draw(&state_with(&[]));
+    assert_eq!(filter.intensity_at(0, 0), INTENSITY_MAX);
+    filter.on_vblank(&state_with(&[]));
+    assert_eq!(filter.intensity_at(0, 0), 170);
+    filter.on_vblank(&state_with(&[]));
+    assert_eq!(filter.intensity_at(0, 0), 85);
+    filter.on_vblank(&state_with(&[]));
+    assert_eq!(filter.intensity_at(0, 0), 0);
+    filter.on_vblank(&state_with(&[(0, 0)]));
+    assert_eq!(filter.intensity_at(0, 0), INTENSITY_MAX);
+    assert!(!filter.presents_on_draw());
+}
+
+#[test]
+fn test_display_filter_blend() {
+    let mut filter = DisplayFilter::new(DisplayMode::Blend);
+    filter.on_vblank(&state_with(&[(5, 5), (6, 6)]));
+    assert_eq!(filter.intensity_at(5, 5), 127);
+    filter.on_vblank(&state_with(&[(5, 5)]));
+    assert_eq!(filter.intensity_at(5, 5), INTENSITY_MAX);
+    assert_eq!(filter.intensity_at(6, 6), 127);
+    assert_eq!(filter.intensity_at(7, 7), 0);
+}
+
+#[test]
+fn test_display_filter_display_wait() {
+    let mut filter = DisplayFilter::new(DisplayMode::DisplayWait);
+    filter.on_draw(&state_with(&[(3, 4)]));
+    assert_eq!(filter.intensity_at(3, 4), 0);
+    filter.on_vblank(&state_with(&[(3, 4)]));
+    assert_eq!(filter.intensity_at(3, 4), INTENSITY_MAX);
+}
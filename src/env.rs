@@ -0,0 +1,301 @@
+//! Reinforcement-learning environment: reset(seed) / step(action) over a Chip8, Gym style.
+//! Score and episode end are read from game memory as described by a GameSpec.
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::chip8::{Chip8, Chip8Error, Platform};
+use crate::screen::ScreenState;
+
+/**
+* Where a game keeps a number
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value {
+    Byte(u16),
+    // big-endian, as the games store them
+    U16(u16),
+    // one decimal digit per byte, most significant first, as Fx33 writes them
+    Bcd { addr: u16, digits: u8 },
+    Register(u8),
+}
+
+impl Value {
+    pub fn read(&self, chip8: &Chip8) -> u32 {
+        match *self {
+            Value::Byte(addr) => chip8.peek(addr).into(),
+            Value::U16(addr) => (u32::from(chip8.peek(addr)) << 8) | u32::from(chip8.peek(addr.wrapping_add(1))),
+            Value::Bcd { addr, digits } => (0..u16::from(digits))
+                .fold(0, |n, d| n * 10 + u32::from(chip8.peek(addr.wrapping_add(d)))),
+            Value::Register(x) => chip8.registers()[usize::from(x & 0xF)].into(),
+        }
+    }
+}
+
+/**
+* When an episode is over
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Done {
+    // e.g. lives reaching 0
+    Equals(Value, u32),
+    // the code in start..end is running: PC or a pending CALL on the stack is in there
+    Executing { start: u16, end: u16 },
+    // only when the emulator stops
+    Never,
+}
+
+impl Done {
+    pub fn check(&self, chip8: &Chip8) -> bool {
+        match *self {
+            Done::Equals(value, expected) => value.read(chip8) == expected,
+            Done::Executing { start, end } => {
+                let stack = chip8.stack();
+                let calls = &stack[..usize::from(chip8.sp())];
+                let pc = chip8.pc();
+                core::iter::once(&pc).chain(calls).any(|addr| (start..end).contains(addr))
+            }
+            Done::Never => false,
+        }
+    }
+}
+
+/**
+* How to play a game: emulator settings, the reward (score increase) and the end of an episode.
+* actions are keypad combinations, bit k set = key k held; put 0 in for "no key"
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GameSpec {
+    pub name: String,
+    pub platform: Platform,
+    pub clock: u32,
+    pub score: Value,
+    pub lives: Option<Value>,
+    pub done: Done,
+    pub actions: Vec<u16>,
+}
+
+impl GameSpec {
+    /**
+     * BLINKY (the ROM in the repo root); 3 up, 6 down, 7 left, 8 right.
+     * Two lives in bit 6 of V7, so the episode ends at the game-over screen instead
+     */
+    pub fn blinky() -> Self {
+        GameSpec {
+            name: "blinky".into(),
+            platform: Platform::Chip8,
+            clock: 600,
+            score: Value::U16(0x8C8),
+            lives: None,
+            done: Done::Executing { start: 0x32E, end: 0x3C6 },
+            actions: vec![0, 1 << 0x3, 1 << 0x6, 1 << 0x7, 1 << 0x8],
+        }
+    }
+
+    /**
+     * CATCH (repo root): catch falling dots with the paddle, 4 left, 6 right.
+     * Score is BCD at 0x260, 3 lives at 0x263
+     */
+    pub fn catch() -> Self {
+        GameSpec {
+            name: "catch".into(),
+            platform: Platform::Chip8,
+            clock: 600,
+            score: Value::Bcd { addr: 0x260, digits: 3 },
+            lives: Some(Value::Byte(0x263)),
+            done: Done::Equals(Value::Byte(0x263), 0),
+            actions: vec![0, 1 << 0x4, 1 << 0x6],
+        }
+    }
+
+    /** The bundled definitions by name: blinky, catch */
+    pub fn bundled(name: &str) -> Option<Self> {
+        match name {
+            "blinky" => Some(GameSpec::blinky()),
+            "catch" => Some(GameSpec::catch()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub observation: ScreenState,
+    // score gained over the skipped frames
+    pub reward: i64,
+    pub done: bool,
+}
+
+pub struct Env {
+    chip8: Chip8,
+    spec: GameSpec,
+    frame_skip: usize,
+    score: u32,
+}
+
+impl Env {
+    /** Frame skip defaults to 4: every step holds the action for 4 frames */
+    pub fn new(spec: GameSpec, rom: &[u8]) -> Result<Self, Chip8Error> {
+        let chip8 = Chip8::builder().rom(rom).platform(spec.platform).clock(spec.clock).build()?;
+        let score = spec.score.read(&chip8);
+        Ok(Env { chip8, spec, frame_skip: 4, score })
+    }
+
+    pub fn set_frame_skip(&mut self, frames: usize) {
+        self.frame_skip = frames.max(1);
+    }
+
+    pub fn frame_skip(&self) -> usize {
+        self.frame_skip
+    }
+
+    pub fn spec(&self) -> &GameSpec {
+        &self.spec
+    }
+
+    pub fn action_count(&self) -> usize {
+        self.spec.actions.len()
+    }
+
+    /** Restarts the game with RND seeded from seed: same seed and actions, same episode */
+    pub fn reset(&mut self, seed: u64) -> ScreenState {
+        self.chip8.reset();
+        self.chip8.seed_rng(seed);
+        self.score = self.spec.score.read(&self.chip8);
+        *self.chip8.pixels()
+    }
+
+    /**
+     * Holds the keys of actions[action] for frame_skip frames, stopping early when the episode ends.
+     * Panics if action >= action_count()
+     */
+    pub fn step(&mut self, action: usize) -> Step {
+        let keys = self.spec.actions[action];
+        for key in 0..16u8 {
+            let held = keys & (1 << key) != 0;
+            if held != self.chip8.is_key_pressed(key) {
+                if held {
+                    self.chip8.key_down(key);
+                } else {
+                    self.chip8.key_up(key);
+                }
+            }
+        }
+        let mut done = self.is_done();
+        for _ in 0..self.frame_skip {
+            if done {
+                break;
+            }
+            self.chip8.run_frame();
+            done = self.is_done();
+        }
+        let score = self.spec.score.read(&self.chip8);
+        let reward = i64::from(score) - i64::from(self.score);
+        self.score = score;
+        Step { observation: *self.chip8.pixels(), reward, done }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.chip8.is_stopped() || self.spec.done.check(&self.chip8)
+    }
+
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    pub fn lives(&self) -> Option<u32> {
+        self.spec.lives.map(|lives| lives.read(&self.chip8))
+    }
+
+    /** The emulator underneath, e.g. for save_state or peeking at memory */
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    pub fn chip8_mut(&mut self) -> &mut Chip8 {
+        &mut self.chip8
+    }
+}
+
+#[cfg(test)]
+fn run_catch(seed: u64, follow: bool) -> (i64, usize) {
+    let mut env = Env::new(GameSpec::catch(), include_bytes!("../CATCH")).unwrap();
+    env.reset(seed);
+    let (mut total, mut steps) = (0, 0);
+    loop {
+        // paddle x in V8, ball x in V1
+        let v = env.chip8().registers();
+        let action = match (follow, v[1], v[8].wrapping_add(3)) {
+            (true, ball, paddle) if ball < paddle => 1,
+            (true, ball, paddle) if ball > paddle => 2,
+            _ => 0,
+        };
+        let step = env.step(action);
+        total += step.reward;
+        steps += 1;
+        if step.done || steps == 10_000 {
+            return (total, steps);
+        }
+    }
+}
+
+#[test]
+fn test_catch_episode() {
+    let (idle_score, idle_steps) = run_catch(7, false);
+    assert!(idle_steps < 10_000);
+    let (score, steps) = run_catch(7, true);
+    assert!(steps < 10_000);
+    assert!(score > idle_score);
+    assert_eq!(run_catch(7, true), (score, steps));
+}
+
+#[test]
+fn test_catch_lives_and_score() {
+    let mut env = Env::new(GameSpec::catch(), include_bytes!("../CATCH")).unwrap();
+    env.reset(1);
+    assert_eq!(env.lives(), Some(3));
+    assert_eq!(env.score(), 0);
+    while !env.step(0).done {}
+    assert_eq!(env.lives(), Some(0));
+    // nothing runs once the episode is over
+    let pc = env.chip8().pc();
+    let step = env.step(1);
+    assert!(step.done);
+    assert_eq!(step.reward, 0);
+    assert_eq!(env.chip8().pc(), pc);
+}
+
+#[test]
+fn test_blinky_ends_at_game_over() {
+    let mut env = Env::new(GameSpec::blinky(), include_bytes!("../BLINKY")).unwrap();
+    env.set_frame_skip(8);
+    let first = env.reset(3);
+    let mut total = 0;
+    let mut steps = 0;
+    loop {
+        let step = env.step(0);
+        total += step.reward;
+        steps += 1;
+        if step.done {
+            break;
+        }
+        assert!(steps < 1000, "no game over after {} frames", steps * 8);
+    }
+    assert!(total > 0);
+    assert_eq!(total, i64::from(env.score()));
+    assert_eq!(env.reset(3), first);
+}
+
+#[test]
+fn test_values() {
+    let rom = [0x12, 0x00, 0x01, 0x02, 0x03, 0x04];
+    let mut chip8 = Chip8::builder().rom(&rom).build().unwrap();
+    chip8.set_register(5, 42);
+    assert_eq!(Value::Byte(0x202).read(&chip8), 1);
+    assert_eq!(Value::U16(0x202).read(&chip8), 0x0102);
+    assert_eq!(Value::Bcd { addr: 0x202, digits: 3 }.read(&chip8), 123);
+    assert_eq!(Value::Register(5).read(&chip8), 42);
+    assert!(Done::Equals(Value::Byte(0x205), 4).check(&chip8));
+    assert!(Done::Executing { start: 0x200, end: 0x202 }.check(&chip8));
+    assert!(!Done::Executing { start: 0x202, end: 0x300 }.check(&chip8));
+}
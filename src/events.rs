@@ -0,0 +1,37 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+
+use crate::sanitizer::Report;
+
+/**
+* Things front-ends may want to react to, collected by the CPU during a frame
+* and handed out by the run loop once the CPU isn't borrowed anymore
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EmulatorEvent {
+    FrameCompleted,
+    SoundOn,
+    SoundOff,
+    // Fx0A started waiting
+    WaitingForKey,
+    // the CPU paused before executing the instruction at this address
+    BreakpointHit(u16),
+    // see CPU::enable_sanitizer
+    Sanitizer(Report),
+    Stopped(StopReason),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Requested,
+    Error(String),
+}
+
+impl StopReason {
+    pub fn describe(&self) -> String {
+        match self {
+            StopReason::Requested => "requested".to_string(),
+            StopReason::Error(e) => format!("error: {}", e),
+        }
+    }
+}
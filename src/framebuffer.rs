@@ -0,0 +1,142 @@
+use crate::cpu_instructions::{X, Y};
+use crate::screen::{make_zero_screen_state, ScreenState, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub struct IsCollision(pub bool);
+
+/**
+* Area of the framebuffer touched since the last presented frame
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DirtyRect {
+    fn pixel(x: usize, y: usize) -> Self {
+        DirtyRect { x, y, width: 1, height: 1 }
+    }
+    pub(crate) fn union(self, other: DirtyRect) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        DirtyRect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+/**
+* A completed frame handed over to presenters
+*/
+pub struct Frame<'a> {
+    pub pixels: &'a ScreenState,
+    // None if nothing changed
+    pub dirty: Option<DirtyRect>,
+}
+
+/**
+* The display memory; owned by CPUState, so collisions don't depend on whoever presents it
+*/
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    pixels: ScreenState,
+    dirty: Option<DirtyRect>,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self {
+            pixels: make_zero_screen_state(),
+            dirty: None,
+        }
+    }
+    pub fn pixels(&self) -> &ScreenState {
+        &self.pixels
+    }
+    pub fn get_pixel(&self, x: X, y: Y) -> bool {
+        self.pixels.get(x.0, y.0)
+    }
+    pub fn toggle_pixel(&mut self, x: X, y: Y) -> IsCollision {
+        let is_collision = self.pixels.get(x.0, y.0);
+        self.pixels.set(x.0, y.0, !is_collision);
+        self.mark_dirty(DirtyRect::pixel(x.0, y.0));
+        IsCollision(is_collision)
+    }
+    /**
+     * XORs 8 pixel wide sprite rows in at (x, y), wrapping around the screen edges
+     */
+    pub fn draw_sprite(&mut self, x: X, y: Y, rows: &[u8]) -> IsCollision {
+        let (x, y) = (x.0 % SCREEN_WIDTH, y.0 % SCREEN_HEIGHT);
+        let is_collision = self.pixels.xor_sprite(x, y, rows);
+        // a wrapped sprite dirties the whole span it wraps across
+        let (x, width) = if x + 8 > SCREEN_WIDTH { (0, SCREEN_WIDTH) } else { (x, 8) };
+        let (y, height) = if y + rows.len() > SCREEN_HEIGHT { (0, SCREEN_HEIGHT) } else { (y, rows.len()) };
+        if height > 0 {
+            self.mark_dirty(DirtyRect { x, y, width, height });
+        }
+        IsCollision(is_collision)
+    }
+    pub fn clear(&mut self) {
+        self.pixels = make_zero_screen_state();
+        self.mark_dirty(DirtyRect { x: 0, y: 0, width: SCREEN_WIDTH, height: SCREEN_HEIGHT });
+    }
+    /**
+     * Replaces every pixel, e.g. when loading a save state
+     */
+    pub fn restore(&mut self, pixels: ScreenState) {
+        self.pixels = pixels;
+        self.mark_dirty(DirtyRect { x: 0, y: 0, width: SCREEN_WIDTH, height: SCREEN_HEIGHT });
+    }
+    /**
+     * The current pixels and whatever changed since the previous call
+     */
+    pub fn take_frame(&mut self) -> Frame<'_> {
+        let dirty = self.dirty.take();
+        Frame {
+            pixels: &self.pixels,
+            dirty,
+        }
+    }
+    fn mark_dirty(&mut self, rect: DirtyRect) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(rect),
+            None => rect,
+        });
+    }
+}
+
+#[test]
+fn test_framebuffer_collision_and_dirty_rect() {
+    let mut framebuffer = Framebuffer::new();
+    assert!(!framebuffer.toggle_pixel(X(3), Y(4)).0);
+    assert!(!framebuffer.toggle_pixel(X(10), Y(1)).0);
+    assert!(framebuffer.get_pixel(X(3), Y(4)));
+    let frame = framebuffer.take_frame();
+    assert_eq!(frame.dirty, Some(DirtyRect { x: 3, y: 1, width: 8, height: 4 }));
+    assert!(framebuffer.toggle_pixel(X(3), Y(4)).0);
+    assert!(!framebuffer.get_pixel(X(3), Y(4)));
+    assert_eq!(framebuffer.take_frame().dirty, Some(DirtyRect { x: 3, y: 4, width: 1, height: 1 }));
+    assert_eq!(framebuffer.take_frame().dirty, None);
+}
+
+#[test]
+fn test_framebuffer_draw_sprite() {
+    let mut framebuffer = Framebuffer::new();
+    assert!(!framebuffer.draw_sprite(X(2), Y(3), &[0xFF, 0x81]).0);
+    assert_eq!(framebuffer.take_frame().dirty, Some(DirtyRect { x: 2, y: 3, width: 8, height: 2 }));
+    assert!(framebuffer.get_pixel(X(9), Y(4)));
+    assert!(!framebuffer.get_pixel(X(5), Y(4)));
+    assert!(framebuffer.draw_sprite(X(66), Y(35), &[0x01]).0);
+    assert!(!framebuffer.get_pixel(X(9), Y(3)));
+}
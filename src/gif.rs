@@ -0,0 +1,255 @@
+//! Just enough of a GIF decoder for Octo cartridges: the palette indices of every frame, colours ignored.
+
+const MAX_CODES: usize = 4096;
+
+// LSB-first, as GIF packs its codes
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bits<'a> {
+    fn read(&mut self, size: u8) -> Option<u16> {
+        if self.pos + usize::from(size) > self.data.len() * 8 {
+            return None;
+        }
+        let mut code = 0;
+        for bit in 0..size {
+            let pos = self.pos + usize::from(bit);
+            code |= u16::from((self.data[pos / 8] >> (pos % 8)) & 1) << bit;
+        }
+        self.pos += usize::from(size);
+        Some(code)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], &'static str> {
+        let bytes = self.data.get(self.pos..self.pos + n).ok_or("truncated")?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, &'static str> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, &'static str> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    // data sub-blocks up to the empty one
+    fn sub_blocks(&mut self) -> Result<Vec<u8>, &'static str> {
+        let mut data = vec![];
+        loop {
+            let len = usize::from(self.byte()?);
+            if len == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(self.bytes(len)?);
+        }
+    }
+
+    fn skip_color_table(&mut self, flags: u8) -> Result<(), &'static str> {
+        if flags & 0x80 != 0 {
+            self.bytes(3 << ((flags & 0x07) + 1))?;
+        }
+        Ok(())
+    }
+}
+
+/** Palette indices of each frame, row by row */
+pub fn frames(data: &[u8]) -> Result<Vec<Vec<u8>>, &'static str> {
+    let mut reader = Reader { data, pos: 0 };
+    let signature = reader.bytes(6)?;
+    if signature != b"GIF87a" && signature != b"GIF89a" {
+        return Err("not a GIF");
+    }
+    // screen size, flags, background, aspect ratio
+    reader.bytes(4)?;
+    let flags = reader.byte()?;
+    reader.bytes(2)?;
+    reader.skip_color_table(flags)?;
+    let mut frames = vec![];
+    loop {
+        match reader.byte()? {
+            // extension: label, then sub-blocks
+            0x21 => {
+                reader.byte()?;
+                reader.sub_blocks()?;
+            }
+            0x2C => {
+                // left, top
+                reader.bytes(4)?;
+                let width = usize::from(reader.u16()?);
+                let height = usize::from(reader.u16()?);
+                let flags = reader.byte()?;
+                reader.skip_color_table(flags)?;
+                let min_code_size = reader.byte()?;
+                let mut pixels = lzw_decode(min_code_size, &reader.sub_blocks()?, width * height)?;
+                if flags & 0x40 != 0 {
+                    pixels = deinterlace(&pixels, width, height);
+                }
+                frames.push(pixels);
+            }
+            0x3B => return Ok(frames),
+            _ => return Err("unknown block"),
+        }
+    }
+}
+
+fn lzw_decode(min_code_size: u8, data: &[u8], len: usize) -> Result<Vec<u8>, &'static str> {
+    if !(1..=11).contains(&min_code_size) {
+        return Err("bad LZW code size");
+    }
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    // entry = prefix entry + last byte; first byte kept for the code-not-yet-in-table case
+    let mut prefix = vec![u16::MAX; MAX_CODES];
+    let mut suffix = vec![0u8; MAX_CODES];
+    let mut first = vec![0u8; MAX_CODES];
+    for code in 0..clear {
+        suffix[usize::from(code)] = code as u8;
+        first[usize::from(code)] = code as u8;
+    }
+    let mut next = end + 1;
+    let mut code_size = min_code_size + 1;
+    let mut previous: Option<u16> = None;
+    let mut bits = Bits { data, pos: 0 };
+    let mut out = Vec::with_capacity(len);
+    let mut string = vec![];
+    while out.len() < len {
+        let code = match bits.read(code_size) {
+            Some(code) => code,
+            None => break,
+        };
+        if code == clear {
+            next = end + 1;
+            code_size = min_code_size + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+        let first_byte = match previous {
+            None if code < clear => code as u8,
+            None => return Err("bad LZW code"),
+            Some(_) if code < next => first[usize::from(code)],
+            Some(p) if code == next => first[usize::from(p)],
+            Some(_) => return Err("bad LZW code"),
+        };
+        if let Some(p) = previous {
+            if usize::from(next) < MAX_CODES {
+                prefix[usize::from(next)] = p;
+                suffix[usize::from(next)] = first_byte;
+                first[usize::from(next)] = first[usize::from(p)];
+                next += 1;
+                if usize::from(next) == 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            }
+        }
+        string.clear();
+        let mut entry = code;
+        while entry != u16::MAX {
+            string.push(suffix[usize::from(entry)]);
+            entry = prefix[usize::from(entry)];
+        }
+        out.extend(string.iter().rev());
+        previous = Some(code);
+    }
+    if out.len() < len {
+        return Err("image data too short");
+    }
+    out.truncate(len);
+    Ok(out)
+}
+
+// rows come in 4 passes: every 8th from 0, every 8th from 4, every 4th from 2, every 2nd from 1
+fn deinterlace(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let rows = [(0, 8), (4, 8), (2, 4), (1, 2)].iter().flat_map(|&(start, step)| (start..height).step_by(step));
+    let mut out = vec![0; pixels.len()];
+    for (source, row) in rows.enumerate() {
+        out[row * width..(row + 1) * width].copy_from_slice(&pixels[source * width..(source + 1) * width]);
+    }
+    out
+}
+
+// every index a literal code, with a clear code before the table would grow the code size
+#[cfg(test)]
+pub(crate) fn encode(width: u16, height: u16, frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut gif = b"GIF89a".to_vec();
+    gif.extend_from_slice(&width.to_le_bytes());
+    gif.extend_from_slice(&height.to_le_bytes());
+    // global table of 4 colours
+    gif.extend_from_slice(&[0x81, 0, 0]);
+    gif.extend_from_slice(&[0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0xFF, 0]);
+    for pixels in frames {
+        gif.push(0x2C);
+        gif.extend_from_slice(&[0, 0, 0, 0]);
+        gif.extend_from_slice(&width.to_le_bytes());
+        gif.extend_from_slice(&height.to_le_bytes());
+        gif.push(0);
+        // min code size 2: clear 4, end 5, 3-bit codes until entry 8
+        gif.push(2);
+        let mut codes = vec![];
+        for chunk in pixels.chunks(2) {
+            codes.push(4);
+            codes.extend(chunk.iter().map(|p| u32::from(*p)));
+        }
+        codes.push(5);
+        let mut packed = vec![];
+        let (mut acc, mut n) = (0u32, 0);
+        for code in codes {
+            acc |= code << n;
+            n += 3;
+            while n >= 8 {
+                packed.push(acc as u8);
+                acc >>= 8;
+                n -= 8;
+            }
+        }
+        if n > 0 {
+            packed.push(acc as u8);
+        }
+        for block in packed.chunks(255) {
+            gif.push(block.len() as u8);
+            gif.extend_from_slice(block);
+        }
+        gif.push(0);
+    }
+    gif.push(0x3B);
+    gif
+}
+
+#[test]
+fn test_decode_frames() {
+    let first: Vec<u8> = (0..48).map(|i| (i * 7 % 4) as u8).collect();
+    let second = vec![3; 48];
+    let decoded = frames(&encode(8, 6, &[first.clone(), second.clone()])).unwrap();
+    assert_eq!(decoded, vec![first, second]);
+    assert!(frames(b"GIF89a").is_err());
+    assert!(frames(b"PNG").is_err());
+}
+
+#[test]
+fn test_lzw_table_codes() {
+    // min size 2: clear, 1, then 6 and 7 each one step ahead of the table ("1 1", "1 1 1"),
+    // after which codes take 4 bits
+    let codes = [(4u32, 3), (1, 3), (6, 3), (7, 3), (5, 4)];
+    let (mut acc, mut n) = (0u32, 0);
+    for (code, size) in codes {
+        acc |= code << n;
+        n += size;
+    }
+    let data = acc.to_le_bytes();
+    assert_eq!(lzw_decode(2, &data[..2], 6).unwrap(), vec![1; 6]);
+    assert!(lzw_decode(2, &data[..2], 7).is_err());
+}
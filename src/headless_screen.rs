@@ -0,0 +1,28 @@
+use futures::{FutureExt, future::ready};
+use futures::future::LocalBoxFuture;
+
+use crate::framebuffer::Frame;
+use crate::screen::{Screen, ScreenDraw};
+
+/**
+* Presents nothing; the framebuffer is read from CPUState instead.
+* Frames don't wait for anything, so run() goes as fast as the timer lets it
+*/
+#[derive(Default)]
+pub struct HeadlessScreen {}
+
+impl HeadlessScreen {
+    pub fn new() -> Self {
+        HeadlessScreen {}
+    }
+}
+
+impl ScreenDraw for HeadlessScreen {
+    fn present(&mut self, _frame: &Frame) {}
+}
+
+impl Screen for HeadlessScreen {
+    fn request_animation_frame(&self) -> LocalBoxFuture<'static, ()> {
+        ready(()).boxed_local()
+    }
+}
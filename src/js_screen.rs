@@ -0,0 +1,142 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use futures::{FutureExt, future::ready};
+use futures::future::LocalBoxFuture;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::*;
+use web_sys::window;
+
+use crate::framebuffer::{DirtyRect, Frame};
+use crate::screen::{Screen, ScreenDraw, ScreenState, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::wasm_canvas_screen::next_animation_frame;
+
+/**
+* Presenter implemented in JS:
+* <pre><code>{
+*   draw(pixels: Uint8Array, width: number, height: number, dirty: {x, y, width, height}),
+*   clear?(),   // instead of draw when the whole screen went blank
+*   present?(), // once per frame
+* }</code></pre>
+*/
+pub struct JsScreen(Rc<JsTarget>);
+
+/**
+* The JS object and what it was asked to do while the CPU was borrowed. It's only called from flush,
+* with the CPU released, so a JS screen may use the WasmProgram
+*/
+pub(crate) struct JsTarget {
+    target: JsValue,
+    draw: js_sys::Function,
+    clear: Option<js_sys::Function>,
+    present: Option<js_sys::Function>,
+    pending: RefCell<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    // the latest pixels and everything that changed since the last flush
+    frame: Option<(ScreenState, DirtyRect)>,
+    present: bool,
+}
+
+fn method(target: &JsValue, name: &str) -> Result<Option<js_sys::Function>, JsValue> {
+    let value = js_sys::Reflect::get(target, &JsValue::from_str(name))?;
+    if value.is_undefined() {
+        Ok(None)
+    } else {
+        value.dyn_into::<js_sys::Function>()
+            .map(Some)
+            .map_err(|_| JsValue::from_str(&format!("screen.{} is not a function", name)))
+    }
+}
+
+impl JsScreen {
+    pub fn new(target: JsValue) -> Result<Self, JsValue> {
+        Ok(Self(Rc::new(JsTarget {
+            draw: method(&target, "draw")?.ok_or_else(|| JsValue::from_str("screen object needs a draw method"))?,
+            clear: method(&target, "clear")?,
+            present: method(&target, "present")?,
+            target,
+            pending: RefCell::new(Pending::default()),
+        })))
+    }
+    pub(crate) fn target(&self) -> Rc<JsTarget> {
+        self.0.clone()
+    }
+}
+
+impl JsTarget {
+    fn report(result: Result<JsValue, JsValue>) {
+        if let Err(e) = result {
+            web_sys::console::error_2(&JsValue::from_str("chip8 JS screen failed"), &e);
+        }
+    }
+    /**
+     * Makes the calls collected since the last flush; the caller mustn't hold the CPU
+     */
+    pub(crate) fn flush(&self) {
+        let pending = self.pending.take();
+        if let Some((pixels, dirty)) = pending.frame {
+            self.draw_frame(&pixels, dirty);
+        }
+        if pending.present {
+            if let Some(present) = &self.present {
+                JsTarget::report(present.call0(&self.target));
+            }
+        }
+    }
+    fn draw_frame(&self, pixels: &ScreenState, dirty: DirtyRect) {
+        if let Some(clear) = &self.clear {
+            if pixels.rows().iter().all(|row| *row == 0) {
+                JsTarget::report(clear.call0(&self.target));
+                return;
+            }
+        }
+        let rect = js_sys::Object::new();
+        for (key, value) in [("x", dirty.x), ("y", dirty.y), ("width", dirty.width), ("height", dirty.height)] {
+            JsTarget::report(js_sys::Reflect::set(&rect, &JsValue::from_str(key), &JsValue::from(value as u32)).map(JsValue::from));
+        }
+        let args = js_sys::Array::of4(
+            &js_sys::Uint8Array::from(&pixels.to_bytes()[..]),
+            &JsValue::from(SCREEN_WIDTH as u32),
+            &JsValue::from(SCREEN_HEIGHT as u32),
+            &rect,
+        );
+        JsTarget::report(self.draw.apply(&self.target, &args));
+    }
+}
+
+// called while the CPU is borrowed, so nothing reaches JS until flush
+impl ScreenDraw for JsScreen {
+    fn present(&mut self, frame: &Frame) {
+        if let Some(dirty) = frame.dirty {
+            let mut pending = self.0.pending.borrow_mut();
+            let dirty = match pending.frame {
+                Some((_, earlier)) => earlier.union(dirty),
+                None => dirty,
+            };
+            pending.frame = Some((*frame.pixels, dirty));
+        }
+    }
+}
+
+impl Screen for JsScreen {
+    fn request_animation_frame(&self) -> LocalBoxFuture<'static, ()> {
+        // no requestAnimationFrame in Node.js and workers
+        let next = match window() {
+            Some(_) => next_animation_frame(),
+            None => ready(()).boxed_local(),
+        };
+        // the run loop awaits this with the CPU released
+        let target = self.target();
+        async move {
+            target.flush();
+            next.await;
+        }.boxed_local()
+    }
+    fn vblank(&self) {
+        self.0.pending.borrow_mut().present = true;
+    }
+}
@@ -1,41 +1,78 @@
-use std::collections::HashMap;
-
-lazy_static! {
-    pub static ref PC_KEY_MAP: HashMap<usize, u8> = {
-        let mut map = HashMap::new();
-        [
-            (88, 0x0),
-            (49, 0x1),
-            (50, 0x2),
-            (51, 0x3),
-            (81, 0x4),
-            (87, 0x5),
-            (69, 0x6),
-            (65, 0x7),
-            (83, 0x8),
-            (68, 0x9),
-            (90, 0xA),
-            (67, 0xB),
-            (52, 0xC),
-            (82, 0xD),
-            (70, 0xE),
-            (86, 0xF),
-        ].iter().for_each(|t| {
-            map.insert(t.0, t.1);
-        });
-        map
-    };
-}
-
-// const PC_KEY_MAP: HashMap<u32, u8> = KEY_TUPLES.into_iter().cloned().collect();
-
-// const CHIP8_KEYS: HashMap<u8, u32> = KEY_TUPLES.iter().cloned().map(|(a, b)| (b, a)).collect();
+use alloc::vec::Vec;
+
+/**
+* PC key codes (KeyboardEvent.keyCode) to the CHIP-8 hex keypad:
+* <pre><code>1 2 3 4      1 2 3 C
+* Q W E R  ->  4 5 6 D
+* A S D F      7 8 9 E
+* Z X C V      A 0 B F</code></pre>
+*/
+pub fn pc_key_to_chip8(k: usize) -> Option<u8> {
+    match k {
+        88 => Some(0x0),
+        49 => Some(0x1),
+        50 => Some(0x2),
+        51 => Some(0x3),
+        81 => Some(0x4),
+        87 => Some(0x5),
+        69 => Some(0x6),
+        65 => Some(0x7),
+        83 => Some(0x8),
+        68 => Some(0x9),
+        90 => Some(0xA),
+        67 => Some(0xB),
+        52 => Some(0xC),
+        82 => Some(0xD),
+        70 => Some(0xE),
+        86 => Some(0xF),
+        _ => None,
+    }
+}
+
+// KeyboardEvent.keyCode of keys a KeyMap can bind
+pub const KEY_SPACE: usize = 32;
+pub const KEY_ENTER: usize = 13;
+pub const KEY_LEFT: usize = 37;
+pub const KEY_UP: usize = 38;
+pub const KEY_RIGHT: usize = 39;
+pub const KEY_DOWN: usize = 40;
+
+/**
+* Per-game PC key bindings, e.g. arrows for the keys a game moves with,
+* checked before the pc_key_to_chip8 layout
+*/
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyMap {
+    bindings: Vec<(usize, u8)>,
+}
+
+impl KeyMap {
+    pub fn new() -> Self {
+        KeyMap::default()
+    }
+    pub fn bind(&mut self, pc_key: usize, chip8_key: u8) {
+        self.bindings.retain(|(k, _)| *k != pc_key);
+        self.bindings.push((pc_key, chip8_key & 0xF));
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bindings.is_empty()
+    }
+    pub fn to_chip8(&self, pc_key: usize) -> Option<u8> {
+        self.bindings.iter().find(|(k, _)| *k == pc_key).map(|(_, c)| *c).or_else(|| pc_key_to_chip8(pc_key))
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct KeyboardState {
     key_state: [bool; 16]
 }
 
+impl Default for KeyboardState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl KeyboardState {
     pub fn new() -> Self {
         Self {key_state: [false; 16]}
@@ -49,4 +86,23 @@ impl KeyboardState {
     pub fn key_up(&mut self, k: &u8) {
         self.key_state[*k as usize] = false;
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_pc_key_to_chip8() {
+    assert_eq!(pc_key_to_chip8(88), Some(0x0));
+    assert_eq!(pc_key_to_chip8(86), Some(0xF));
+    assert_eq!(pc_key_to_chip8(32), None);
+}
+
+#[test]
+fn test_key_map() {
+    let mut map = KeyMap::new();
+    map.bind(KEY_UP, 0x5);
+    map.bind(88, 0x2);
+    map.bind(KEY_UP, 0x3);
+    assert_eq!(map.to_chip8(KEY_UP), Some(0x3));
+    assert_eq!(map.to_chip8(88), Some(0x2));
+    assert_eq!(map.to_chip8(86), Some(0xF));
+    assert_eq!(map.to_chip8(KEY_DOWN), None);
+}
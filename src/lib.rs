@@ -1,77 +1,76 @@
-mod cpu;
-mod screen;
-mod console_screen;
+//! CHIP-8 emulator.
+//!
+//! Without default features the core (CPU, decoder, framebuffer, display filters) is `no_std` + `alloc`;
+//! `std` adds OS seeding and the parallel `batch` runner, `runner` the async run loop, `wasm` the browser/Node bindings,
+//! `romdb` per-ROM settings from a ROM database, `octo` Octo cartridge GIFs, `console` the terminal screen, `libretro` a libretro core, `capi` a C API,
+//! `python` a PyO3 module and `scripting` Rhai scripts hooked into the emulator.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod cpu;
+pub mod audio;
+mod chip8;
+pub mod snapshot;
+pub mod screen;
+#[cfg(feature = "console")]
+pub mod console_screen;
 mod macros;
 mod cpu_decoder;
 mod cpu_instructions;
 #[cfg(test)]
 mod test_utils;
-mod keyboard;
+pub mod keyboard;
+#[cfg(feature = "wasm")]
 mod wasm_canvas_screen;
+pub mod pixel_buffer;
+pub mod theme;
+pub mod display_filter;
+pub mod framebuffer;
+pub mod command_queue;
+pub mod events;
+#[cfg(feature = "wasm")]
+mod wasm_callbacks;
+pub mod headless_screen;
+pub mod env;
+pub mod rom;
+pub mod analysis;
+pub mod control_flow;
+pub mod sanitizer;
+pub mod profiler;
+pub mod coverage;
+pub mod provenance;
+pub mod cheats;
+#[cfg(feature = "octo")]
+mod gif;
+#[cfg(feature = "octo")]
+pub mod octo;
+#[cfg(feature = "octo")]
+mod cartridge;
+#[cfg(feature = "romdb")]
+pub mod rom_db;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "wasm")]
+mod js_screen;
+#[cfg(feature = "wasm")]
+mod wasm_program;
+#[cfg(feature = "libretro")]
+mod libretro;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "scripting")]
+pub mod scripting;
 
-use std::sync::Arc;
-use std::time::Duration;
-use fluvio_wasm_timer::Delay;
-use wasm_bindgen::prelude::*;
-#[macro_use]
-extern crate lazy_static;
-
-use wasm_bindgen::JsCast;
-use wasm_bindgen_futures::spawn_local;
-use wasm_mutex::Mutex;
-
-use cpu::CPU;
-
-use crate::wasm_canvas_screen::WasmCanvasScreen;
-
-#[wasm_bindgen]
-pub struct WasmProgram {
-    // #[wasm_bindgen(skip)]
-    cpu: Arc<Mutex<CPU>>,
-}
-
-// TODO DRY locks (macros?)
-#[wasm_bindgen]
-impl WasmProgram {
-    pub fn run(&self) {
-        let clone = self.cpu.clone();
-        spawn_local(async move {
-            CPU::run(clone).await;
-        })
-    }
-    pub fn stop(&mut self) {
-        let clone = self.cpu.clone();
-        spawn_local(async move {
-            let mut guard = clone.lock().await;
-            guard.stop();
-        })
-    }
-
-    pub fn key_down(&mut self, k: usize) {
-        let clone = self.cpu.clone();
-        spawn_local(async move {
-            let mut guard = clone.lock().await;
-            guard.key_down(k);
-        })
-    }
-
-    pub fn key_up(&mut self, k: usize) {
-        let clone = self.cpu.clone();
-        spawn_local(async move {
-            let mut guard = clone.lock().await;
-            guard.key_up(k);
-        })
-    }
-}
-
-#[wasm_bindgen]
-pub fn init_program(program: &[u8], canvas: JsValue) -> Result<WasmProgram, JsValue> {
-    match canvas.dyn_into::<web_sys::CanvasRenderingContext2d>() {
-        Ok(canvas) => {
-            let mut cpu = CPU::new(Box::new(WasmCanvasScreen::new(canvas)));
-            cpu.load_program(program.to_vec());
-            Ok(WasmProgram { cpu: Arc::new(Mutex::new(cpu)) })
-        }
-        Err(_) => Err(JsValue::from_str("canvas argument not a HtmlCanvas")),
-    }
-}
+pub use audio::Audio;
+pub use chip8::{Chip8, Chip8Builder, Chip8Error, Platform};
+pub use cpu::{CPUQuirks, StepError, StepTrace, CPU};
+pub use env::{Env, GameSpec};
+pub use rom::{LoadError, Rom, RomFormat};
+pub use events::{EmulatorEvent, StopReason};
+pub use screen::{Screen, ScreenDraw, ScreenState};
+pub use cpu_decoder::DecodeError;
+#[cfg(feature = "wasm")]
+pub use wasm_program::*;
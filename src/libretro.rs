@@ -0,0 +1,502 @@
+//! libretro core: `cargo build --release --no-default-features --features libretro`
+//! produces a shared library RetroArch loads as a core.
+//!
+//! Keypad: the PC layout (1234/QWER/ASDF/ZXCV) on the keyboard, and on the joypad
+//! D-pad 2/8/4/6, A 5, B 0, X A, Y B, L 1, R 3, Select E, Start F.
+use std::cell::{Cell, RefCell};
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::audio::Audio;
+use crate::chip8::Chip8;
+use crate::command_queue::{Command, CommandQueue};
+use crate::cpu::CPUQuirks;
+use crate::screen::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::snapshot::SNAPSHOT_SIZE;
+use crate::theme::{Rgba, Theme};
+
+const RETRO_API_VERSION: c_uint = 1;
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_REGION_NTSC: c_uint = 0;
+
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_ENVIRONMENT_SET_KEYBOARD_CALLBACK: c_uint = 12;
+const RETRO_ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const RETRO_ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const FPS: f64 = 60.0;
+const SAMPLE_RATE: usize = 44100;
+const SAMPLES_PER_FRAME: usize = SAMPLE_RATE / 60;
+const TONE_HZ: usize = 440;
+const TONE_AMPLITUDE: i16 = 0x2000;
+
+// RETRO_DEVICE_ID_JOYPAD_* to keypad keys
+const JOYPAD_KEYS: [(c_uint, u8); 12] = [
+    (0, 0x0), // B
+    (1, 0xB), // Y
+    (2, 0xE), // Select
+    (3, 0xF), // Start
+    (4, 0x2), // Up
+    (5, 0x8), // Down
+    (6, 0x4), // Left
+    (7, 0x6), // Right
+    (8, 0x5), // A
+    (9, 0xA), // X
+    (10, 0x1), // L
+    (11, 0x3), // R
+];
+
+const OPTION_CLOCK: &[u8] = b"chip8_clock\0";
+const OPTION_SHIFT: &[u8] = b"chip8_shift_quirk\0";
+const OPTION_LOAD_STORE: &[u8] = b"chip8_load_store_quirk\0";
+
+#[repr(C)]
+pub struct SystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    geometry: GameGeometry,
+    timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[repr(C)]
+struct Variable {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+#[repr(C)]
+struct KeyboardCallback {
+    callback: extern "C" fn(down: bool, keycode: c_uint, character: u32, key_modifiers: u16),
+}
+
+pub type EnvironmentFn = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type VideoRefreshFn = extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type AudioSampleFn = extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = extern "C" fn();
+pub type InputStateFn = extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+// fn pointers are copied out before calling, so the front-end may call back into the core
+#[derive(Default, Clone, Copy)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+struct Tone(Arc<AtomicBool>);
+
+impl Audio for Tone {
+    fn set_tone(&mut self, on: bool) {
+        self.0.store(on, Ordering::Relaxed);
+    }
+}
+
+struct Core {
+    chip8: Chip8,
+    tone: Arc<AtomicBool>,
+    // position in the square wave, carried across frames
+    phase: usize,
+    // joypad buttons held during the previous frame, one bit per JOYPAD_KEYS entry
+    buttons: u16,
+    video: Vec<u32>,
+    audio: Vec<i16>,
+    theme: Theme,
+    // what the ROM picked for itself, restored by the "auto" options
+    detected: Detected,
+}
+
+#[derive(Clone, Copy)]
+struct Detected {
+    clock: u32,
+    quirks: CPUQuirks,
+}
+
+// libretro calls a core from a single thread
+thread_local! {
+    static CALLBACKS: Cell<Callbacks> = Cell::new(Callbacks::default());
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+    // the keyboard callback may come while retro_run holds CORE, so keys go through the command queue
+    static KEYBOARD: RefCell<Option<CommandQueue>> = const { RefCell::new(None) };
+}
+
+fn callbacks() -> Callbacks {
+    CALLBACKS.with(|c| c.get())
+}
+
+fn set_callbacks(f: impl FnOnce(&mut Callbacks)) {
+    CALLBACKS.with(|c| {
+        let mut callbacks = c.get();
+        f(&mut callbacks);
+        c.set(callbacks);
+    });
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match callbacks().environment {
+        Some(environment) => environment(cmd, data),
+        None => false,
+    }
+}
+
+fn get_variable(key: &'static [u8]) -> Option<String> {
+    let mut variable = Variable { key: key.as_ptr() as *const c_char, value: ptr::null() };
+    if !environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut variable as *mut Variable as *mut c_void) || variable.value.is_null() {
+        return None;
+    }
+    // the front-end owns the value string
+    Some(unsafe { CStr::from_ptr(variable.value) }.to_string_lossy().into_owned())
+}
+
+/**
+* Options left unset or at "auto" keep what the ROM picked, so they don't clobber detected quirks
+*/
+fn apply_options(chip8: &mut Chip8, detected: Detected) {
+    chip8.set_clock(get_variable(OPTION_CLOCK).and_then(|v| v.parse().ok()).unwrap_or(detected.clock));
+    let enabled = |key| match get_variable(key).as_deref() {
+        Some("enabled") => Some(true),
+        Some("disabled") => Some(false),
+        _ => None,
+    };
+    chip8.set_quirks(CPUQuirks {
+        shift: enabled(OPTION_SHIFT).unwrap_or(detected.quirks.shift),
+        load_store: enabled(OPTION_LOAD_STORE).unwrap_or(detected.quirks.load_store),
+    });
+}
+
+fn xrgb(color: Rgba) -> u32 {
+    (color.0 as u32) << 16 | (color.1 as u32) << 8 | color.2 as u32
+}
+
+extern "C" fn keyboard_event(down: bool, keycode: c_uint, _character: u32, _key_modifiers: u16) {
+    // RETROK_a.. are lowercase ASCII; the keypad map takes browser key codes, i.e. uppercase
+    let key = match keycode {
+        97..=122 => keycode - 32,
+        _ => keycode,
+    };
+    KEYBOARD.with(|keyboard| {
+        if let Some(commands) = keyboard.borrow().as_ref() {
+            let key = key as usize;
+            commands.push(if down { Command::KeyDown(key) } else { Command::KeyUp(key) });
+        }
+    });
+}
+
+impl Core {
+    fn poll_joypad(&mut self) {
+        let Callbacks { input_poll, input_state, .. } = callbacks();
+        if let Some(input_poll) = input_poll {
+            input_poll();
+        }
+        let input_state = match input_state {
+            Some(input_state) => input_state,
+            None => return,
+        };
+        let mut buttons = 0u16;
+        for (i, (id, key)) in JOYPAD_KEYS.iter().enumerate() {
+            let held = input_state(0, RETRO_DEVICE_JOYPAD, 0, *id) != 0;
+            buttons |= (held as u16) << i;
+            if held != (self.buttons & 1 << i != 0) {
+                if held {
+                    self.chip8.key_down(*key);
+                } else {
+                    self.chip8.key_up(*key);
+                }
+            }
+        }
+        self.buttons = buttons;
+    }
+
+    fn render(&mut self) {
+        let (bg, fg) = (xrgb(self.theme.background()), xrgb(self.theme.foreground()));
+        let pixels = self.chip8.pixels();
+        for (i, out) in self.video.iter_mut().enumerate() {
+            *out = if pixels.get(i % SCREEN_WIDTH, i / SCREEN_WIDTH) { fg } else { bg };
+        }
+        if let Some(video_refresh) = callbacks().video_refresh {
+            video_refresh(self.video.as_ptr() as *const c_void, SCREEN_WIDTH as c_uint, SCREEN_HEIGHT as c_uint, SCREEN_WIDTH * 4);
+        }
+    }
+
+    fn play(&mut self) {
+        let half_period = SAMPLE_RATE / TONE_HZ / 2;
+        let on = self.tone.load(Ordering::Relaxed);
+        for frame in self.audio.chunks_mut(2) {
+            let sample = match on {
+                true if (self.phase / half_period) % 2 == 0 => TONE_AMPLITUDE,
+                true => -TONE_AMPLITUDE,
+                false => 0,
+            };
+            frame[0] = sample;
+            frame[1] = sample;
+            self.phase = (self.phase + 1) % (half_period * 2);
+        }
+        if let Some(audio_sample_batch) = callbacks().audio_sample_batch {
+            audio_sample_batch(self.audio.as_ptr(), SAMPLES_PER_FRAME);
+        }
+    }
+}
+
+fn with_core<T>(f: impl FnOnce(&mut Core) -> T) -> Option<T> {
+    CORE.with(|core| core.borrow_mut().as_mut().map(f))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    retro_unload_game();
+}
+
+/// # Safety
+/// `info` must point to a writable retro_system_info
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: c"rust-wasm-chip8".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"ch8|c8|sc8|xo8|gif|rom".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` must point to a writable retro_system_av_info
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: SCREEN_WIDTH as c_uint,
+            base_height: SCREEN_HEIGHT as c_uint,
+            max_width: SCREEN_WIDTH as c_uint,
+            max_height: SCREEN_HEIGHT as c_uint,
+            aspect_ratio: SCREEN_WIDTH as f32 / SCREEN_HEIGHT as f32,
+        },
+        timing: SystemTiming { fps: FPS, sample_rate: SAMPLE_RATE as f64 },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: EnvironmentFn) {
+    set_callbacks(|c| c.environment = Some(cb));
+    let variables = [
+        Variable {
+            key: OPTION_CLOCK.as_ptr() as *const c_char,
+            value: c"Instructions per second; auto|300|400|500|600|700|800|1000|1200|1500|2000".as_ptr(),
+        },
+        Variable {
+            key: OPTION_SHIFT.as_ptr() as *const c_char,
+            value: c"Shift quirk (8xy6/8xyE shift VX in place); auto|disabled|enabled".as_ptr(),
+        },
+        Variable {
+            key: OPTION_LOAD_STORE.as_ptr() as *const c_char,
+            value: c"Load/store quirk (Fx55/Fx65 leave I unchanged); auto|disabled|enabled".as_ptr(),
+        },
+        Variable { key: ptr::null(), value: ptr::null() },
+    ];
+    cb(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_ptr() as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: VideoRefreshFn) {
+    set_callbacks(|c| c.video_refresh = Some(cb));
+}
+
+// the batch callback is used instead
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: AudioSampleBatchFn) {
+    set_callbacks(|c| c.audio_sample_batch = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: InputPollFn) {
+    set_callbacks(|c| c.input_poll = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: InputStateFn) {
+    set_callbacks(|c| c.input_state = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+/// # Safety
+/// `game` must be null or point to a retro_game_info whose data is valid for size bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = std::slice::from_raw_parts((*game).data as *const u8, (*game).size);
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+        return false;
+    }
+    let tone = Arc::new(AtomicBool::new(false));
+    let mut chip8 = match Chip8::builder().audio(Box::new(Tone(tone.clone()))).rom(rom).build() {
+        Ok(chip8) => chip8,
+        Err(e) => {
+            log::error!("can't load the game: {}", e);
+            return false;
+        }
+    };
+    let detected = Detected { clock: chip8.clock(), quirks: chip8.quirks() };
+    apply_options(&mut chip8, detected);
+    let callback = KeyboardCallback { callback: keyboard_event };
+    environment(RETRO_ENVIRONMENT_SET_KEYBOARD_CALLBACK, &callback as *const KeyboardCallback as *mut c_void);
+    KEYBOARD.with(|keyboard| *keyboard.borrow_mut() = Some(chip8.command_queue()));
+    let core = Core {
+        chip8,
+        tone,
+        phase: 0,
+        buttons: 0,
+        video: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        audio: vec![0; SAMPLES_PER_FRAME * 2],
+        theme: Theme::default(),
+        detected,
+    };
+    CORE.with(|c| *c.borrow_mut() = Some(core));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const GameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    KEYBOARD.with(|keyboard| *keyboard.borrow_mut() = None);
+    CORE.with(|c| *c.borrow_mut() = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    with_core(|core| {
+        let mut updated = false;
+        if environment(RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void) && updated {
+            apply_options(&mut core.chip8, core.detected);
+        }
+        core.poll_joypad();
+        core.chip8.run_frame();
+        core.render();
+        core.play();
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core(|core| core.chip8.reset());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    SNAPSHOT_SIZE
+}
+
+/// # Safety
+/// `data` must be null or valid for writing size bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    if data.is_null() || size < SNAPSHOT_SIZE {
+        return false;
+    }
+    match with_core(|core| core.chip8.save_state()) {
+        Some(state) => {
+            ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+            true
+        }
+        None => false,
+    }
+}
+
+/// # Safety
+/// `data` must be null or valid for reading size bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let state = std::slice::from_raw_parts(data as *const u8, size);
+    with_core(|core| match core.chip8.load_state(state) {
+        // the options win over the quirks stored in the state
+        Ok(()) => {
+            apply_options(&mut core.chip8, core.detected);
+            true
+        }
+        Err(e) => {
+            log::error!("can't load the state: {}", e);
+            false
+        }
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
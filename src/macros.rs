@@ -2,7 +2,7 @@ macro_rules! newtype_copy {
     ($t:ident) => {
         impl Clone for $t {
             fn clone(&self) -> Self {
-                $t(self.0)
+                *self
             }
         }
         impl Copy for $t {
@@ -1,35 +1,178 @@
-mod cpu;
-mod screen;
-mod console_screen;
-mod macros;
-mod cpu_decoder;
-mod cpu_instructions;
-#[cfg(test)]
-mod test_utils;
-mod keyboard;
-mod wasm_canvas_screen;
-#[macro_use]
-extern crate lazy_static;
-
-use std::fs::File;
-use std::io::Read;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::cell::RefCell;
+use std::process;
+use std::rc::Rc;
 use std::sync::Arc;
-use wasm_mutex::Mutex;
-use cpu::CPU;
-use crate::console_screen::ConsoleScreen;
+#[cfg(feature = "scripting")]
+use std::time::Duration;
+
+use rust_wasm_chip8::cheats::{Cheat, CheatList};
+use rust_wasm_chip8::control_flow;
+use rust_wasm_chip8::{Platform, CPU};
+use rust_wasm_chip8::console_screen::ConsoleScreen;
+use rust_wasm_chip8::events::{EmulatorEvent, StopReason};
+use rust_wasm_chip8::rom::Rom;
+use rust_wasm_chip8::rom_db::RomDatabase;
+use rust_wasm_chip8::sanitizer::SanitizerOptions;
+#[cfg(feature = "scripting")]
+use rust_wasm_chip8::scripting::Script;
+
+const USAGE: &str = "usage: rust-wasm-chip8 [ROM] [--db programs.json] [--load-address 0x600] [--platform chip8|superchip] [--cfg dot|json] [--sanitize] [--random-ram] [--cheats FILE] [--cheat 3A0=09]... [--script file.rhai]";
+
+struct Args {
+    rom: String,
+    db: Option<String>,
+    load_address: Option<u16>,
+    // picked from the ROM's code otherwise
+    platform: Option<Platform>,
+    // print the control-flow graph in this format instead of running
+    cfg: Option<String>,
+    // --random-ram implies --sanitize
+    sanitizer: Option<SanitizerOptions>,
+    // the ROM's name with .cht if there's such a file
+    cheats: Option<String>,
+    // added after the file's
+    cheat_codes: Vec<Cheat>,
+    // runs the frames instead of the usual loop, see scripting
+    script: Option<String>,
+}
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> std::io::Result<()> {
-    let program = read_rom();
+    let args = parse_args();
+    let mut rom = fail_on_error(&args.rom, Rom::parse(Some(&args.rom), &fs::read(&args.rom)?));
+    if let Some(addr) = args.load_address {
+        rom = fail_on_error(&args.rom, rom.at(addr));
+    }
+    if let Some(format) = &args.cfg {
+        let flow = control_flow::analyse(&rom.program, rom.load_address);
+        for issue in &flow.issues {
+            eprintln!("{}: {}", args.rom, issue.describe());
+        }
+        println!("{}", if format == "json" { flow.to_json() } else { flow.to_dot() });
+        return Ok(());
+    }
+    if let Some(platform) = args.platform {
+        rom.platform = Some(platform);
+        rom.quirks = None;
+    }
     let mut cpu = CPU::new(Box::new(ConsoleScreen::new()));
-    cpu.load_program(program);
-    CPU::run(Arc::new(Mutex::new(cpu))).await;
+    if let Some(path) = args.db {
+        let db = fail_on_error(&path, RomDatabase::from_json(&fs::read_to_string(&path)?));
+        cpu.set_rom_database(Arc::new(db));
+    }
+    if let Some(options) = args.sanitizer {
+        cpu.enable_sanitizer(options);
+    }
+    fail_on_error(&args.rom, cpu.load(rom));
+    let cheat_file = args.cheats.or_else(|| {
+        let default = Path::new(&args.rom).with_extension("cht");
+        default.is_file().then(|| default.to_string_lossy().into_owned())
+    });
+    if let Some(path) = cheat_file {
+        cpu.set_cheats(fail_on_error(&path, CheatList::parse(&fs::read_to_string(&path)?)));
+    }
+    for cheat in args.cheat_codes {
+        cpu.add_cheat(cheat);
+    }
+    if let Some(info) = cpu.rom_info() {
+        eprintln!("{}", info.describe());
+    }
+    if let Some(path) = &args.script {
+        return run_script(&mut cpu, path).await;
+    }
+    CPU::run_with_listener(Rc::new(RefCell::new(cpu)), report).await;
+    Ok(())
+}
+
+fn report(event: EmulatorEvent) {
+    match event {
+        EmulatorEvent::Stopped(StopReason::Error(e)) => eprintln!("Error during cycle, {}. STOPPING", e),
+        EmulatorEvent::Sanitizer(report) => eprintln!("sanitizer: {}", report),
+        _ => {}
+    }
+}
+
+// 60 frames a second until the script or the ROM stops; script errors and failed assertions exit with 1
+#[cfg(feature = "scripting")]
+async fn run_script(cpu: &mut CPU, path: &str) -> std::io::Result<()> {
+    let mut script = fail_on_error(path, Script::new(&fs::read_to_string(path)?));
+    while !cpu.is_done() {
+        tokio::time::sleep(Duration::from_millis(1000 / 60)).await;
+        match script.run_frame(cpu) {
+            Ok(events) => events.into_iter().for_each(report),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
     Ok(())
 }
 
-fn read_rom() -> Vec<u8> {
-    let mut file = File::open("BLINKY").unwrap();
-    let mut r: Vec<u8> = Vec::new();
-    file.read_to_end(&mut r).unwrap();
-    return r;
-}
\ No newline at end of file
+#[cfg(not(feature = "scripting"))]
+async fn run_script(_cpu: &mut CPU, path: &str) -> std::io::Result<()> {
+    eprintln!("{}: scripts need the scripting feature, cargo run --features console,scripting", path);
+    process::exit(2);
+}
+
+fn fail_on_error<T, E: std::fmt::Display>(path: &str, result: Result<T, E>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        process::exit(2);
+    })
+}
+
+// hex with 0x, decimal otherwise
+fn parse_address(arg: &str) -> Option<u16> {
+    match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => arg.parse().ok(),
+    }
+}
+
+fn parse_platform(arg: &str) -> Option<Platform> {
+    match arg {
+        "chip8" => Some(Platform::Chip8),
+        "superchip" | "schip" => Some(Platform::SuperChip),
+        _ => None,
+    }
+}
+
+// ROM defaults to BLINKY in the working directory
+fn parse_args() -> Args {
+    let mut rom = None;
+    let mut db = None;
+    let mut load_address = None;
+    let mut platform = None;
+    let mut cfg = None;
+    let mut sanitizer: Option<SanitizerOptions> = None;
+    let mut cheats = None;
+    let mut cheat_codes = vec![];
+    let mut script = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--db" => db = Some(args.next().unwrap_or_else(|| usage())),
+            "--platform" => platform = Some(args.next().as_deref().and_then(parse_platform).unwrap_or_else(|| usage())),
+            "--load-address" => load_address = Some(args.next().as_deref().and_then(parse_address).unwrap_or_else(|| usage())),
+            "--cfg" => cfg = Some(args.next().filter(|format| format == "dot" || format == "json").unwrap_or_else(|| usage())),
+            "--sanitize" => sanitizer = Some(sanitizer.unwrap_or_default()),
+            "--random-ram" => sanitizer = Some(SanitizerOptions { randomize: true, ..sanitizer.unwrap_or_default() }),
+            "--cheats" => cheats = Some(args.next().unwrap_or_else(|| usage())),
+            "--cheat" => cheat_codes.push(args.next().and_then(|code| Cheat::parse(&code).ok()).unwrap_or_else(|| usage())),
+            "--script" => script = Some(args.next().unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => usage(),
+        }
+    }
+    Args { rom: rom.unwrap_or_else(|| "BLINKY".to_string()), db, load_address, platform, cfg, sanitizer, cheats, cheat_codes, script }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
@@ -0,0 +1,615 @@
+//! Assembler for the CHIP-8 / SUPER-CHIP subset of Octo (https://github.com/JohnEarnest/Octo),
+//! for the source embedded in Octo cartridges.
+//!
+//! Supported: labels, `:const`, `:alias`, `:org`, `:call`, `:byte`, bare numbers as data, all CHIP-8
+//! and SUPER-CHIP statements, `if ... then`, `if ... begin ... else ... end`, `loop ... while ... again`.
+//! Macros, `:calc`, `:unpack`, `<`/`>` comparisons and XO-CHIP statements are reported as errors.
+use std::collections::HashMap;
+use std::fmt;
+
+const START: usize = 0x200;
+const MEM_SIZE: usize = 4096;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    // 1-based
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+type Result<T> = std::result::Result<T, AsmError>;
+
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+#[derive(Clone, Copy)]
+enum Condition {
+    // skip opcodes for "skip the next instruction if this holds"
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+impl Condition {
+    fn negate(self) -> Self {
+        match self {
+            Condition::Equal(x, rhs) => Condition::NotEqual(x, rhs),
+            Condition::NotEqual(x, rhs) => Condition::Equal(x, rhs),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+        }
+    }
+
+    // the skip instruction that skips when the condition holds
+    fn skip_if(self) -> u16 {
+        let x = |x: u8| u16::from(x) << 8;
+        match self {
+            Condition::Equal(vx, Operand::Byte(n)) => 0x3000 | x(vx) | u16::from(n),
+            Condition::NotEqual(vx, Operand::Byte(n)) => 0x4000 | x(vx) | u16::from(n),
+            Condition::Equal(vx, Operand::Register(vy)) => 0x5000 | x(vx) | (u16::from(vy) << 4),
+            Condition::NotEqual(vx, Operand::Register(vy)) => 0x9000 | x(vx) | (u16::from(vy) << 4),
+            Condition::Key(vx) => 0xE09E | x(vx),
+            Condition::NotKey(vx) => 0xE0A1 | x(vx),
+        }
+    }
+}
+
+enum Block {
+    // position of the jump over the body, patched at else/end
+    If { jump: usize },
+    Else { jump: usize },
+    Loop { start: usize, exits: Vec<usize> },
+}
+
+struct Assembler<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    // from 0x200
+    rom: Vec<u8>,
+    labels: HashMap<&'a str, u16>,
+    constants: HashMap<&'a str, i32>,
+    aliases: HashMap<&'a str, u8>,
+    // 12-bit address fields waiting for a label: position of the opcode, label
+    fixups: Vec<(usize, Token<'a>)>,
+    blocks: Vec<(Block, usize)>,
+}
+
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        tokens.extend(code.split_whitespace().map(|text| Token { text, line: index + 1 }));
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i32::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+/** Assembles Octo source into a program for 0x200; execution starts at the label main */
+pub fn assemble(source: &str) -> Result<Vec<u8>> {
+    let mut asm = Assembler {
+        tokens: tokenize(source),
+        pos: 0,
+        rom: vec![],
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: vec![],
+        blocks: vec![],
+    };
+    // Octo puts a jump to main first
+    asm.emit_address(0x1000, Token { text: "main", line: 1 })?;
+    while asm.pos < asm.tokens.len() {
+        asm.statement()?;
+    }
+    if let Some((_, line)) = asm.blocks.last() {
+        return Err(AsmError { line: *line, message: "block without end or again".into() });
+    }
+    asm.resolve()?;
+    Ok(asm.rom)
+}
+
+impl<'a> Assembler<'a> {
+    fn error<T>(&self, token: Token, message: impl Into<String>) -> Result<T> {
+        Err(AsmError { line: token.line, message: message.into() })
+    }
+
+    fn next(&mut self) -> Result<Token<'a>> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(*token)
+            }
+            None => {
+                let line = self.tokens.last().map_or(1, |token| token.line);
+                Err(AsmError { line, message: "unexpected end of source".into() })
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|token| token.text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let token = self.next()?;
+        if token.text != text {
+            return self.error(token, format!("expected '{}', found '{}'", text, token.text));
+        }
+        Ok(())
+    }
+
+    fn here(&self) -> usize {
+        START + self.rom.len()
+    }
+
+    fn emit(&mut self, opcode: u16) -> Result<()> {
+        self.rom.extend_from_slice(&opcode.to_be_bytes());
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, token: Token, value: i32) -> Result<()> {
+        if !(-128..=255).contains(&value) {
+            return self.error(token, format!("{} doesn't fit in a byte", value));
+        }
+        self.rom.push(value as u8);
+        Ok(())
+    }
+
+    // opcode with a 12-bit address, possibly a label defined further down
+    fn emit_address(&mut self, opcode: u16, token: Token<'a>) -> Result<()> {
+        if let Some(addr) = self.address(token)? {
+            return self.emit(opcode | addr);
+        }
+        self.fixups.push((self.rom.len(), token));
+        self.emit(opcode)
+    }
+
+    // None for names not defined yet
+    fn address(&self, token: Token<'a>) -> Result<Option<u16>> {
+        let value = match self.value(token.text) {
+            Some(value) => value,
+            None if self.is_name(token.text) => return Ok(None),
+            None => return self.error(token, format!("'{}' isn't an address", token.text)),
+        };
+        if !(0..MEM_SIZE as i32).contains(&value) {
+            return self.error(token, format!("address {} out of range", value));
+        }
+        Ok(Some(value as u16))
+    }
+
+    fn is_name(&self, text: &str) -> bool {
+        !text.is_empty() && register(text).is_none() && text.chars().all(|c| c.is_alphanumeric() || "-_.".contains(c))
+    }
+
+    // numbers, constants and labels seen so far
+    fn value(&self, text: &str) -> Option<i32> {
+        parse_number(text)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|addr| i32::from(*addr)))
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        match self.value(token.text) {
+            Some(value) if (-128..=255).contains(&value) => Ok(value as u8),
+            Some(value) => self.error(token, format!("{} doesn't fit in a byte", value)),
+            None => self.error(token, format!("expected a number, found '{}'", token.text)),
+        }
+    }
+
+    fn nibble(&mut self) -> Result<u16> {
+        let token = self.next()?;
+        match self.value(token.text) {
+            Some(value) if (0..16).contains(&value) => Ok(value as u16),
+            _ => self.error(token, format!("expected 0-15, found '{}'", token.text)),
+        }
+    }
+
+    fn register_of(&self, token: Token) -> Option<u8> {
+        register(token.text).or_else(|| self.aliases.get(token.text).copied())
+    }
+
+    fn register(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        match self.register_of(token) {
+            Some(x) => Ok(x),
+            None => self.error(token, format!("expected a register, found '{}'", token.text)),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        let token = self.next()?;
+        match self.register_of(token) {
+            Some(y) => Ok(Operand::Register(y)),
+            None => {
+                self.pos -= 1;
+                Ok(Operand::Byte(self.byte()?))
+            }
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition> {
+        let x = self.register()?;
+        let op = self.next()?;
+        match op.text {
+            "==" => Ok(Condition::Equal(x, self.operand()?)),
+            "!=" => Ok(Condition::NotEqual(x, self.operand()?)),
+            "key" => Ok(Condition::Key(x)),
+            "-key" => Ok(Condition::NotKey(x)),
+            "<" | ">" | "<=" | ">=" => self.error(op, format!("'{}' comparisons aren't supported", op.text)),
+            _ => self.error(op, format!("unknown condition '{}'", op.text)),
+        }
+    }
+
+    fn patch_jump(&mut self, at: usize, target: usize) {
+        let opcode = 0x1000 | target as u16;
+        self.rom[at..at + 2].copy_from_slice(&opcode.to_be_bytes());
+    }
+
+    fn statement(&mut self) -> Result<()> {
+        let token = self.next()?;
+        let x = |x: u8| u16::from(x) << 8;
+        match token.text {
+            ":" => {
+                let name = self.next()?;
+                if !self.is_name(name.text) || self.labels.contains_key(name.text) {
+                    return self.error(name, format!("bad or duplicate label '{}'", name.text));
+                }
+                self.labels.insert(name.text, self.here() as u16);
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next()?;
+                match self.value(value.text) {
+                    Some(v) => self.constants.insert(name.text, v),
+                    None => return self.error(value, format!("expected a number, found '{}'", value.text)),
+                };
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let x = self.register()?;
+                self.aliases.insert(name.text, x);
+            }
+            ":org" => {
+                let addr = self.next()?;
+                match self.value(addr.text) {
+                    Some(addr) if addr as usize >= self.here() && (addr as usize) < MEM_SIZE => {
+                        self.rom.resize(addr as usize - START, 0);
+                    }
+                    _ => return self.error(addr, "only forward :org is supported"),
+                }
+            }
+            ":call" => {
+                let target = self.next()?;
+                self.emit_address(0x2000, target)?;
+            }
+            ":byte" => {
+                let value = self.next()?;
+                match self.value(value.text) {
+                    Some(v) => self.emit_byte(value, v)?,
+                    None => return self.error(value, format!("expected a number, found '{}'", value.text)),
+                }
+            }
+            // debugger directives, nothing to assemble
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            "clear" => self.emit(0x00E0)?,
+            "return" | ";" => self.emit(0x00EE)?,
+            "hires" => self.emit(0x00FF)?,
+            "lores" => self.emit(0x00FE)?,
+            "exit" => self.emit(0x00FD)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n)?;
+            }
+            "scroll-left" => self.emit(0x00FC)?,
+            "scroll-right" => self.emit(0x00FB)?,
+            "bcd" => {
+                let vx = self.register()?;
+                self.emit(0xF033 | x(vx))?;
+            }
+            "save" | "load" => {
+                let vx = self.register()?;
+                if self.peek() == Some("-") {
+                    return self.error(token, "register ranges are XO-CHIP");
+                }
+                self.emit(if token.text == "save" { 0xF055 } else { 0xF065 } | x(vx))?;
+            }
+            "saveflags" => {
+                let vx = self.register()?;
+                self.emit(0xF075 | x(vx))?;
+            }
+            "loadflags" => {
+                let vx = self.register()?;
+                self.emit(0xF085 | x(vx))?;
+            }
+            "sprite" => {
+                let vx = self.register()?;
+                let vy = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | x(vx) | (u16::from(vy) << 4) | n)?;
+            }
+            "jump" => {
+                let target = self.next()?;
+                self.emit_address(0x1000, target)?;
+            }
+            "jump0" => {
+                let target = self.next()?;
+                self.emit_address(0xB000, target)?;
+            }
+            "native" => {
+                let target = self.next()?;
+                self.emit_address(0x0000, target)?;
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let vx = self.register()?;
+                self.emit(if token.text == "delay" { 0xF015 } else { 0xF018 } | x(vx))?;
+            }
+            "i" => self.index()?,
+            "if" => {
+                let condition = self.condition()?;
+                let then = self.next()?;
+                match then.text {
+                    // the next statement runs only if the condition holds
+                    "then" => self.emit(condition.negate().skip_if())?,
+                    "begin" => {
+                        self.emit(condition.skip_if())?;
+                        self.blocks.push((Block::If { jump: self.rom.len() }, token.line));
+                        self.emit(0x1000)?;
+                    }
+                    _ => return self.error(then, "expected 'then' or 'begin'"),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some((Block::If { jump }, line)) => {
+                    let own = self.rom.len();
+                    self.emit(0x1000)?;
+                    self.patch_jump(jump, self.here());
+                    self.blocks.push((Block::Else { jump: own }, line));
+                }
+                _ => return self.error(token, "else without if ... begin"),
+            },
+            "end" => match self.blocks.pop() {
+                Some((Block::If { jump }, _)) | Some((Block::Else { jump }, _)) => self.patch_jump(jump, self.here()),
+                _ => return self.error(token, "end without if ... begin"),
+            },
+            "loop" => self.blocks.push((Block::Loop { start: self.here(), exits: vec![] }, token.line)),
+            "while" => {
+                let condition = self.condition()?;
+                // jump out unless the condition holds
+                self.emit(condition.skip_if())?;
+                let at = self.rom.len();
+                let exits = self.blocks.iter_mut().rev().find_map(|(block, _)| match block {
+                    Block::Loop { exits, .. } => Some(exits),
+                    _ => None,
+                });
+                match exits {
+                    Some(exits) => exits.push(at),
+                    None => return Err(AsmError { line: token.line, message: "while outside a loop".into() }),
+                }
+                self.emit(0x1000)?;
+            }
+            "again" => match self.blocks.pop() {
+                Some((Block::Loop { start, exits }, _)) => {
+                    self.emit(0x1000 | start as u16)?;
+                    for exit in exits {
+                        self.patch_jump(exit, self.here());
+                    }
+                }
+                _ => return self.error(token, "again without loop"),
+            },
+            ":macro" | ":calc" | ":unpack" | ":next" | ":assert" | ":stringmode" | ":pointer" | "scroll-up" | "plane"
+            | "audio" | "pitch" | ":org-long" => {
+                return self.error(token, format!("'{}' isn't supported", token.text));
+            }
+            _ => {
+                if let Some(vx) = self.register_of(token) {
+                    return self.assignment(vx);
+                }
+                if let Some(value) = parse_number(token.text).or_else(|| self.constants.get(token.text).copied()) {
+                    return self.emit_byte(token, value);
+                }
+                if self.is_name(token.text) {
+                    // a bare label calls it
+                    return self.emit_address(0x2000, token);
+                }
+                return self.error(token, format!("unexpected '{}'", token.text));
+            }
+        }
+        Ok(())
+    }
+
+    fn index(&mut self) -> Result<()> {
+        let op = self.next()?;
+        match op.text {
+            ":=" => {
+                let target = self.next()?;
+                match target.text {
+                    "hex" => {
+                        let vx = self.register()?;
+                        self.emit(0xF029 | (u16::from(vx) << 8))
+                    }
+                    "bighex" => {
+                        let vx = self.register()?;
+                        self.emit(0xF030 | (u16::from(vx) << 8))
+                    }
+                    "long" => self.error(target, "'i := long' is XO-CHIP"),
+                    _ => self.emit_address(0xA000, target),
+                }
+            }
+            "+=" => {
+                let vx = self.register()?;
+                self.emit(0xF01E | (u16::from(vx) << 8))
+            }
+            _ => self.error(op, format!("unknown operator 'i {}'", op.text)),
+        }
+    }
+
+    fn assignment(&mut self, vx: u8) -> Result<()> {
+        let x = u16::from(vx) << 8;
+        let op = self.next()?;
+        let alu = |op: u16, vy: u8| 0x8000 | x | (u16::from(vy) << 4) | op;
+        match op.text {
+            ":=" => {
+                let source = self.next()?;
+                match source.text {
+                    "key" => self.emit(0xF00A | x),
+                    "delay" => self.emit(0xF007 | x),
+                    "random" => {
+                        let mask = self.byte()?;
+                        self.emit(0xC000 | x | u16::from(mask))
+                    }
+                    _ => match self.register_of(source) {
+                        Some(vy) => self.emit(alu(0x0, vy)),
+                        None => {
+                            self.pos -= 1;
+                            let n = self.byte()?;
+                            self.emit(0x6000 | x | u16::from(n))
+                        }
+                    },
+                }
+            }
+            "+=" => match self.operand()? {
+                Operand::Register(vy) => self.emit(alu(0x4, vy)),
+                Operand::Byte(n) => self.emit(0x7000 | x | u16::from(n)),
+            },
+            "-=" => match self.operand()? {
+                Operand::Register(vy) => self.emit(alu(0x5, vy)),
+                Operand::Byte(n) => self.emit(0x7000 | x | u16::from(n.wrapping_neg())),
+            },
+            "=-" | "|=" | "&=" | "^=" | ">>=" | "<<=" => {
+                let vy = self.register()?;
+                let code = match op.text {
+                    "=-" => 0x7,
+                    "|=" => 0x1,
+                    "&=" => 0x2,
+                    "^=" => 0x3,
+                    ">>=" => 0x6,
+                    _ => 0xE,
+                };
+                self.emit(alu(code, vy))
+            }
+            _ => self.error(op, format!("unknown operator '{}'", op.text)),
+        }
+    }
+
+    fn resolve(&mut self) -> Result<()> {
+        for (at, token) in std::mem::take(&mut self.fixups) {
+            let addr = match self.labels.get(token.text) {
+                Some(addr) => *addr,
+                None => return self.error(token, format!("undefined name '{}'", token.text)),
+            };
+            let opcode = u16::from_be_bytes([self.rom[at], self.rom[at + 1]]) | addr;
+            self.rom[at..at + 2].copy_from_slice(&opcode.to_be_bytes());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn words(rom: &[u8]) -> Vec<u16> {
+    rom.chunks_exact(2).map(|w| u16::from_be_bytes([w[0], w[1]])).collect()
+}
+
+#[test]
+fn test_assemble_statements() {
+    let source = "
+        :alias x v3
+        :const SPEED 2
+        : main
+            clear
+            x := 0  v4 := random 0xFF  v4 += SPEED  v4 -= 1
+            i := digit  sprite x v4 5  i := hex v4
+            delay := x  vf := key  x |= v4  x >>= x
+            draw  jump main
+        : draw  # a call target further down
+            ;
+        : digit 0xF0 0x90 0xF0
+    ";
+    let rom = assemble(source).unwrap();
+    assert_eq!(
+        words(&rom[..0x1E]),
+        [
+            0x1202, 0x00E0, 0x6300, 0xC4FF, 0x7402, 0x74FF, 0xA220, 0xD345, 0xF429, 0xF315, 0xFF0A, 0x8341, 0x8336,
+            0x221E, 0x1202
+        ]
+    );
+    assert_eq!(&rom[0x1E..], &[0x00, 0xEE, 0xF0, 0x90, 0xF0]);
+}
+
+#[test]
+fn test_assemble_control_flow() {
+    let source = "
+        : main
+            if v0 == 1 then v1 := 2
+            loop
+                if v2 key begin v3 := 1 else v3 := 2 end
+                while v3 != 0
+            again
+    ";
+    assert_eq!(
+        words(&assemble(source).unwrap()),
+        [
+            0x1202, // jump main
+            0x4001, 0x6102, // skip unless v0 == 1
+            0xE29E, 0x120E, 0x6301, 0x1210, 0x6302, // if/else
+            0x4300, 0x1216, // while: leave unless v3 != 0
+            0x1206, // again
+        ]
+    );
+}
+
+#[test]
+fn test_assemble_errors() {
+    assert_eq!(assemble(": main\n  v0 := 300").unwrap_err(), AsmError { line: 2, message: "300 doesn't fit in a byte".into() });
+    assert_eq!(assemble(": main\n\n  if v0 < 3 then clear").unwrap_err().line, 3);
+    assert!(assemble(": main loop clear").is_err());
+    assert!(assemble("clear").unwrap_err().message.contains("main"));
+    assert!(assemble(":macro foo { clear }").is_err());
+}
@@ -0,0 +1,139 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::display_filter::{IntensityBuffer, INTENSITY_MAX};
+use crate::screen::{ScreenState, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::theme::{Rgba, Theme};
+
+const BYTES_PER_PIXEL: usize = 4;
+
+/**
+* RGBA pixel buffer, integer-scaled, laid out the way ImageData expects it
+*/
+pub struct PixelBuffer {
+    scale: usize,
+    data: Vec<u8>,
+}
+
+impl PixelBuffer {
+    pub fn new(scale: usize) -> Self {
+        let scale = scale.max(1);
+        Self {
+            scale,
+            data: vec![0; SCREEN_WIDTH * scale * SCREEN_HEIGHT * scale * BYTES_PER_PIXEL],
+        }
+    }
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+    pub fn width(&self) -> usize {
+        SCREEN_WIDTH * self.scale
+    }
+    pub fn height(&self) -> usize {
+        SCREEN_HEIGHT * self.scale
+    }
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    pub fn set_scale(&mut self, scale: usize) {
+        if scale.max(1) != self.scale {
+            *self = PixelBuffer::new(scale);
+        }
+    }
+    /**
+     * Renders one or more bitplanes; the palette index of a pixel is built from its plane bits
+     */
+    pub fn blit_planes(&mut self, planes: &[&ScreenState], theme: &Theme) {
+        self.blit(|x, y| planes.iter().enumerate().fold(0, |acc, (plane, state)| {
+            acc | ((state.get(x, y) as usize) << plane)
+        }), theme);
+    }
+    pub fn blit_screen_state(&mut self, state: &ScreenState, theme: &Theme) {
+        self.blit_planes(&[state], theme);
+    }
+    /**
+     * Renders display filter output, blending from the background to the foreground colour
+     */
+    pub fn blit_intensity(&mut self, intensity: &IntensityBuffer, theme: &Theme) {
+        let (bg, fg) = (theme.background(), theme.foreground());
+        let mix = |from: u8, to: u8, i: u8| {
+            ((from as u32 * (INTENSITY_MAX - i) as u32 + to as u32 * i as u32) / INTENSITY_MAX as u32) as u8
+        };
+        self.blit_colors(|x, y| {
+            let i = intensity[y * SCREEN_WIDTH + x];
+            Rgba(mix(bg.0, fg.0, i), mix(bg.1, fg.1, i), mix(bg.2, fg.2, i), mix(bg.3, fg.3, i))
+        });
+    }
+    fn blit(&mut self, palette_index: impl Fn(usize, usize) -> usize, theme: &Theme) {
+        self.blit_colors(|x, y| theme.color(palette_index(x, y)));
+    }
+    fn blit_colors(&mut self, color: impl Fn(usize, usize) -> Rgba) {
+        let row_len = self.width() * BYTES_PER_PIXEL;
+        let scale = self.scale;
+        for y in 0..SCREEN_HEIGHT {
+            let row_start = y * scale * row_len;
+            for x in 0..SCREEN_WIDTH {
+                let Rgba(r, g, b, a) = color(x, y);
+                let px_start = row_start + x * scale * BYTES_PER_PIXEL;
+                for px in self.data[px_start..px_start + scale * BYTES_PER_PIXEL].chunks_exact_mut(BYTES_PER_PIXEL) {
+                    px.copy_from_slice(&[r, g, b, a]);
+                }
+            }
+            // the rest of the scaled row is a copy of its first line
+            for line in 1..scale {
+                self.data.copy_within(row_start..row_start + row_len, row_start + line * row_len);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_pixel_buffer_scales_pixels() {
+    use crate::screen::make_zero_screen_state;
+    let mut state = make_zero_screen_state();
+    state.set(1, 0, true);
+    let theme = Theme::default();
+    let mut buffer = PixelBuffer::new(2);
+    buffer.blit_screen_state(&state, &theme);
+    let pixel = |x: usize, y: usize| {
+        let i = (y * buffer.width() + x) * BYTES_PER_PIXEL;
+        Rgba(buffer.data()[i], buffer.data()[i + 1], buffer.data()[i + 2], buffer.data()[i + 3])
+    };
+    assert_eq!(buffer.data().len(), 128 * 64 * 4);
+    assert_eq!(pixel(0, 0), theme.background());
+    assert_eq!(pixel(1, 1), theme.background());
+    for (x, y) in [(2, 0), (3, 0), (2, 1), (3, 1)] {
+        assert_eq!(pixel(x, y), theme.foreground());
+    }
+    assert_eq!(pixel(2, 2), theme.background());
+}
+
+#[test]
+fn test_pixel_buffer_bitplanes() {
+    use crate::screen::make_zero_screen_state;
+    let mut plane0 = make_zero_screen_state();
+    let mut plane1 = make_zero_screen_state();
+    plane0.set(0, 0, true);
+    plane1.set(0, 0, true);
+    plane1.set(1, 0, true);
+    let theme = Theme::default();
+    let mut buffer = PixelBuffer::new(1);
+    buffer.blit_planes(&[&plane0, &plane1], &theme);
+    let Rgba(r, g, b, a) = theme.color(3);
+    assert_eq!(&buffer.data()[0..4], &[r, g, b, a]);
+    let Rgba(r, g, b, a) = theme.color(2);
+    assert_eq!(&buffer.data()[4..8], &[r, g, b, a]);
+}
+
+#[test]
+fn test_pixel_buffer_intensity() {
+    let theme = Theme::from_colors(Rgba(200, 100, 0, 255), Rgba(0, 0, 0, 255));
+    let mut intensity = [0; SCREEN_WIDTH * SCREEN_HEIGHT];
+    intensity[0] = INTENSITY_MAX;
+    intensity[1] = 127;
+    let mut buffer = PixelBuffer::new(1);
+    buffer.blit_intensity(&intensity, &theme);
+    assert_eq!(&buffer.data()[0..4], &[200, 100, 0, 255]);
+    assert_eq!(&buffer.data()[4..8], &[99, 49, 0, 255]);
+    assert_eq!(&buffer.data()[8..12], &[0, 0, 0, 255]);
+}
@@ -0,0 +1,285 @@
+//! Execution profiler: where the instructions of a run go, see CPU::enable_profiler.
+//! Counts per address and per opcode class, draws per frame, and a call tree from CALL/RET
+//! for inclusive and exclusive counts per subroutine and folded stacks for flame graphs.
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::analysis::mnemonic;
+
+// about a minute at 60 Hz
+const FRAME_HISTORY: usize = 3600;
+// as deep as the CPU's stack goes before it wraps
+const MAX_DEPTH: usize = 16;
+
+/** Opcode pattern in the usual notation, e.g. "Dxyn" or "8xy4" */
+pub fn opcode_class(opcode: u16) -> &'static str {
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => "00E0",
+            0x00EE => "00EE",
+            0x00C0..=0x00CF => "00Cn",
+            0x00FB..=0x00FF => "00FB-00FF",
+            _ => "0nnn",
+        },
+        0x1000 => "1nnn",
+        0x2000 => "2nnn",
+        0x3000 => "3xkk",
+        0x4000 => "4xkk",
+        0x5000 => "5xy0",
+        0x6000 => "6xkk",
+        0x7000 => "7xkk",
+        0x8000 => match opcode & 0xF {
+            0x0 => "8xy0",
+            0x1 => "8xy1",
+            0x2 => "8xy2",
+            0x3 => "8xy3",
+            0x4 => "8xy4",
+            0x5 => "8xy5",
+            0x6 => "8xy6",
+            0x7 => "8xy7",
+            0xE => "8xyE",
+            _ => "8xy?",
+        },
+        0x9000 => "9xy0",
+        0xA000 => "Annn",
+        0xB000 => "Bnnn",
+        0xC000 => "Cxkk",
+        0xD000 => "Dxyn",
+        0xE000 => "Exkk",
+        _ => match opcode & 0xFF {
+            0x07 => "Fx07",
+            0x0A => "Fx0A",
+            0x15 => "Fx15",
+            0x18 => "Fx18",
+            0x1E => "Fx1E",
+            0x29 => "Fx29",
+            0x33 => "Fx33",
+            0x55 => "Fx55",
+            0x65 => "Fx65",
+            _ => "Fx??",
+        },
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub instructions: u32,
+    // DRW instructions, and the sprite rows they drew
+    pub draws: u32,
+    pub sprite_rows: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SubroutineStats {
+    pub calls: u64,
+    // instructions from the CALL's target up to its RET, nested calls included
+    pub inclusive: u64,
+    // the ones not inside nested calls
+    pub exclusive: u64,
+}
+
+#[derive(Clone, Debug)]
+struct Activation {
+    entry: u16,
+    // instruction count at the CALL
+    started: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    instructions: u64,
+    // by address: (last opcode seen there, executions)
+    by_pc: BTreeMap<u16, (u16, u64)>,
+    by_class: BTreeMap<&'static str, u64>,
+    frame: FrameStats,
+    frames: VecDeque<FrameStats>,
+    frame_count: u64,
+    subroutines: BTreeMap<u16, SubroutineStats>,
+    stack: Vec<Activation>,
+    // subroutine entries from the outermost, to instructions executed there
+    folded: BTreeMap<Vec<u16>, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    /** Counts the instruction at pc before it runs */
+    pub(crate) fn record(&mut self, pc: u16, opcode: u16) {
+        self.instructions += 1;
+        let entry = self.by_pc.entry(pc).or_insert((opcode, 0));
+        *entry = (opcode, entry.1 + 1);
+        *self.by_class.entry(opcode_class(opcode)).or_insert(0) += 1;
+        self.frame.instructions += 1;
+        if opcode & 0xF000 == 0xD000 {
+            self.frame.draws += 1;
+            self.frame.sprite_rows += u32::from(opcode & 0xF);
+        }
+        let path: Vec<u16> = self.stack.iter().map(|activation| activation.entry).collect();
+        *self.folded.entry(path).or_insert(0) += 1;
+        if let Some(top) = self.stack.last() {
+            self.subroutines.entry(top.entry).or_default().exclusive += 1;
+        }
+        match opcode & 0xF000 {
+            0x2000 => {
+                let entry = opcode & 0x0FFF;
+                self.subroutines.entry(entry).or_default().calls += 1;
+                // CALLs that are never returned from: keep the innermost
+                if self.stack.len() == MAX_DEPTH {
+                    self.stack.remove(0);
+                }
+                self.stack.push(Activation { entry, started: self.instructions });
+            }
+            // RET counts to the subroutine it leaves; one without a CALL seen is ignored
+            0x0000 if opcode == 0x00EE => {
+                if let Some(activation) = self.stack.pop() {
+                    self.subroutines.entry(activation.entry).or_default().inclusive += self.instructions - activation.started;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        if self.frames.len() == FRAME_HISTORY {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(core::mem::take(&mut self.frame));
+        self.frame_count += 1;
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /** Frames run since the profiler was enabled */
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /** Executions at an address */
+    pub fn count(&self, pc: u16) -> u64 {
+        self.by_pc.get(&pc).map_or(0, |(_, count)| *count)
+    }
+
+    /** The n most executed addresses as (address, opcode, executions), most first */
+    pub fn hotspots(&self, n: usize) -> Vec<(u16, u16, u64)> {
+        let mut hotspots: Vec<(u16, u16, u64)> = self.by_pc.iter().map(|(pc, (opcode, count))| (*pc, *opcode, *count)).collect();
+        hotspots.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        hotspots.truncate(n);
+        hotspots
+    }
+
+    /** Executions per opcode_class, most first */
+    pub fn classes(&self) -> Vec<(&'static str, u64)> {
+        let mut classes: Vec<(&'static str, u64)> = self.by_class.iter().map(|(class, count)| (*class, *count)).collect();
+        classes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        classes
+    }
+
+    /** The last minute or so of frames, oldest first */
+    pub fn frames(&self) -> impl Iterator<Item = &FrameStats> {
+        self.frames.iter()
+    }
+
+    /** By entry address; inclusive counts only cover calls that have returned */
+    pub fn subroutines(&self) -> &BTreeMap<u16, SubroutineStats> {
+        &self.subroutines
+    }
+
+    /**
+     * Folded stacks, one "main;sub_20a;sub_2f0 count" line per call path with its exclusive
+     * instructions, as flamegraph.pl and inferno take them
+     */
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for (path, count) in &self.folded {
+            folded.push_str("main");
+            for entry in path {
+                let _ = write!(folded, ";sub_{:03x}", entry);
+            }
+            let _ = writeln!(folded, " {}", count);
+        }
+        folded
+    }
+
+    /** Human-readable summary: hotspots, opcode classes, draws per frame and subroutines */
+    pub fn report(&self) -> String {
+        let mut report = format!("{} instructions in {} frames\n\nhotspots:\n", self.instructions, self.frame_count);
+        for (pc, opcode, count) in self.hotspots(20) {
+            let _ = writeln!(report, "  {:#05x}  {:04X}  {:<16} {:>10}  {:5.1}%", pc, opcode, mnemonic(opcode), count, self.percent(count));
+        }
+        report.push_str("\nopcode classes:\n");
+        for (class, count) in self.classes() {
+            let _ = writeln!(report, "  {:<10} {:>10}  {:5.1}%", class, count, self.percent(count));
+        }
+        if !self.frames.is_empty() {
+            let n = self.frames.len() as f64;
+            let average = |field: fn(&FrameStats) -> u32| self.frames.iter().map(|frame| f64::from(field(frame))).sum::<f64>() / n;
+            let max = |field: fn(&FrameStats) -> u32| self.frames.iter().map(field).max().unwrap_or(0);
+            let _ = writeln!(report, "\nper frame, last {} frames (average / max):", self.frames.len());
+            let _ = writeln!(report, "  instructions {:8.1} / {}", average(|f| f.instructions), max(|f| f.instructions));
+            let _ = writeln!(report, "  draws        {:8.1} / {}", average(|f| f.draws), max(|f| f.draws));
+            let _ = writeln!(report, "  sprite rows  {:8.1} / {}", average(|f| f.sprite_rows), max(|f| f.sprite_rows));
+        }
+        if !self.subroutines.is_empty() {
+            let _ = writeln!(report, "\nsubroutines:\n  {:<8} {:>8} {:>12} {:>12}", "entry", "calls", "inclusive", "exclusive");
+            for (entry, stats) in &self.subroutines {
+                let _ = writeln!(report, "  {:#05x}    {:>8} {:>12} {:>12}", entry, stats.calls, stats.inclusive, stats.exclusive);
+            }
+        }
+        report
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        count as f64 * 100.0 / self.instructions.max(1) as f64
+    }
+}
+
+#[test]
+fn test_call_tree() {
+    let mut profiler = Profiler::new();
+    // 0x200: CALL 0x210, 0x210: CALL 0x220, 0x220: RET, 0x212: RET, 0x202: JP 0x202
+    for (pc, opcode) in [(0x200, 0x2210), (0x210, 0x2220), (0x220, 0x00EE), (0x212, 0x00EE), (0x202, 0x1202)] {
+        profiler.record(pc, opcode);
+    }
+    profiler.record(0x202, 0x1202);
+    let subroutines = profiler.subroutines();
+    assert_eq!(subroutines[&0x210], SubroutineStats { calls: 1, inclusive: 3, exclusive: 2 });
+    assert_eq!(subroutines[&0x220], SubroutineStats { calls: 1, inclusive: 1, exclusive: 1 });
+    assert_eq!(profiler.folded(), "main 3\nmain;sub_210 2\nmain;sub_210;sub_220 1\n");
+    assert_eq!(profiler.hotspots(1), vec![(0x202, 0x1202, 2)]);
+    assert_eq!(profiler.classes(), vec![("00EE", 2), ("1nnn", 2), ("2nnn", 2)]);
+}
+
+#[test]
+fn test_frames() {
+    let mut profiler = Profiler::new();
+    profiler.record(0x200, 0xD125);
+    profiler.record(0x202, 0xD12F);
+    profiler.record(0x204, 0x1204);
+    profiler.end_frame();
+    assert_eq!(profiler.frames().next(), Some(&FrameStats { instructions: 3, draws: 2, sprite_rows: 20 }));
+    assert!(profiler.report().contains("3 instructions in 1 frames"));
+}
+
+#[test]
+fn test_queued_profiler_starts_with_the_next_frame() {
+    use crate::command_queue::Command;
+    use crate::cpu::CPU;
+    use crate::headless_screen::HeadlessScreen;
+
+    let mut cpu = CPU::new(Box::new(HeadlessScreen::new()));
+    cpu.load_program(vec![0x12, 0x00]).unwrap();
+    cpu.command_queue().push(Command::EnableProfiler);
+    assert!(cpu.profiler().is_none());
+    cpu.run_frame_now();
+    assert_eq!(cpu.profiler().unwrap().frames().count(), 1);
+    cpu.command_queue().push(Command::DisableProfiler);
+    cpu.run_frame_now();
+    assert!(cpu.profiler().is_none());
+}
@@ -0,0 +1,130 @@
+//! Memory provenance: which instruction last wrote each byte, and a history of the writes to
+//! watched ranges, see CPU::enable_provenance. Bytes the loader put there have no writer.
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+const MEM_SIZE: usize = 4096;
+// watched writes kept, oldest dropped first
+const HISTORY_SIZE: usize = 4096;
+/** pc and opcode of the writes pokes and freezes make; no instruction sits there, PC is 12 bits */
+pub const CHEAT: u16 = 0xFFFF;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryWrite {
+    pub addr: u16,
+    pub value: u8,
+    // the writing instruction, CHEAT for pokes and freezes
+    pub pc: u16,
+    pub opcode: u16,
+    // frames run since provenance was enabled
+    pub frame: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Provenance {
+    last: Vec<Option<MemoryWrite>>,
+    frame: u64,
+    watches: Vec<Range<u16>>,
+    history: VecDeque<MemoryWrite>,
+}
+
+impl Default for Provenance {
+    fn default() -> Self {
+        Provenance { last: vec![None; MEM_SIZE], frame: 0, watches: vec![], history: VecDeque::new() }
+    }
+}
+
+impl Provenance {
+    pub fn new() -> Self {
+        Provenance::default()
+    }
+
+    pub(crate) fn record(&mut self, addr: u16, value: u8, pc: u16, opcode: u16) {
+        let write = MemoryWrite { addr, value, pc, opcode, frame: self.frame };
+        if let Some(last) = self.last.get_mut(usize::from(addr)) {
+            *last = Some(write);
+        }
+        if self.watches.iter().any(|range| range.contains(&addr)) {
+            if self.history.len() == HISTORY_SIZE {
+                self.history.pop_front();
+            }
+            self.history.push_back(write);
+        }
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    // a power cycle reloads memory, so nothing has a writer anymore; watches and history stay
+    pub(crate) fn power_on(&mut self) {
+        self.last.iter_mut().for_each(|last| *last = None);
+    }
+
+    /** The instruction that last wrote addr; None if nothing has since it was loaded */
+    pub fn last_writer(&self, addr: u16) -> Option<MemoryWrite> {
+        self.last.get(usize::from(addr)).copied().flatten()
+    }
+
+    /** Keeps the writes to start..end from now on */
+    pub fn watch(&mut self, start: u16, end: u16) {
+        self.watches.push(start..end);
+    }
+
+    pub fn unwatch(&mut self, start: u16, end: u16) {
+        self.watches.retain(|range| *range != (start..end));
+    }
+
+    /** Writes to watched ranges, oldest first; only the last 4096 are kept */
+    pub fn history(&self) -> impl Iterator<Item = &MemoryWrite> {
+        self.history.iter()
+    }
+
+    /** The history within start..end */
+    pub fn history_of(&self, start: u16, end: u16) -> Vec<MemoryWrite> {
+        self.history.iter().filter(|write| (start..end).contains(&write.addr)).copied().collect()
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+}
+
+#[test]
+fn test_last_writer_and_history() {
+    let mut provenance = Provenance::new();
+    provenance.watch(0x300, 0x302);
+    provenance.record(0x300, 1, 0x204, 0xF155);
+    provenance.end_frame();
+    provenance.record(0x300, 2, 0x20A, 0xF255);
+    provenance.record(0x400, 3, 0x20A, 0xF255);
+    assert_eq!(provenance.last_writer(0x300), Some(MemoryWrite { addr: 0x300, value: 2, pc: 0x20A, opcode: 0xF255, frame: 1 }));
+    assert_eq!(provenance.last_writer(0x301), None);
+    assert_eq!(provenance.history().map(|write| (write.value, write.frame)).collect::<Vec<_>>(), vec![(1, 0), (2, 1)]);
+    assert_eq!(provenance.history_of(0x301, 0x302), vec![]);
+    provenance.power_on();
+    assert_eq!(provenance.last_writer(0x400), None);
+    assert_eq!(provenance.history().count(), 2);
+}
+
+#[test]
+fn test_queued_watch() {
+    use crate::command_queue::Command;
+    use crate::cpu::CPU;
+    use crate::headless_screen::HeadlessScreen;
+
+    let mut cpu = CPU::new(Box::new(HeadlessScreen::new()));
+    cpu.load_program(vec![
+        0xA3, 0x00, // LD I, 0x300
+        0xF0, 0x55, // LD [I], V0
+        0x12, 0x04, // JP 0x204
+    ])
+    .unwrap();
+    cpu.command_queue().push(Command::WatchWrites(0x300, 0x301));
+    assert!(cpu.provenance().is_none());
+    cpu.run_frame_now();
+    let history = cpu.write_history(0x300, 0x301);
+    assert_eq!(history, vec![MemoryWrite { addr: 0x300, value: 0, pc: 0x202, opcode: 0xF055, frame: 0 }]);
+}
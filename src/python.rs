@@ -0,0 +1,281 @@
+//! Python module `rust_wasm_chip8`: `maturin develop` (see pyproject.toml) builds and installs it.
+//! Wraps Chip8, i.e. the CPU and its CPUState; nothing runs on its own, the script calls step_frame().
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use crate::chip8::{Chip8, Platform};
+use crate::env::{Env, GameSpec};
+use crate::events::EmulatorEvent;
+use crate::screen::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+fn platform(name: &str) -> PyResult<Platform> {
+    match name {
+        "chip8" => Ok(Platform::Chip8),
+        "superchip" => Ok(Platform::SuperChip),
+        _ => Err(PyValueError::new_err(format!("unknown platform {}, expected chip8 or superchip", name))),
+    }
+}
+
+fn event_name(event: &EmulatorEvent) -> String {
+    match event {
+        EmulatorEvent::FrameCompleted => "frame".to_string(),
+        EmulatorEvent::SoundOn => "sound_on".to_string(),
+        EmulatorEvent::SoundOff => "sound_off".to_string(),
+        EmulatorEvent::WaitingForKey => "waiting_for_key".to_string(),
+        EmulatorEvent::BreakpointHit(pc) => format!("breakpoint:{:#05x}", pc),
+        EmulatorEvent::Stopped(reason) => format!("stopped:{}", reason.describe()),
+        EmulatorEvent::Sanitizer(report) => format!("sanitizer:{}", report),
+    }
+}
+
+/**
+* Pixels of one frame, one byte (0 or 1) each; `numpy.asarray(fb)` gives a (height, width) uint8 array
+*/
+#[pyclass]
+pub struct Framebuffer {
+    pixels: Vec<u8>,
+}
+
+#[pymethods]
+impl Framebuffer {
+    #[getter]
+    fn width(&self) -> usize {
+        SCREEN_WIDTH
+    }
+
+    #[getter]
+    fn height(&self) -> usize {
+        SCREEN_HEIGHT
+    }
+
+    fn tobytes<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.pixels)
+    }
+
+    fn __len__(&self) -> usize {
+        self.pixels.len()
+    }
+
+    // the NumPy array interface, version 3; data is a bytes object, so the array is a read-only copy
+    #[getter(__array_interface__)]
+    fn array_interface<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let interface = PyDict::new(py);
+        interface.set_item("shape", (SCREEN_HEIGHT, SCREEN_WIDTH))?;
+        interface.set_item("typestr", "|u1")?;
+        interface.set_item("data", PyBytes::new(py, &self.pixels))?;
+        interface.set_item("version", 3)?;
+        Ok(interface)
+    }
+}
+
+#[pyclass(unsendable)]
+pub struct Emulator {
+    chip8: Chip8,
+}
+
+#[pymethods]
+impl Emulator {
+    #[new]
+    // without a platform, the ROM's code picks one
+    #[pyo3(signature = (rom, seed = None, clock = 600, platform = None))]
+    fn new(rom: &[u8], seed: Option<u64>, clock: u32, platform: Option<&str>) -> PyResult<Self> {
+        let mut builder = Chip8::builder().rom(rom).clock(clock);
+        if let Some(platform) = platform {
+            builder = builder.platform(self::platform(platform)?);
+        }
+        if let Some(seed) = seed {
+            builder = builder.seed(seed);
+        }
+        let chip8 = builder.build().map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(Emulator { chip8 })
+    }
+
+    /** Runs one 60 Hz frame; returns the names of the events it raised */
+    fn step_frame(&mut self) -> Vec<String> {
+        self.chip8.run_frame().iter().map(event_name).collect()
+    }
+
+    /** Runs n frames, returning whether the emulator is still running */
+    fn step_frames(&mut self, n: usize) -> bool {
+        for _ in 0..n {
+            if self.chip8.is_stopped() {
+                break;
+            }
+            self.chip8.run_frame();
+        }
+        !self.chip8.is_stopped()
+    }
+
+    /** A single instruction, without ticking the timers */
+    fn step(&mut self) -> PyResult<()> {
+        self.chip8.step().map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    fn key_down(&mut self, key: u8) {
+        self.chip8.key_down(key);
+    }
+
+    fn key_up(&mut self, key: u8) {
+        self.chip8.key_up(key);
+    }
+
+    fn reset(&mut self) {
+        self.chip8.reset();
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) -> PyResult<()> {
+        self.chip8.load_rom(rom).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.chip8.seed_rng(seed);
+    }
+
+    /** V0..VF */
+    #[getter]
+    fn registers(&self) -> Vec<u8> {
+        self.chip8.registers().to_vec()
+    }
+
+    fn set_register(&mut self, index: usize, value: u8) -> PyResult<()> {
+        if index >= 16 {
+            return Err(PyValueError::new_err("register index out of range"));
+        }
+        self.chip8.set_register(index, value);
+        Ok(())
+    }
+
+    #[getter]
+    fn pc(&self) -> u16 {
+        self.chip8.pc()
+    }
+
+    #[getter]
+    fn i(&self) -> u16 {
+        self.chip8.i()
+    }
+
+    #[getter]
+    fn sp(&self) -> u8 {
+        self.chip8.sp()
+    }
+
+    #[getter]
+    fn stack(&self) -> Vec<u16> {
+        self.chip8.stack().to_vec()
+    }
+
+    #[getter]
+    fn delay_timer(&self) -> u8 {
+        self.chip8.delay_timer()
+    }
+
+    #[getter]
+    fn sound_timer(&self) -> u8 {
+        self.chip8.sound_timer()
+    }
+
+    #[getter]
+    fn sound_on(&self) -> bool {
+        self.chip8.is_sound_on()
+    }
+
+    #[getter]
+    fn waiting_for_key(&self) -> bool {
+        self.chip8.is_waiting_for_key()
+    }
+
+    /** length bytes from start, cut at the end of the 4 KiB memory; everything by default */
+    #[pyo3(signature = (start = 0, length = None))]
+    fn memory<'py>(&self, py: Python<'py>, start: usize, length: Option<usize>) -> &'py PyBytes {
+        let memory = self.chip8.memory();
+        let start = start.min(memory.len());
+        let end = length.map(|l| start.saturating_add(l).min(memory.len())).unwrap_or(memory.len());
+        PyBytes::new(py, &memory[start..end])
+    }
+
+    fn write_memory(&mut self, addr: u16, data: &[u8]) -> PyResult<()> {
+        if addr as usize + data.len() > self.chip8.memory().len() {
+            return Err(PyValueError::new_err("write past the end of memory"));
+        }
+        for (offset, value) in data.iter().enumerate() {
+            self.chip8.poke(addr + offset as u16, *value);
+        }
+        Ok(())
+    }
+
+    fn framebuffer(&self) -> Framebuffer {
+        Framebuffer { pixels: self.chip8.pixels().to_bytes() }
+    }
+
+    fn save_state<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.chip8.save_state())
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        self.chip8.load_state(state).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+}
+
+/**
+* Env(game, rom, frame_skip=4) for a bundled game definition ("blinky", "catch");
+* step(action) returns (observation, reward, done)
+*/
+#[pyclass(name = "Env", unsendable)]
+pub struct PyEnv {
+    env: Env,
+}
+
+#[pymethods]
+impl PyEnv {
+    #[new]
+    #[pyo3(signature = (game, rom, frame_skip = 4))]
+    fn new(game: &str, rom: &[u8], frame_skip: usize) -> PyResult<Self> {
+        let spec = GameSpec::bundled(game).ok_or_else(|| PyValueError::new_err(format!("unknown game {}", game)))?;
+        let mut env = Env::new(spec, rom).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        env.set_frame_skip(frame_skip);
+        Ok(PyEnv { env })
+    }
+
+    fn reset(&mut self, seed: u64) -> Framebuffer {
+        Framebuffer { pixels: self.env.reset(seed).to_bytes() }
+    }
+
+    fn step(&mut self, action: usize) -> PyResult<(Framebuffer, i64, bool)> {
+        if action >= self.env.action_count() {
+            return Err(PyValueError::new_err("action out of range"));
+        }
+        let step = self.env.step(action);
+        Ok((Framebuffer { pixels: step.observation.to_bytes() }, step.reward, step.done))
+    }
+
+    #[getter]
+    fn action_count(&self) -> usize {
+        self.env.action_count()
+    }
+
+    /** Keypad bitmask of each action */
+    #[getter]
+    fn actions(&self) -> Vec<u16> {
+        self.env.spec().actions.clone()
+    }
+
+    #[getter]
+    fn score(&self) -> u32 {
+        self.env.score()
+    }
+
+    #[getter]
+    fn lives(&self) -> Option<u32> {
+        self.env.lives()
+    }
+}
+
+#[pymodule]
+fn rust_wasm_chip8(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Emulator>()?;
+    m.add_class::<Framebuffer>()?;
+    m.add_class::<PyEnv>()?;
+    Ok(())
+}
@@ -0,0 +1,232 @@
+//! ROM files: format detection, load addresses and the settings a file carries with it.
+//! Rom::parse is what init_program and the CLI load through.
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::analysis::{self, Detection, Variant};
+use crate::chip8::Platform;
+use crate::cpu::CPUQuirks;
+use crate::theme::Theme;
+
+const MEM_SIZE: usize = 4096;
+pub const PROGRAM_START_ADDR: u16 = 0x200;
+// ETI-660 interpreters keep the program after their own code
+pub const ETI_660_LOAD_ADDRESS: u16 = 0x600;
+// 64x64 "hires" CHIP-8 variants, with the display routines before the program
+pub const HIRES_LOAD_ADDRESS: u16 = 0x2C0;
+pub const HIRES_ALT_LOAD_ADDRESS: u16 = 0x2E0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RomFormat {
+    // .ch8, and anything not recognised
+    Chip8,
+    // .sc8
+    SuperChip,
+    // .xo8; XO-CHIP instructions aren't emulated
+    XoChip,
+    // .gif with the program's Octo source and options hidden in the pixels
+    OctoCartridge,
+}
+
+impl RomFormat {
+    pub fn from_extension(name: &str) -> Option<Self> {
+        let extension = name.rsplit_once('.')?.1.to_ascii_lowercase();
+        match extension.as_str() {
+            "ch8" | "c8" => Some(RomFormat::Chip8),
+            "sc8" => Some(RomFormat::SuperChip),
+            "xo8" => Some(RomFormat::XoChip),
+            "gif" => Some(RomFormat::OctoCartridge),
+            _ => None,
+        }
+    }
+
+    /** GIF data is a cartridge whatever the name; otherwise the extension decides, CHIP-8 without one */
+    pub fn detect(name: Option<&str>, data: &[u8]) -> Self {
+        if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            return RomFormat::OctoCartridge;
+        }
+        match name.and_then(RomFormat::from_extension) {
+            Some(RomFormat::OctoCartridge) | None => RomFormat::Chip8,
+            Some(format) => format,
+        }
+    }
+
+    pub fn platform(&self) -> Option<Platform> {
+        match self {
+            RomFormat::Chip8 => Some(Platform::Chip8),
+            RomFormat::SuperChip => Some(Platform::SuperChip),
+            RomFormat::XoChip | RomFormat::OctoCartridge => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    TooLarge { size: usize, max: usize },
+    // programs go between the interpreter area (below 0x200) and the end of memory
+    BadLoadAddress(u16),
+    BadCartridge(String),
+    // Octo source from a cartridge that didn't assemble
+    Assembly { line: usize, message: String },
+    // needs a cargo feature that's off, e.g. "octo" for cartridges
+    Unsupported(RomFormat),
+    // reachable code needs instructions that aren't emulated; the first one found
+    UnsupportedVariant { variant: Variant, addr: u16, opcode: u16 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::TooLarge { size, max } => write!(f, "ROM takes {} bytes, at most {} fit", size, max),
+            LoadError::BadLoadAddress(addr) => write!(f, "can't load a program at {:#05x}", addr),
+            LoadError::BadCartridge(e) => write!(f, "not an Octo cartridge: {}", e),
+            LoadError::Assembly { line, message } => write!(f, "cartridge source, line {}: {}", line, message),
+            LoadError::Unsupported(format) => write!(f, "{:?} ROMs aren't supported in this build", format),
+            LoadError::UnsupportedVariant { variant, addr, opcode } => {
+                write!(f, "{:?} instruction {:04X} at {:#05x} isn't emulated", variant, opcode, addr)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LoadError {}
+
+/**
+* A program ready for CPU::load: the bytes, where they go, and the settings the file asks for.
+* Settings left None keep whatever the CPU has
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rom {
+    pub program: Vec<u8>,
+    pub format: RomFormat,
+    pub load_address: u16,
+    pub platform: Option<Platform>,
+    pub quirks: Option<CPUQuirks>,
+    // instructions per 60 Hz frame
+    pub tickrate: Option<u32>,
+    pub theme: Option<Theme>,
+}
+
+/** Whether size bytes fit in memory from addr */
+pub fn check_fits(size: usize, addr: u16) -> Result<(), LoadError> {
+    let addr = usize::from(addr);
+    if addr < usize::from(PROGRAM_START_ADDR) || addr >= MEM_SIZE {
+        return Err(LoadError::BadLoadAddress(addr as u16));
+    }
+    if size > MEM_SIZE - addr {
+        return Err(LoadError::TooLarge { size, max: MEM_SIZE - addr });
+    }
+    Ok(())
+}
+
+impl Rom {
+    /** Plain bytes, loaded at 0x200 with no settings of their own */
+    pub fn new(program: Vec<u8>) -> Self {
+        Rom {
+            program,
+            format: RomFormat::Chip8,
+            load_address: PROGRAM_START_ADDR,
+            platform: None,
+            quirks: None,
+            tickrate: None,
+            theme: None,
+        }
+    }
+
+    /**
+     * Reads a ROM file; name (a path or file name) only serves to detect the format.
+     * Raw formats load at 0x200, see at() for the others. Their platform is the one of the extension;
+     * code that needs SUPER-CHIP or XO-CHIP instructions has none, and check_variant() reports it
+     */
+    pub fn parse(name: Option<&str>, data: &[u8]) -> Result<Self, LoadError> {
+        let format = RomFormat::detect(name, data);
+        let rom = match format {
+            RomFormat::OctoCartridge => Rom::from_cartridge(data)?,
+            _ => {
+                let mut rom = Rom { format, ..Rom::new(data.to_vec()) };
+                rom.platform = rom.detected_platform();
+                rom
+            }
+        };
+        check_fits(rom.program.len(), rom.load_address)?;
+        Ok(rom)
+    }
+
+    #[cfg(feature = "octo")]
+    fn from_cartridge(data: &[u8]) -> Result<Self, LoadError> {
+        crate::cartridge::read(data)?.into_rom()
+    }
+
+    #[cfg(not(feature = "octo"))]
+    fn from_cartridge(_data: &[u8]) -> Result<Self, LoadError> {
+        Err(LoadError::Unsupported(RomFormat::OctoCartridge))
+    }
+
+    /** The instruction set the reachable code uses */
+    pub fn detect(&self) -> Detection {
+        analysis::detect(&self.program, self.load_address)
+    }
+
+    /** Whether the reachable code sticks to the CHIP-8 instructions the CPU has */
+    pub fn check_variant(&self) -> Result<(), LoadError> {
+        let detection = self.detect();
+        match detection.evidence.first() {
+            Some(&(addr, opcode)) => Err(LoadError::UnsupportedVariant { variant: detection.variant, addr, opcode }),
+            None => Ok(()),
+        }
+    }
+
+    fn detected_platform(&self) -> Option<Platform> {
+        match self.detect().variant {
+            Variant::Chip8 => self.format.platform(),
+            variant => variant.platform(),
+        }
+    }
+
+    /** Loads the program at addr instead, e.g. ETI_660_LOAD_ADDRESS; jump targets change, so raw formats are scanned again */
+    pub fn at(mut self, addr: u16) -> Result<Self, LoadError> {
+        check_fits(self.program.len(), addr)?;
+        self.load_address = addr;
+        if self.format != RomFormat::OctoCartridge {
+            self.platform = self.detected_platform();
+        }
+        Ok(self)
+    }
+}
+
+#[test]
+fn test_detect_format() {
+    assert_eq!(RomFormat::detect(Some("roms/BLINKY.CH8"), &[0x12, 0x00]), RomFormat::Chip8);
+    assert_eq!(RomFormat::detect(Some("car.sc8"), &[]), RomFormat::SuperChip);
+    assert_eq!(RomFormat::detect(Some("a.b/c.xo8"), &[]), RomFormat::XoChip);
+    assert_eq!(RomFormat::detect(Some("BLINKY"), &[]), RomFormat::Chip8);
+    assert_eq!(RomFormat::detect(None, b"GIF89a..."), RomFormat::OctoCartridge);
+    // a .gif that isn't one is just bytes
+    assert_eq!(RomFormat::detect(Some("x.gif"), &[0x12, 0x00]), RomFormat::Chip8);
+}
+
+#[test]
+fn test_platform_from_code() {
+    // HIGH, then JP 0x202
+    let hires = Rom::parse(Some("game.sc8"), &[0x00, 0xFF, 0x12, 0x02]).unwrap();
+    assert_eq!(hires.platform, None);
+    assert_eq!(hires.check_variant(), Err(LoadError::UnsupportedVariant { variant: Variant::SuperChip, addr: 0x200, opcode: 0x00FF }));
+    assert_eq!(Rom::parse(Some("game.sc8"), &[0x12, 0x00]).unwrap().platform, Some(Platform::SuperChip));
+    assert_eq!(Rom::parse(None, &[0x12, 0x00]).unwrap().platform, Some(Platform::Chip8));
+    assert_eq!(Rom::parse(None, &[0x12, 0x00]).unwrap().check_variant(), Ok(()));
+    // F000 nnnn
+    assert_eq!(Rom::parse(None, &[0xF0, 0x00, 0x02, 0x00]).unwrap().platform, None);
+}
+
+#[test]
+fn test_load_addresses() {
+    let rom = Rom::parse(Some("game.sc8"), &[0x12, 0x00]).unwrap();
+    assert_eq!((rom.load_address, rom.platform), (PROGRAM_START_ADDR, Some(Platform::SuperChip)));
+    assert_eq!(rom.clone().at(ETI_660_LOAD_ADDRESS).unwrap().load_address, 0x600);
+    assert_eq!(rom.clone().at(0x100), Err(LoadError::BadLoadAddress(0x100)));
+    assert!(Rom::new(vec![0; 3000]).at(HIRES_ALT_LOAD_ADDRESS).is_ok());
+    assert_eq!(Rom::new(vec![0; 3361]).at(HIRES_ALT_LOAD_ADDRESS), Err(LoadError::TooLarge { size: 3361, max: 3360 }));
+    assert_eq!(Rom::parse(None, &[0; 3585]), Err(LoadError::TooLarge { size: 3585, max: 3584 }));
+}
@@ -0,0 +1,301 @@
+//! ROM database: per-game settings looked up by SHA-1, read from the `programs.json`
+//! of the community chip-8-database (https://github.com/chip-8/chip-8-database).
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use crate::chip8::Platform;
+use crate::cpu::CPUQuirks;
+use crate::keyboard::{KeyMap, KEY_DOWN, KEY_ENTER, KEY_LEFT, KEY_RIGHT, KEY_SPACE, KEY_UP};
+use crate::theme::{Rgba, Theme, PALETTE_SIZE};
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    description: Option<String>,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<u32>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, Quirks>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Quirks {
+    shift: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+}
+
+/**
+* What the database knows about one ROM; settings it doesn't mention are None and left alone
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub description: Option<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<CPUQuirks>,
+    // instructions per 60 Hz frame
+    pub tickrate: Option<u32>,
+    // None if the database binds none of the game's keys
+    pub keys: Option<KeyMap>,
+    pub theme: Option<Theme>,
+}
+
+#[derive(Debug)]
+pub enum RomDbError {
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for RomDbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomDbError::Json(e) => write!(f, "invalid ROM database: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RomDbError {}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RomDatabase {
+    // lowercase hex SHA-1
+    roms: HashMap<String, RomInfo>,
+}
+
+pub fn sha1_hex(data: &[u8]) -> String {
+    Sha1::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// platform ids of the database; the ones running on neither of ours (XO-CHIP, MegaChip, CHIP-8X) are skipped
+fn platform(id: &str) -> Option<Platform> {
+    match id {
+        "originalChip8" | "hybridVIP" | "modernChip8" => Some(Platform::Chip8),
+        "chip48" | "superchip1" | "superchip" => Some(Platform::SuperChip),
+        _ => None,
+    }
+}
+
+// "up" etc. are the names the database gives to the keys a game uses; player 2 has no binding
+fn pc_key(name: &str) -> Option<usize> {
+    match name {
+        "up" => Some(KEY_UP),
+        "down" => Some(KEY_DOWN),
+        "left" => Some(KEY_LEFT),
+        "right" => Some(KEY_RIGHT),
+        "a" => Some(KEY_SPACE),
+        "b" => Some(KEY_ENTER),
+        _ => None,
+    }
+}
+
+fn theme(colors: &Colors) -> Option<Theme> {
+    if colors.pixels.len() < 2 {
+        return None;
+    }
+    let mut theme = Theme::default();
+    for (index, color) in colors.pixels.iter().take(PALETTE_SIZE).enumerate() {
+        theme.palette[index] = Rgba::from_hex(color)?;
+    }
+    Some(theme)
+}
+
+impl RomInfo {
+    fn new(program: &Program, rom: &Rom) -> Self {
+        let (id, platform) = rom.platforms.iter()
+            .find_map(|id| platform(id).map(|p| (id.as_str(), p)))
+            .map_or((None, None), |(id, p)| (Some(id), Some(p)));
+        let quirks = platform.map(|p| {
+            let mut quirks = p.quirks();
+            if let Some(overrides) = id.and_then(|id| rom.quirky_platforms.get(id)) {
+                quirks.shift = overrides.shift.unwrap_or(quirks.shift);
+                quirks.load_store = overrides.memory_leave_i_unchanged.unwrap_or(quirks.load_store);
+            }
+            quirks
+        });
+        let mut keys = KeyMap::new();
+        for (name, key) in &rom.keys {
+            if let Some(pc_key) = pc_key(name) {
+                keys.bind(pc_key, *key);
+            }
+        }
+        RomInfo {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            description: program.description.clone(),
+            platform,
+            quirks,
+            tickrate: rom.tickrate,
+            keys: if keys.is_empty() { None } else { Some(keys) },
+            theme: rom.colors.as_ref().and_then(theme),
+        }
+    }
+
+    /** "Title by A, B", or just the title */
+    pub fn describe(&self) -> String {
+        if self.authors.is_empty() {
+            self.title.clone()
+        } else {
+            format!("{} by {}", self.title, self.authors.join(", "))
+        }
+    }
+}
+
+impl RomDatabase {
+    /** Parses programs.json: an array of programs, each with its ROMs keyed by SHA-1 */
+    pub fn from_json(json: &str) -> Result<Self, RomDbError> {
+        let programs: Vec<Program> = serde_json::from_str(json).map_err(RomDbError::Json)?;
+        let mut roms = HashMap::new();
+        for program in &programs {
+            for (sha1, rom) in &program.roms {
+                roms.insert(sha1.to_ascii_lowercase(), RomInfo::new(program, rom));
+            }
+        }
+        Ok(RomDatabase { roms })
+    }
+
+    pub fn get(&self, sha1: &str) -> Option<&RomInfo> {
+        self.roms.get(&sha1.to_ascii_lowercase())
+    }
+
+    /** Looks the ROM up by the SHA-1 of its bytes */
+    pub fn identify(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.get(&sha1_hex(rom))
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+}
+
+#[cfg(test)]
+const TEST_DB: &str = r##"[
+  {
+    "title": "Jumper",
+    "authors": ["Someone", "Someone Else"],
+    "release": "2022",
+    "roms": {
+      "8A1A2A6E4C34A7E4F7A3E5C5A1E2B0A9C8D7E6F5": {
+        "file": "jumper.ch8",
+        "platforms": ["xochip", "superchip"],
+        "tickrate": 30,
+        "keys": { "left": 7, "right": 9, "a": 6, "player2Up": 1 },
+        "colors": { "pixels": ["#102030", "#fff"] },
+        "quirkyPlatforms": { "superchip": { "shift": false, "wrap": true } }
+      }
+    }
+  },
+  {
+    "title": "Nameless",
+    "roms": { "da39a3ee5e6b4b0d3255bfef95601890afd80709": { "platforms": ["originalChip8"] } }
+  }
+]"##;
+
+#[test]
+fn test_sha1_hex() {
+    assert_eq!(sha1_hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+}
+
+#[test]
+fn test_rom_database() {
+    let db = RomDatabase::from_json(TEST_DB).unwrap();
+    assert_eq!(db.len(), 2);
+    let jumper = db.get("8a1a2a6e4c34a7e4f7a3e5c5a1e2b0a9c8d7e6f5").unwrap();
+    assert_eq!(jumper.describe(), "Jumper by Someone, Someone Else");
+    // xochip isn't supported, superchip with its shift quirk turned off
+    assert_eq!(jumper.platform, Some(Platform::SuperChip));
+    assert_eq!(jumper.quirks, Some(CPUQuirks { shift: false, load_store: true }));
+    assert_eq!(jumper.tickrate, Some(30));
+    let keys = jumper.keys.as_ref().unwrap();
+    assert_eq!(keys.to_chip8(KEY_LEFT), Some(7));
+    assert_eq!(keys.to_chip8(KEY_SPACE), Some(6));
+    let theme = jumper.theme.as_ref().unwrap();
+    assert_eq!((theme.background(), theme.foreground()), (Rgba(0x10, 0x20, 0x30, 0xFF), Rgba::WHITE));
+
+    let nameless = db.identify(&[]).unwrap();
+    assert_eq!(nameless.describe(), "Nameless");
+    assert_eq!(nameless.quirks, Some(CPUQuirks::default()));
+    assert_eq!(nameless.theme, None);
+    assert_eq!(nameless.keys, None);
+    assert!(db.identify(b"abc").is_none());
+    assert!(RomDatabase::from_json("{}").is_err());
+}
+
+#[test]
+fn test_recognised_rom_is_set_up() {
+    use crate::headless_screen::HeadlessScreen;
+    use crate::cpu::CPU;
+    use std::sync::Arc;
+
+    let rom = [0x12, 0x00];
+    let json = TEST_DB.replace("8A1A2A6E4C34A7E4F7A3E5C5A1E2B0A9C8D7E6F5", &sha1_hex(&rom));
+    let mut cpu = CPU::new(Box::new(HeadlessScreen::new()));
+    cpu.set_rom_database(Arc::new(RomDatabase::from_json(&json).unwrap()));
+    cpu.load_program(rom.to_vec()).unwrap();
+    assert_eq!(cpu.rom_info().unwrap().title, "Jumper");
+    assert_eq!(cpu.state.quirks, CPUQuirks { shift: false, load_store: true });
+    cpu.key_down(KEY_RIGHT);
+    assert!(cpu.state.keyboard.is_key_pressed(&9));
+    // the default layout still works
+    cpu.key_down(88);
+    assert!(cpu.state.keyboard.is_key_pressed(&0));
+
+    cpu.load_rom(crate::rom::Rom::new(vec![0x12, 0x02])).unwrap();
+    assert!(cpu.rom_info().is_none());
+    cpu.key_down(KEY_LEFT);
+    assert!(!cpu.state.keyboard.is_key_pressed(&7));
+
+    // the caller's own bindings survive loading a ROM the database doesn't know
+    let mut keymap = KeyMap::new();
+    keymap.bind(KEY_LEFT, 0x4);
+    cpu.set_keymap(keymap);
+    cpu.load_rom(crate::rom::Rom::new(vec![0x12, 0x04])).unwrap();
+    cpu.key_down(KEY_LEFT);
+    assert!(cpu.state.keyboard.is_key_pressed(&4));
+}
+
+#[test]
+fn test_queued_database_follows_queued_rom() {
+    use crate::command_queue::Command;
+    use crate::headless_screen::HeadlessScreen;
+    use crate::cpu::CPU;
+    use crate::rom::Rom;
+    use std::sync::Arc;
+
+    let rom = [0x12, 0x00];
+    let json = TEST_DB.replace("8A1A2A6E4C34A7E4F7A3E5C5A1E2B0A9C8D7E6F5", &sha1_hex(&rom));
+    let mut cpu = CPU::new(Box::new(HeadlessScreen::new()));
+    let commands = cpu.command_queue();
+    commands.push(Command::LoadRom(Rom::new(rom.to_vec())));
+    commands.push(Command::SetRomDatabase(Arc::new(RomDatabase::from_json(&json).unwrap())));
+    assert!(cpu.rom_info().is_none());
+    cpu.run_frame_now();
+    assert_eq!(cpu.rom_info().unwrap().title, "Jumper");
+}
@@ -0,0 +1,208 @@
+//! Sanitizer mode: every instruction is checked before it runs for things a ROM shouldn't rely on,
+//! see CPU::enable_sanitizer. Memory is shadowed with what has been written and what has been read as data.
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use ux::u12;
+
+use crate::cpu::{CPUState, FONTS_LENGTH};
+use crate::rom::PROGRAM_START_ADDR;
+
+const MEM_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SanitizerOptions {
+    // power on with random RAM (besides fonts and program), V0-VF and I, so uninitialised reads show
+    pub randomize: bool,
+    // stop at any finding; otherwise only at the ones the CPU can't carry on from
+    pub stop_on_finding: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Finding {
+    // memory neither loaded nor written by the program
+    UninitialisedRead(u16),
+    // len bytes from I go past the end of memory
+    IndexOutOfRange { i: u16, len: u16 },
+    // a CALL 16 levels deep; the stack pointer wraps to 0 and the return addresses are lost
+    StackOverflow,
+    // RET with nothing on the stack
+    StackUnderflow,
+    // an opcode fetched from bytes drawn as a sprite or loaded into registers
+    ExecutedData(u16),
+    // Fx55/Fx33 into the interpreter and font area
+    WriteBelowProgram(u16),
+}
+
+impl Finding {
+    /** The instruction would read or write past memory or the stack */
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Finding::IndexOutOfRange { .. } | Finding::StackUnderflow)
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Finding::UninitialisedRead(addr) => write!(f, "reads {:#05x}, which was never written", addr),
+            Finding::IndexOutOfRange { i, len } => write!(f, "{} bytes from I = {:#05x} go past the end of memory", len, i),
+            Finding::StackOverflow => write!(f, "stack overflow"),
+            Finding::StackUnderflow => write!(f, "RET with an empty stack"),
+            Finding::ExecutedData(addr) => write!(f, "executes {:#05x}, which was used as data", addr),
+            Finding::WriteBelowProgram(addr) => write!(f, "writes {:#05x}, below {:#05x}", addr, PROGRAM_START_ADDR),
+        }
+    }
+}
+
+/**
+* A finding, with where it happened: the instruction and the CALLs that led to it, outermost first
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub pc: u16,
+    pub opcode: u16,
+    pub finding: Finding,
+    pub call_stack: Vec<u16>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#05x} ({:04X}) {}", self.pc, self.opcode, self.finding)?;
+        if !self.call_stack.is_empty() {
+            let calls: Vec<String> = self.call_stack.iter().map(|addr| format!("{:#05x}", addr)).collect();
+            write!(f, ", called from {}", calls.join(" > "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Sanitizer {
+    options: SanitizerOptions,
+    written: Vec<bool>,
+    data: Vec<bool>,
+    // (pc, finding) already reported, so loops don't repeat them
+    reported: BTreeSet<(u16, Finding)>,
+}
+
+impl Sanitizer {
+    pub(crate) fn new(options: SanitizerOptions) -> Self {
+        Sanitizer { options, written: vec![], data: vec![], reported: BTreeSet::new() }
+    }
+
+    pub(crate) fn options(&self) -> SanitizerOptions {
+        self.options
+    }
+
+    /** Forgets what was seen; the fonts and len program bytes at load_address start out written */
+    pub(crate) fn forget(&mut self, load_address: u16, len: usize) {
+        let program = usize::from(load_address)..usize::from(load_address) + len;
+        self.written = (0..MEM_SIZE).map(|addr| addr < FONTS_LENGTH || program.contains(&addr)).collect();
+        self.data = vec![false; MEM_SIZE];
+        self.reported.clear();
+    }
+
+    /** forget, and fill whatever the program didn't load with noise if asked to */
+    pub(crate) fn power_on(&mut self, state: &mut CPUState, load_address: u16, len: usize) {
+        self.forget(load_address, len);
+        if self.options.randomize {
+            // not the RND sequence itself, but as repeatable with CPU::seed_rng
+            let mut rng = ChaCha8Rng::seed_from_u64(state.rng_seed.rotate_left(32));
+            for (addr, byte) in state.mem.iter_mut().enumerate() {
+                if !self.written[addr] {
+                    byte.0 = rng.gen();
+                }
+            }
+            for v in state.v.iter_mut() {
+                v.0 = rng.gen();
+            }
+            state.i.0 = u12::new(rng.gen::<u16>() & 0x0FFF);
+        }
+    }
+
+    /** Checks the instruction at PC before it runs, and notes the memory it reads and writes */
+    pub(crate) fn check(&mut self, state: &CPUState) -> Vec<Report> {
+        let pc = state.pci() as u16;
+        let opcode = state.fetch();
+        let i = u16::from(state.i.0);
+        let x = (opcode & 0x0F00) >> 8;
+        let sp = u8::from(state.sp.0) as usize;
+        let mut findings = vec![];
+        if (pc..pc + 2).any(|addr| self.data.get(usize::from(addr)) == Some(&true)) {
+            findings.push(Finding::ExecutedData(pc));
+        }
+        findings.extend((pc..pc + 2).find(|addr| self.written.get(usize::from(*addr)) != Some(&true)).map(Finding::UninitialisedRead));
+        match (opcode & 0xF000, opcode & 0x00FF) {
+            _ if opcode == 0x00EE && sp == 0 => findings.push(Finding::StackUnderflow),
+            (0x2000, _) if sp == state.stack.len() - 1 => findings.push(Finding::StackOverflow),
+            (0xD000, _) => findings.extend(self.read(i, opcode & 0xF)),
+            (0xF000, 0x65) => findings.extend(self.read(i, x + 1)),
+            (0xF000, 0x55) => findings.extend(self.write(i, x + 1)),
+            (0xF000, 0x33) => findings.extend(self.write(i, 3)),
+            _ => {}
+        }
+        let call_stack: Vec<u16> = state.stack[..sp].iter().map(|addr| u16::from(*addr)).collect();
+        findings
+            .into_iter()
+            .filter(|finding| finding.is_fatal() || self.reported.insert((pc, *finding)))
+            .map(|finding| Report { pc, opcode, finding, call_stack: call_stack.clone() })
+            .collect()
+    }
+
+    fn read(&mut self, i: u16, len: u16) -> Option<Finding> {
+        let range = match self.range(i, len) {
+            Some(range) => range,
+            None => return Some(Finding::IndexOutOfRange { i, len }),
+        };
+        let unwritten = range.clone().find(|addr| !self.written[*addr]);
+        for addr in range {
+            self.data[addr] = true;
+        }
+        unwritten.map(|addr| Finding::UninitialisedRead(addr as u16))
+    }
+
+    fn write(&mut self, i: u16, len: u16) -> Option<Finding> {
+        let range = match self.range(i, len) {
+            Some(range) => range,
+            None => return Some(Finding::IndexOutOfRange { i, len }),
+        };
+        for addr in range {
+            self.written[addr] = true;
+        }
+        Some(Finding::WriteBelowProgram(i)).filter(|_| i < PROGRAM_START_ADDR)
+    }
+
+    fn range(&self, i: u16, len: u16) -> Option<Range<usize>> {
+        let end = usize::from(i) + usize::from(len);
+        Some(usize::from(i)..end).filter(|_| end <= self.written.len())
+    }
+}
+
+#[test]
+fn test_stack_and_data() {
+    use crate::cpu::{MemValue, I, PC, SP};
+    use ux::u4;
+    let mut state = CPUState::new();
+    // DRW V0, V0, 2 of the next instruction, CALL 0x200
+    for (offset, byte) in [0xD0, 0x02, 0x22, 0x00].iter().enumerate() {
+        state.mem[0x200 + offset] = MemValue(*byte);
+    }
+    state.i = I(u12::new(0x202));
+    let mut sanitizer = Sanitizer::new(SanitizerOptions::default());
+    sanitizer.forget(0x200, 4);
+    assert!(sanitizer.check(&state).is_empty());
+    state.pc = PC(u12::new(0x202));
+    state.sp = SP(u4::new(15));
+    let reports = sanitizer.check(&state);
+    assert_eq!(reports.iter().map(|report| report.finding).collect::<Vec<_>>(), vec![Finding::ExecutedData(0x202), Finding::StackOverflow]);
+    assert_eq!(reports[0].call_stack.len(), 15);
+    // once per instruction
+    assert!(sanitizer.check(&state).is_empty());
+}
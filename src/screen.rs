@@ -1,24 +1,17 @@
-
+use alloc::vec::Vec;
 
 use futures::future::LocalBoxFuture;
 
+use crate::framebuffer::Frame;
+use crate::theme::Theme;
 
-
-
-use crate::cpu_instructions::{X, Y};
-
-pub struct IsCollision(pub bool);
-
-pub fn toggle_pixel(state: &mut ScreenState, x: X, y: Y) -> IsCollision {
-    let is_collision = state[y.0][x.0];
-    state[y.0][x.0] = !is_collision;
-    IsCollision(is_collision)
-}
-
+/**
+* Presents frames; the framebuffer itself lives in CPUState
+*/
 pub trait ScreenDraw {
-    fn toggle_pixel(&mut self, x: X, y: Y) -> IsCollision;
-    fn repaint(&mut self);
-    fn clear(&mut self);
+    fn present(&mut self, frame: &Frame);
+    // colours picked for the game, e.g. from the ROM database; ignored by monochrome presenters
+    fn set_theme(&mut self, _theme: &Theme) {}
     fn get_width(&self) -> usize {
         SCREEN_WIDTH
     }
@@ -28,14 +21,86 @@ pub trait ScreenDraw {
 }
 
 pub trait Screen: ScreenDraw {
-    fn request_animation_frame(&self) -> LocalBoxFuture<()>;
+    // mustn't borrow the screen, the run loop doesn't hold the CPU while waiting
+    fn request_animation_frame(&self) -> LocalBoxFuture<'static, ()>;
+    // once per frame, before waiting for the next one
+    fn vblank(&self) {}
 }
 
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
 
-pub type ScreenState = [[bool; SCREEN_WIDTH]; SCREEN_HEIGHT];
+// u128 rows and 64 of them are enough for SCHIP hires (128x64)
+pub const MAX_SCREEN_HEIGHT: usize = 64;
+
+type Row = u128;
+
+/**
+* Bit-packed pixels, one Row per line; column 0 is the most significant bit
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ScreenState {
+    rows: [Row; MAX_SCREEN_HEIGHT],
+}
+
+impl ScreenState {
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        (self.rows[y] >> (Row::BITS as usize - 1 - x)) & 1 == 1
+    }
+    pub fn set(&mut self, x: usize, y: usize, on: bool) {
+        let bit = 1 << (Row::BITS as usize - 1 - x);
+        if on {
+            self.rows[y] |= bit;
+        } else {
+            self.rows[y] &= !bit;
+        }
+    }
+    /**
+     * One byte (0 or 1) per pixel, row-major
+     */
+    pub fn to_bytes(&self) -> Vec<u8> {
+        (0..SCREEN_HEIGHT).flat_map(|y| (0..SCREEN_WIDTH).map(move |x| self.get(x, y) as u8)).collect()
+    }
+    pub fn rows(&self) -> &[Row] {
+        &self.rows[..SCREEN_HEIGHT]
+    }
+    /**
+     * XORs an 8 pixel wide sprite in, wrapping around the edges. Returns whether any lit pixel was erased
+     */
+    pub fn xor_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let width = SCREEN_WIDTH;
+        let visible = !0 << (Row::BITS as usize - width);
+        let mut collision = false;
+        for (line, byte) in sprite.iter().enumerate() {
+            let row = &mut self.rows[(y + line) % SCREEN_HEIGHT];
+            let at_left = (*byte as Row) << (Row::BITS - 8);
+            // the part sticking out to the right comes back in from the left
+            let wrapped = at_left.checked_shl((width - x) as u32).unwrap_or(0);
+            let mask = ((at_left >> x) & visible) | wrapped;
+            collision |= *row & mask != 0;
+            *row ^= mask;
+        }
+        collision
+    }
+}
 
 pub fn make_zero_screen_state() -> ScreenState {
-    [[false; SCREEN_WIDTH]; SCREEN_HEIGHT]
-}
\ No newline at end of file
+    ScreenState { rows: [0; MAX_SCREEN_HEIGHT] }
+}
+
+#[test]
+fn test_screen_state_xor_sprite() {
+    let mut state = make_zero_screen_state();
+    assert!(!state.xor_sprite(62, 31, &[0b1100_0001, 0b1000_0000]));
+    assert!(state.get(62, 31));
+    assert!(state.get(63, 31));
+    assert!(state.get(5, 31));
+    assert!(!state.get(0, 31));
+    assert!(state.get(62, 0));
+    assert!(!state.get(63, 0));
+    assert!(!state.xor_sprite(63, 0, &[0b1000_0000]));
+    assert!(state.xor_sprite(62, 0, &[0b1100_0000]));
+    assert!(!state.get(62, 0));
+    assert!(!state.get(63, 0));
+    assert_eq!(state.rows().iter().map(|r| r.count_ones()).sum::<u32>(), 3);
+}
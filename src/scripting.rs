@@ -0,0 +1,322 @@
+//! Rhai scripts driving the emulator, for automated play-testing without recompiling (feature `scripting`).
+//! The top level runs before the first frame and registers hooks:
+//! ```text
+//! on_frame(|frame| if frame == 600 { assert(peek(0x263) > 0, "lost every life"); stop(); });
+//! on_pc(0x24A, |pc| print(`life lost in frame ${frame()}`));
+//! on_write(0x260, 0x263, |addr, value| print(`score digit ${addr}: ${value}`));
+//! on_draw(|pc| screenshot(`frame${frame()}.pgm`));
+//! ```
+//! Hooks: `on_frame(f(frame))` after every frame, `on_pc(addr, f(pc))` after the instruction at addr ran,
+//! `on_write(start, end, f(addr, value))` after every write to start..end, `on_draw(f(pc))` after every DRW.
+//! Machine: `v(x)`, `set_v(x, value)`, `i()`, `set_i(addr)`, `pc()`, `dt()`, `st()`, `peek(addr)`, `poke(addr, value)`,
+//! `pixel(x, y)`, `frame()` (frames run so far), `press(key)` and `release(key)`.
+//! `screenshot()` returns the screen as a PGM blob, `screenshot(path)` writes it.
+//! `assert(condition)` and `assert(condition, message)` fail the run, `stop()` ends it after the current frame.
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::rc::Rc;
+
+use rhai::{Blob, Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, AST, INT};
+use ux::u12;
+
+use crate::cpu::{StepTrace, CPU, I, V};
+use crate::events::EmulatorEvent;
+use crate::screen::{make_zero_screen_state, ScreenState, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+type RhaiResult<T> = Result<T, Box<EvalAltResult>>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScriptError {
+    Compile(String),
+    // raised by the script, or by a function it called
+    Runtime(String),
+    Assertion(String),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::Compile(e) => write!(f, "script doesn't compile: {}", e),
+            ScriptError::Runtime(e) => write!(f, "script error: {}", e),
+            ScriptError::Assertion(message) => write!(f, "assertion failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+// what a script changed, applied to the CPU once it returns
+enum Action {
+    SetV(usize, u8),
+    SetI(u16),
+    Poke(u16, u8),
+    Press(u8),
+    Release(u8),
+    Stop,
+}
+
+#[derive(Default)]
+struct Hooks {
+    frame: Vec<FnPtr>,
+    pc: Vec<(u16, FnPtr)>,
+    write: Vec<(Range<u16>, FnPtr)>,
+    draw: Vec<FnPtr>,
+}
+
+impl Hooks {
+    // without these, frames run at full speed
+    fn observe_steps(&self) -> bool {
+        !self.pc.is_empty() || !self.write.is_empty() || !self.draw.is_empty()
+    }
+}
+
+// the machine as scripts see it: copied from the CPU before they run, their changes applied after
+struct Machine {
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    dt: u8,
+    st: u8,
+    mem: Vec<u8>,
+    pixels: ScreenState,
+    frame: u64,
+    actions: Vec<Action>,
+    hooks: Hooks,
+    stopped: bool,
+    failure: Option<String>,
+}
+
+impl Machine {
+    fn new() -> Self {
+        Machine {
+            v: [0; 16],
+            i: 0,
+            pc: 0,
+            dt: 0,
+            st: 0,
+            mem: vec![],
+            pixels: make_zero_screen_state(),
+            frame: 0,
+            actions: vec![],
+            hooks: Hooks::default(),
+            stopped: false,
+            failure: None,
+        }
+    }
+
+    fn load(&mut self, cpu: &CPU) {
+        let state = &cpu.state;
+        for (v, register) in self.v.iter_mut().zip(state.v.iter()) {
+            *v = register.0;
+        }
+        self.i = u16::from(state.i.0);
+        self.pc = state.pci() as u16;
+        self.dt = state.dt.0;
+        self.st = state.st.0;
+        self.mem.clear();
+        self.mem.extend(state.mem.iter().map(|m| m.0));
+        self.pixels = *state.framebuffer.pixels();
+    }
+
+    fn apply(&mut self, cpu: &mut CPU) {
+        for action in self.actions.drain(..) {
+            match action {
+                Action::SetV(x, value) => cpu.state.v[x] = V(value),
+                Action::SetI(addr) => cpu.state.i = I(u12::new(addr)),
+                Action::Poke(addr, value) => cpu.poke(addr, value),
+                Action::Press(key) => cpu.press_key(key),
+                Action::Release(key) => cpu.release_key(key),
+                Action::Stop => cpu.stop(),
+            }
+        }
+    }
+
+    fn fail(&mut self, message: String) -> RhaiResult<()> {
+        self.failure = Some(message.clone());
+        Err(message.into())
+    }
+}
+
+// the screen as a binary PGM
+fn pgm(pixels: &ScreenState) -> Blob {
+    let mut out = format!("P5\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT).into_bytes();
+    out.extend(pixels.to_bytes().iter().map(|on| on * 255));
+    out
+}
+
+// 12-bit addresses, wrapping like the CPU's
+fn address(addr: INT) -> u16 {
+    (addr & 0xFFF) as u16
+}
+
+// a function of the machine; the borrow ends before the script goes on
+macro_rules! machine_fn {
+    ($engine:ident, $machine:ident, $name:literal, |$m:ident $(, $arg:ident: $ty:ty)*| -> $ret:ty $body:block) => {{
+        let machine = $machine.clone();
+        $engine.register_fn($name, move |$($arg: $ty),*| -> $ret {
+            let $m = &mut *machine.borrow_mut();
+            $body
+        });
+    }};
+    ($engine:ident, $machine:ident, $name:literal, |$m:ident $(, $arg:ident: $ty:ty)*| $body:expr) => {{
+        let machine = $machine.clone();
+        $engine.register_fn($name, move |$($arg: $ty),*| {
+            let $m = &mut *machine.borrow_mut();
+            $body
+        });
+    }};
+}
+
+fn engine(machine: &Rc<RefCell<Machine>>) -> Engine {
+    let mut engine = Engine::new();
+    machine_fn!(engine, machine, "on_frame", |m, hook: FnPtr| m.hooks.frame.push(hook));
+    machine_fn!(engine, machine, "on_pc", |m, addr: INT, hook: FnPtr| m.hooks.pc.push((address(addr), hook)));
+    machine_fn!(engine, machine, "on_write", |m, start: INT, end: INT, hook: FnPtr| m.hooks.write.push((address(start)..end.clamp(0, 0x1000) as u16, hook)));
+    machine_fn!(engine, machine, "on_draw", |m, hook: FnPtr| m.hooks.draw.push(hook));
+    machine_fn!(engine, machine, "v", |m, x: INT| -> RhaiResult<INT> {
+        let register = usize::try_from(x).ok().and_then(|x| m.v.get(x));
+        register.map(|v| INT::from(*v)).ok_or_else(|| format!("no register V{}", x).into())
+    });
+    machine_fn!(engine, machine, "set_v", |m, x: INT, value: INT| -> RhaiResult<()> {
+        let x = usize::try_from(x).ok().filter(|x| *x < m.v.len()).ok_or_else(|| format!("no register V{}", x))?;
+        m.v[x] = value as u8;
+        m.actions.push(Action::SetV(x, value as u8));
+        Ok(())
+    });
+    machine_fn!(engine, machine, "i", |m| INT::from(m.i));
+    machine_fn!(engine, machine, "set_i", |m, addr: INT| {
+        m.i = address(addr);
+        m.actions.push(Action::SetI(address(addr)));
+    });
+    machine_fn!(engine, machine, "pc", |m| INT::from(m.pc));
+    machine_fn!(engine, machine, "dt", |m| INT::from(m.dt));
+    machine_fn!(engine, machine, "st", |m| INT::from(m.st));
+    machine_fn!(engine, machine, "frame", |m| m.frame as INT);
+    machine_fn!(engine, machine, "peek", |m, addr: INT| INT::from(m.mem[usize::from(address(addr))]));
+    machine_fn!(engine, machine, "poke", |m, addr: INT, value: INT| {
+        m.mem[usize::from(address(addr))] = value as u8;
+        m.actions.push(Action::Poke(address(addr), value as u8));
+    });
+    machine_fn!(engine, machine, "pixel", |m, x: INT, y: INT| {
+        m.pixels.get(x.rem_euclid(SCREEN_WIDTH as INT) as usize, y.rem_euclid(SCREEN_HEIGHT as INT) as usize)
+    });
+    machine_fn!(engine, machine, "press", |m, key: INT| m.actions.push(Action::Press(key as u8)));
+    machine_fn!(engine, machine, "release", |m, key: INT| m.actions.push(Action::Release(key as u8)));
+    machine_fn!(engine, machine, "screenshot", |m| pgm(&m.pixels));
+    machine_fn!(engine, machine, "screenshot", |m, path: &str| -> RhaiResult<()> {
+        fs::write(path, pgm(&m.pixels)).map_err(|e| format!("{}: {}", path, e).into())
+    });
+    machine_fn!(engine, machine, "assert", |m, condition: bool| -> RhaiResult<()> {
+        if condition { Ok(()) } else { m.fail(format!("in frame {}", m.frame)) }
+    });
+    machine_fn!(engine, machine, "assert", |m, condition: bool, message: &str| -> RhaiResult<()> {
+        if condition { Ok(()) } else { m.fail(message.to_string()) }
+    });
+    machine_fn!(engine, machine, "stop", |m| {
+        m.stopped = true;
+        m.actions.push(Action::Stop);
+    });
+    engine
+}
+
+/**
+* A compiled script and its hooks; run_frame() drives the CPU in place of run_frame_now()
+*/
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    machine: Rc<RefCell<Machine>>,
+    started: bool,
+}
+
+impl Script {
+    pub fn new(source: &str) -> Result<Self, ScriptError> {
+        let machine = Rc::new(RefCell::new(Machine::new()));
+        let engine = engine(&machine);
+        let ast = engine.compile(source).map_err(|e| ScriptError::Compile(e.to_string()))?;
+        Ok(Script { engine, ast, machine, started: false })
+    }
+
+    /** The script called stop() */
+    pub fn is_done(&self) -> bool {
+        self.machine.borrow().stopped
+    }
+
+    /**
+     * Runs a frame, calling the hooks as it goes; the first call runs the top level before it.
+     * Returns the frame's events, or the first error or failed assertion
+     */
+    pub fn run_frame(&mut self, cpu: &mut CPU) -> Result<Vec<EmulatorEvent>, ScriptError> {
+        if !self.started {
+            self.started = true;
+            self.machine.borrow_mut().load(cpu);
+            let result = self.engine.run_ast(&self.ast);
+            self.finish(cpu, result)?;
+        }
+        if self.machine.borrow().hooks.observe_steps() {
+            let mut error = None;
+            cpu.run_frame_observed(&mut |cpu, trace| {
+                if error.is_none() {
+                    error = self.on_step(cpu, trace).err();
+                }
+            });
+            if let Some(e) = error {
+                return Err(e);
+            }
+        } else {
+            cpu.run_frame_now();
+        }
+        let (frame, hooks) = {
+            let mut machine = self.machine.borrow_mut();
+            machine.frame += 1;
+            (machine.frame as INT, machine.hooks.frame.clone())
+        };
+        for hook in &hooks {
+            self.call(cpu, hook, (frame,))?;
+        }
+        Ok(cpu.take_events())
+    }
+
+    fn on_step(&self, cpu: &mut CPU, trace: &StepTrace) -> Result<(), ScriptError> {
+        let (at_pc, writes) = {
+            let machine = self.machine.borrow();
+            let hooks = &machine.hooks;
+            let mut at_pc: Vec<FnPtr> = hooks.pc.iter().filter(|(addr, _)| *addr == trace.pc).map(|(_, hook)| hook.clone()).collect();
+            if trace.opcode & 0xF000 == 0xD000 {
+                at_pc.extend(hooks.draw.iter().cloned());
+            }
+            let writes: Vec<(FnPtr, u16, u8)> = trace
+                .writes
+                .iter()
+                .flat_map(|&(addr, value)| hooks.write.iter().filter(move |(range, _)| range.contains(&addr)).map(move |(_, hook)| (hook.clone(), addr, value)))
+                .collect();
+            (at_pc, writes)
+        };
+        for hook in &at_pc {
+            self.call(cpu, hook, (INT::from(trace.pc),))?;
+        }
+        for (hook, addr, value) in &writes {
+            self.call(cpu, hook, (INT::from(*addr), INT::from(*value)))?;
+        }
+        Ok(())
+    }
+
+    fn call(&self, cpu: &mut CPU, hook: &FnPtr, args: impl FuncArgs) -> Result<(), ScriptError> {
+        self.machine.borrow_mut().load(cpu);
+        let result = hook.call::<Dynamic>(&self.engine, &self.ast, args).map(|_| ());
+        self.finish(cpu, result)
+    }
+
+    // applies what the script changed, and reports how it went
+    fn finish(&self, cpu: &mut CPU, result: RhaiResult<()>) -> Result<(), ScriptError> {
+        let mut machine = self.machine.borrow_mut();
+        machine.apply(cpu);
+        match (result, machine.failure.take()) {
+            (_, Some(message)) => Err(ScriptError::Assertion(message)),
+            (Err(e), None) => Err(ScriptError::Runtime(e.to_string())),
+            (Ok(()), None) => Ok(()),
+        }
+    }
+}
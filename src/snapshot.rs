@@ -0,0 +1,196 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use ux::{u12, u4};
+
+use crate::cpu::{CPUQuirks, CPUState, MemValue, V};
+use crate::cpu_instructions::X;
+use crate::screen::{make_zero_screen_state, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const MAGIC: [u8; 3] = *b"C8S";
+const VERSION: u8 = 1;
+
+/**
+* Machine state only: memory, registers, timers, keypad, pixels and the RNG seed.
+* Run loop settings (pause, breakpoints, clock) aren't part of it.
+* Always SNAPSHOT_SIZE bytes, which libretro front-ends rely on
+*/
+pub const SNAPSHOT_SIZE: usize = 4 // header
+    + 4096 // memory
+    + 16 // V0..VF
+    + 2 + 2 // PC, I
+    + 16 * 2 // stack
+    + 3 // SP, DT, ST
+    + 3 // flags, Fx0A register, quirks
+    + 8 // RNG seed
+    + 2 // keypad
+    + SCREEN_WIDTH / 8 * SCREEN_HEIGHT;
+// the flags byte, followed by the Fx0A register
+const FLAGS_OFFSET: usize = 4 + 4096 + 16 + 2 + 2 + 16 * 2 + 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    WrongSize { expected: usize, actual: usize },
+    UnknownFormat,
+    // waiting for a key without a register to store it in, or the other way round
+    InvalidKeyWait,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::WrongSize { expected, actual } => write!(f, "save state has {} bytes, expected {}", actual, expected),
+            SnapshotError::UnknownFormat => write!(f, "not a save state of this version"),
+            SnapshotError::InvalidKeyWait => write!(f, "save state waits for a key without a register to store it in"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SnapshotError {}
+
+pub(crate) fn save(state: &CPUState) -> Vec<u8> {
+    let mut out = Vec::with_capacity(SNAPSHOT_SIZE);
+    out.extend_from_slice(&MAGIC);
+    out.push(VERSION);
+    out.extend(state.mem.iter().map(|m| m.0));
+    out.extend(state.v.iter().map(|v| v.0));
+    out.extend_from_slice(&u16::from(state.pc.0).to_be_bytes());
+    out.extend_from_slice(&u16::from(state.i.0).to_be_bytes());
+    for addr in state.stack.iter() {
+        out.extend_from_slice(&u16::from(*addr).to_be_bytes());
+    }
+    out.push(u8::from(state.sp.0));
+    out.push(state.dt.0);
+    out.push(state.st.0);
+    out.push(state.halted.0 as u8 | (state.waiting_kb.0 as u8) << 1 | (state.repaint.0 as u8) << 2);
+    out.push(state.waiting_kb_x.map(|x| x.0 as u8).unwrap_or(0xFF));
+    out.push(state.quirks.shift as u8 | (state.quirks.load_store as u8) << 1);
+    out.extend_from_slice(&state.rng_seed.to_be_bytes());
+    let keys = (0..16u8).fold(0u16, |keys, k| keys | (state.keyboard.is_key_pressed(&k) as u16) << k);
+    out.extend_from_slice(&keys.to_be_bytes());
+    let pixels = state.framebuffer.pixels();
+    for y in 0..SCREEN_HEIGHT {
+        for byte in 0..SCREEN_WIDTH / 8 {
+            out.push((0..8).fold(0u8, |b, bit| b | (pixels.get(byte * 8 + bit, y) as u8) << (7 - bit)));
+        }
+    }
+    debug_assert_eq!(out.len(), SNAPSHOT_SIZE);
+    out
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> &'a [u8] {
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        head
+    }
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+    fn u16(&mut self) -> u16 {
+        u16::from_be_bytes([self.u8(), self.u8()])
+    }
+}
+
+/**
+* Overwrites the state; on error it's left untouched
+*/
+pub(crate) fn restore(state: &mut CPUState, data: &[u8]) -> Result<(), SnapshotError> {
+    if data.len() != SNAPSHOT_SIZE {
+        return Err(SnapshotError::WrongSize { expected: SNAPSHOT_SIZE, actual: data.len() });
+    }
+    let mut r = Reader(data);
+    if r.take(3) != MAGIC || r.u8() != VERSION {
+        return Err(SnapshotError::UnknownFormat);
+    }
+    let waiting_kb = data[FLAGS_OFFSET] & 2 != 0;
+    if waiting_kb != (data[FLAGS_OFFSET + 1] < 16) {
+        return Err(SnapshotError::InvalidKeyWait);
+    }
+    for (m, b) in state.mem.iter_mut().zip(r.take(4096)) {
+        *m = MemValue(*b);
+    }
+    for (v, b) in state.v.iter_mut().zip(r.take(16)) {
+        *v = V(*b);
+    }
+    state.pc.0 = u12::new(r.u16() & 0x0FFF);
+    state.i.0 = u12::new(r.u16() & 0x0FFF);
+    for addr in state.stack.iter_mut() {
+        *addr = u12::new(r.u16() & 0x0FFF);
+    }
+    state.sp.0 = u4::new(r.u8() & 0x0F);
+    state.dt.0 = r.u8();
+    state.st.0 = r.u8();
+    let flags = r.u8();
+    state.halted.0 = flags & 1 != 0;
+    state.waiting_kb.0 = waiting_kb;
+    state.repaint.0 = flags & 4 != 0;
+    let x = r.u8();
+    state.waiting_kb_x = if waiting_kb { Some(X(x as usize)) } else { None };
+    let quirks = r.u8();
+    state.quirks = CPUQuirks { shift: quirks & 1 != 0, load_store: quirks & 2 != 0 };
+    state.rng_seed = u64::from_be_bytes(r.take(8).try_into().unwrap());
+    let keys = r.u16();
+    for k in 0..16u8 {
+        if keys & 1 << k != 0 {
+            state.keyboard.key_down(&k);
+        } else {
+            state.keyboard.key_up(&k);
+        }
+    }
+    let mut pixels = make_zero_screen_state();
+    for y in 0..SCREEN_HEIGHT {
+        for (byte, b) in r.take(SCREEN_WIDTH / 8).iter().enumerate() {
+            for bit in 0..8 {
+                pixels.set(byte * 8 + bit, y, b & 0x80 >> bit != 0);
+            }
+        }
+    }
+    state.framebuffer.restore(pixels);
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_roundtrip() {
+    use crate::cpu_instructions::Y;
+    let mut state = CPUState::new();
+    state.mem[0x300] = MemValue(0xAB);
+    state.v[0xE] = V(7);
+    state.pc.0 = u12::new(0x234);
+    state.stack[1] = u12::new(0xFED);
+    state.sp.0 = u4::new(2);
+    state.st.0 = 9;
+    state.waiting_kb.0 = true;
+    state.waiting_kb_x = Some(X(3));
+    state.quirks.load_store = true;
+    state.keyboard.key_down(&0xC);
+    state.framebuffer.draw_sprite(X(60), Y(31), &[0xFF]);
+    let data = save(&state);
+    assert_eq!(data.len(), SNAPSHOT_SIZE);
+
+    let mut restored = CPUState::new();
+    restore(&mut restored, &data).unwrap();
+    assert_eq!(save(&restored), data);
+    assert_eq!(restored.mem[0x300].0, 0xAB);
+    assert_eq!(u16::from(restored.pc.0), 0x234);
+    assert_eq!(restored.waiting_kb_x.map(|x| x.0), Some(3));
+    assert!(restored.keyboard.is_key_pressed(&0xC));
+    assert!(restored.framebuffer.get_pixel(X(3), Y(31)));
+    assert_eq!(restored.framebuffer.pixels(), state.framebuffer.pixels());
+
+    assert_eq!(restore(&mut restored, &data[1..]), Err(SnapshotError::WrongSize { expected: SNAPSHOT_SIZE, actual: SNAPSHOT_SIZE - 1 }));
+    let mut bad = data.clone();
+    bad[3] = 0;
+    assert_eq!(restore(&mut restored, &bad), Err(SnapshotError::UnknownFormat));
+    let mut bad = data.clone();
+    bad[FLAGS_OFFSET + 1] = 0xFF;
+    assert_eq!(restore(&mut restored, &bad), Err(SnapshotError::InvalidKeyWait));
+    bad[FLAGS_OFFSET] &= !2;
+    restore(&mut restored, &bad).unwrap();
+    assert!(restored.waiting_kb_x.is_none());
+    bad[FLAGS_OFFSET + 1] = 3;
+    assert_eq!(restore(&mut restored, &bad), Err(SnapshotError::InvalidKeyWait));
+}
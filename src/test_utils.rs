@@ -1,29 +1,9 @@
-use crate::screen::*;
-use futures::{future::BoxFuture, future::ready};
-use futures::future::LocalBoxFuture;
-use crate::cpu::{CPU, MemValue, CPUState, V, PC, SP, I, DT};
+use crate::headless_screen::HeadlessScreen;
+pub(crate) use crate::cpu::{CPU, MemValue, CPUState, V, PC, SP, I, DT};
 use ux::{u12, u4};
 use crate::cpu_instructions::{X, Y};
-use mockall::*;
-use mockall::predicate::*;
 
-mock! {
-   pub TestScreen {}
-   impl ScreenDraw for TestScreen {
-        pub fn toggle_pixel(&mut self, x: X, y: Y) -> IsCollision;
-        pub fn repaint(&mut self);
-        pub fn clear(&mut self);
-        pub fn get_width(&self) -> usize;
-        pub fn get_height(&self) -> usize;
-    }
-
-    impl Screen for TestScreen {
-        pub fn request_animation_frame(&self) -> LocalBoxFuture<'static, ()>;
-    }
-}
-
-pub struct TestScope<'a> {
-    pub screen_draw: &'a mut MockTestScreen,
+pub struct TestScope {
     pub old_cpu_state: CPUState,
 }
 
@@ -99,6 +79,7 @@ pub(crate) struct TestCycleParams {
     pub(crate) op_args: Option<TestCycleOpArgs>,
     pub(crate) pre_fn: Option<fn(&mut CPU, Option<TestCycleOpArgs>)>,
     pub(crate) post_fn: Option<fn(&CPUState, TestScope, Option<TestCycleOpArgs>)>,
+    #[allow(dead_code)]
     pub(crate) op: Option<Box<fn()>>,
     pub(crate) expectations: fn(TestScope),
     pub(crate) expect_inc: bool,
@@ -119,29 +100,24 @@ impl Default for TestCycleParams {
 }
 
 pub(crate) fn test_cycle(params: TestCycleParams) {
-    let screen = MockTestScreen::new();
+    let screen = HeadlessScreen::new();
     let cpu = &mut CPU::new(Box::new(screen));
     cpu.state.mem[cpu.state.pci()] = MemValue(params.op_code.to_be_bytes()[0]);
     cpu.state.mem[cpu.state.pci() + 1] = MemValue(params.op_code.to_be_bytes()[1]);
-    match params.pre_fn {
-        Some(f) => f(cpu, params.op_args.clone()),
-        None => {}
+    if let Some(f) = params.pre_fn {
+        f(cpu, params.op_args.clone());
     }
-    let mut screen_draw2 = MockTestScreen::new();
     let old_cpu_state = cpu.state.clone();
     (params.expectations)(TestScope {
-        screen_draw: &mut screen_draw2,
         old_cpu_state: old_cpu_state.clone(),
     });
     let old_pc = cpu.state.pc.0;
-    CPU::step(&mut cpu.state, &mut screen_draw2).expect("expected to run successfully");
+    CPU::step(&mut cpu.state).expect("expected to run successfully");
     cpu.state.update_timers();
-    match params.post_fn {
-        Some(f) => f(&cpu.state, TestScope {
-            screen_draw: &mut screen_draw2,
+    if let Some(f) = params.post_fn {
+        f(&cpu.state, TestScope {
             old_cpu_state: old_cpu_state.clone(),
-        }, params.op_args.clone()),
-        None => {}
+        }, params.op_args.clone());
     }
     if params.expect_inc {
         assert_eq!(cpu.state.pc.0, old_pc + u12::new(2));
@@ -0,0 +1,100 @@
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+pub const PALETTE_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgba(pub u8, pub u8, pub u8, pub u8);
+
+impl Rgba {
+    pub const BLACK: Rgba = Rgba(0x00, 0x00, 0x00, 0xFF);
+    pub const WHITE: Rgba = Rgba(0xFF, 0xFF, 0xFF, 0xFF);
+
+    /**
+     * Parses "#rgb", "#rrggbb" and "#rrggbbaa" (the leading "#" is optional)
+     */
+    pub fn from_hex(s: &str) -> Option<Rgba> {
+        let s = s.strip_prefix('#').unwrap_or(s);
+        let channel = |i: usize| u8::from_str_radix(s.get(i..i + 2)?, 16).ok();
+        match s.len() {
+            3 => {
+                let short = |i: usize| u8::from_str_radix(s.get(i..i + 1)?, 16).ok().map(|c| c * 0x11);
+                Some(Rgba(short(0)?, short(1)?, short(2)?, 0xFF))
+            }
+            6 => Some(Rgba(channel(0)?, channel(2)?, channel(4)?, 0xFF)),
+            8 => Some(Rgba(channel(0)?, channel(2)?, channel(4)?, channel(6)?)),
+            _ => None,
+        }
+    }
+}
+
+/**
+* Colours used by the renderers.
+* Palette index 0 is the background, 1 is the foreground;
+* 2 and 3 are only reachable in bitplane modes, where the index is built from the plane bits.
+*/
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Theme {
+    pub(crate) palette: [Rgba; PALETTE_SIZE],
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            palette: [Rgba::WHITE, Rgba::BLACK, Rgba(0xAA, 0xAA, 0xAA, 0xFF), Rgba(0x55, 0x55, 0x55, 0xFF)],
+        }
+    }
+}
+
+impl Theme {
+    pub fn from_colors(foreground: Rgba, background: Rgba) -> Self {
+        let mut theme = Theme::default();
+        theme.palette[0] = background;
+        theme.palette[1] = foreground;
+        theme
+    }
+    pub fn color(&self, index: usize) -> Rgba {
+        self.palette[index % PALETTE_SIZE]
+    }
+    pub fn background(&self) -> Rgba {
+        self.palette[0]
+    }
+    pub fn foreground(&self) -> Rgba {
+        self.palette[1]
+    }
+}
+
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+impl Theme {
+    #[wasm_bindgen(constructor)]
+    pub fn new(foreground: &str, background: &str) -> Result<Theme, JsValue> {
+        Ok(Theme::from_colors(parse_color(foreground)?, parse_color(background)?))
+    }
+
+    /**
+     * Sets one of the 4 bitplane palette entries, e.g. set_palette_color(2, "#ff0000")
+     */
+    pub fn set_palette_color(&mut self, index: usize, color: &str) -> Result<(), JsValue> {
+        if index >= PALETTE_SIZE {
+            return Err(JsValue::from_str("palette index out of range"));
+        }
+        self.palette[index] = parse_color(color)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "wasm")]
+fn parse_color(s: &str) -> Result<Rgba, JsValue> {
+    Rgba::from_hex(s).ok_or_else(|| JsValue::from_str(&format!("can't parse colour {}", s)))
+}
+
+#[test]
+fn test_rgba_from_hex() {
+    assert_eq!(Rgba::from_hex("#000000"), Some(Rgba::BLACK));
+    assert_eq!(Rgba::from_hex("fff"), Some(Rgba::WHITE));
+    assert_eq!(Rgba::from_hex("#10203040"), Some(Rgba(0x10, 0x20, 0x30, 0x40)));
+    assert_eq!(Rgba::from_hex("#12345"), None);
+    assert_eq!(Rgba::from_hex("#gg0000"), None);
+}
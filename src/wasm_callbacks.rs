@@ -0,0 +1,52 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::cpu::CPU;
+use crate::events::EmulatorEvent;
+
+/**
+* JS functions registered on WasmProgram
+*/
+#[derive(Default)]
+pub struct JsCallbacks {
+    pub(crate) frame: Option<js_sys::Function>,
+    pub(crate) sound: Option<js_sys::Function>,
+    pub(crate) waiting_for_key: Option<js_sys::Function>,
+    pub(crate) stopped: Option<js_sys::Function>,
+    pub(crate) breakpoint: Option<js_sys::Function>,
+}
+
+impl JsCallbacks {
+    /**
+     * Called by the run loop with the CPU released; neither the CPU nor the callbacks are borrowed
+     * while JS runs, so callbacks may use the WasmProgram, including registering other callbacks
+     */
+    pub(crate) fn dispatch(this: &Rc<RefCell<JsCallbacks>>, cpu: &Rc<RefCell<CPU>>, event: EmulatorEvent) {
+        let callbacks = this.borrow();
+        let (callback, arg) = match event {
+            // the framebuffer is only copied if someone listens
+            EmulatorEvent::FrameCompleted if callbacks.frame.is_some() => (
+                callbacks.frame.clone(),
+                js_sys::Uint8Array::from(&cpu.borrow().state.framebuffer.pixels().to_bytes()[..]).into(),
+            ),
+            EmulatorEvent::FrameCompleted => (None, JsValue::undefined()),
+            EmulatorEvent::SoundOn => (callbacks.sound.clone(), JsValue::from_bool(true)),
+            EmulatorEvent::SoundOff => (callbacks.sound.clone(), JsValue::from_bool(false)),
+            EmulatorEvent::WaitingForKey => (callbacks.waiting_for_key.clone(), JsValue::undefined()),
+            EmulatorEvent::BreakpointHit(pc) => (callbacks.breakpoint.clone(), JsValue::from(pc)),
+            EmulatorEvent::Stopped(reason) => (callbacks.stopped.clone(), JsValue::from_str(&reason.describe())),
+            EmulatorEvent::Sanitizer(report) => {
+                web_sys::console::warn_1(&JsValue::from_str(&report.to_string()));
+                (None, JsValue::undefined())
+            }
+        };
+        drop(callbacks);
+        if let Some(callback) = callback {
+            if let Err(e) = callback.call1(&JsValue::NULL, &arg) {
+                web_sys::console::error_2(&JsValue::from_str("chip8 event callback failed"), &e);
+            }
+        }
+    }
+}
@@ -1,53 +1,63 @@
-
+use std::cell::{Cell, RefCell};
 
 use callback_future::CallbackFuture;
 
 use futures::{FutureExt};
 use futures::future::LocalBoxFuture;
 
-use wasm_bindgen::JsCast;
-use web_sys::window;
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::{window, ImageData};
 use web_sys::CanvasRenderingContext2d;
 
-use crate::cpu_instructions::{X, Y};
-use crate::screen::{IsCollision, Screen, ScreenDraw, ScreenState, make_zero_screen_state, toggle_pixel};
+use crate::display_filter::{DisplayFilter, DisplayMode};
+use crate::framebuffer::Frame;
+use crate::pixel_buffer::PixelBuffer;
+use crate::screen::{Screen, ScreenDraw, ScreenState, make_zero_screen_state};
+use crate::theme::Theme;
 use wasm_bindgen::prelude::*;
 
+/**
+* Keeps an RGBA buffer in wasm memory and blits it with a single putImageData per frame
+*/
 pub struct WasmCanvasScreen {
+    // last presented frame, needed again at vblank
     state: ScreenState,
     canvas: web_sys::CanvasRenderingContext2d,
+    theme: Theme,
+    buffer: RefCell<PixelBuffer>,
+    filter: RefCell<DisplayFilter>,
+    dirty: Cell<bool>,
 }
 
-
 impl ScreenDraw for WasmCanvasScreen {
-    fn toggle_pixel(&mut self, x: X, y: Y) -> IsCollision {
-        let is_collision = toggle_pixel(&mut self.state, x, y.clone());
-        self.draw_pixel(x, y, !is_collision.0);
-        is_collision
-    }
-    fn repaint(&mut self) {
-        self.state.iter().enumerate().for_each(|(y, r)| {
-            r.iter().enumerate().for_each(|(x, c)| {
-                self.draw_pixel(X(x), Y(y), *c);
-            });
-        });
+    fn present(&mut self, frame: &Frame) {
+        if frame.dirty.is_none() {
+            return;
+        }
+        self.state = *frame.pixels;
+        self.filter.get_mut().on_draw(&self.state);
+        self.dirty.set(true);
     }
-    fn clear(&mut self) {
-        self.state = make_zero_screen_state();
-        self.state.iter().enumerate().for_each(|(y, r)| {
-            r.iter().enumerate().for_each(|(x, _)| {
-                self.draw_pixel(X(x), Y(y), false);
-            });
-        });
+
+    fn set_theme(&mut self, theme: &Theme) {
+        self.theme = theme.clone();
+        self.fill_background();
+        self.dirty.set(true);
     }
 }
 
 impl WasmCanvasScreen {
-    pub fn new(canvas: web_sys::CanvasRenderingContext2d) -> Self {
-        Self {
+    pub fn new(canvas: web_sys::CanvasRenderingContext2d, theme: Theme, display_mode: DisplayMode) -> Self {
+        let screen = Self {
             state: make_zero_screen_state(),
             canvas,
-        }
+            theme,
+            buffer: RefCell::new(PixelBuffer::new(1)),
+            filter: RefCell::new(DisplayFilter::new(display_mode)),
+            dirty: Cell::new(true),
+        };
+        screen.fill_background();
+        screen
     }
     fn get_canvas_context(&self) -> &CanvasRenderingContext2d {
         &self.canvas
@@ -55,29 +65,53 @@ impl WasmCanvasScreen {
     fn get_canvas_size(&self) -> (u32, u32) {
         (self.canvas.canvas().unwrap().width(), self.canvas.canvas().unwrap().height())
     }
-    fn get_canvas_scale(&self) -> (f32, f32) {
+    // integer scale so that every chip8 pixel maps to a whole square of canvas pixels
+    fn get_canvas_scale(&self) -> usize {
         let (width, height) = self.get_canvas_size();
-        let scale_x = width as f32 / self.get_width() as f32;
-        let scale_y = height as f32 / self.get_height() as f32;
-        (scale_x, scale_y)
+        let scale_x = width as usize / self.get_width();
+        let scale_y = height as usize / self.get_height();
+        scale_x.min(scale_y).max(1)
     }
-    fn draw_pixel(&self, x: X, y: Y, yes: bool) {
-        let (scale_x, scale_y) = self.get_canvas_scale();
+    fn fill_background(&self) {
+        let (width, height) = self.get_canvas_size();
+        let bg = self.theme.background();
         let ctx = self.get_canvas_context();
-        ctx.set_fill_style(&JsValue::from_str(if yes { "black" } else { "white" }));
-        ctx.fill_rect((x.0 as f32 * scale_x).into(), (y.0 as f32 * scale_y).into(), scale_x.into(), scale_y.into());
+        ctx.set_fill_style(&JsValue::from_str(&format!("rgba({}, {}, {}, {})", bg.0, bg.1, bg.2, bg.3 as f32 / 255.0)));
+        ctx.fill_rect(0.0, 0.0, width.into(), height.into());
+    }
+    fn present(&self) {
+        let mut filter = self.filter.borrow_mut();
+        filter.on_vblank(&self.state);
+        // decaying/blended pixels change every frame even if nothing was drawn
+        if !self.dirty.replace(false) && filter.presents_on_draw() {
+            return;
+        }
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.set_scale(self.get_canvas_scale());
+        buffer.blit_intensity(filter.intensity(), &self.theme);
+        let image = ImageData::new_with_u8_clamped_array_and_sh(Clamped(buffer.data()), buffer.width() as u32, buffer.height() as u32)
+            .expect("pixel buffer size should match ImageData size");
+        self.get_canvas_context().put_image_data(&image, 0.0, 0.0).expect("should put image data");
     }
 }
 
 impl Screen for WasmCanvasScreen {
-    fn request_animation_frame(&self) -> LocalBoxFuture<()> {
-        let f = CallbackFuture::new(|complete| {
-            window()
-                .expect("Should have window")
-                .request_animation_frame(Closure::once_into_js(move || {complete(())}).as_ref().unchecked_ref())
-                .expect("should register `requestAnimationFrame` OK");
-        });
-
-        f.boxed_local()
+    fn request_animation_frame(&self) -> LocalBoxFuture<'static, ()> {
+        next_animation_frame()
     }
-}
\ No newline at end of file
+    fn vblank(&self) {
+        // everything drawn since the last frame goes out in one blit
+        self.present();
+    }
+}
+
+pub(crate) fn next_animation_frame() -> LocalBoxFuture<'static, ()> {
+    let f = CallbackFuture::new(|complete| {
+        window()
+            .expect("Should have window")
+            .request_animation_frame(Closure::once_into_js(move || {complete(())}).as_ref().unchecked_ref())
+            .expect("should register `requestAnimationFrame` OK");
+    });
+
+    f.boxed_local()
+}
@@ -0,0 +1,414 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use wasm_bindgen::prelude::*;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::spawn_local;
+
+use crate::cheats::{Cheat, CheatList, Comparison, MemorySearch};
+use crate::coverage::Coverage;
+use crate::cpu::CPU;
+use crate::command_queue::{Command, CommandQueue};
+use crate::display_filter::DisplayOptions;
+use crate::rom::Rom;
+use crate::rom_db::RomDatabase;
+use crate::screen::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::theme::Theme;
+use crate::headless_screen::HeadlessScreen;
+use crate::js_screen::{JsScreen, JsTarget};
+use crate::wasm_callbacks::JsCallbacks;
+use crate::wasm_canvas_screen::WasmCanvasScreen;
+
+#[wasm_bindgen]
+pub struct WasmProgram {
+    cpu: Rc<RefCell<CPU>>,
+    commands: CommandQueue,
+    callbacks: Rc<RefCell<JsCallbacks>>,
+    // see start_search
+    search: Option<MemorySearch>,
+    // a JS screen is only called once the CPU is released, see JsTarget::flush
+    js_screen: Option<Rc<JsTarget>>,
+}
+
+// calls only enqueue; the run loop applies them between frames, in order
+#[wasm_bindgen]
+impl WasmProgram {
+    pub fn run(&self) {
+        let clone = self.cpu.clone();
+        let cpu = self.cpu.clone();
+        let callbacks = self.callbacks.clone();
+        spawn_local(async move {
+            CPU::run_with_listener(clone, move |event| JsCallbacks::dispatch(&callbacks, &cpu, event)).await;
+        })
+    }
+
+    /**
+     * Runs n frames synchronously instead of run(), e.g. in Node.js.
+     * Queued commands are applied and callbacks called as in run(). Returns false once stopped
+     */
+    pub fn run_frames(&mut self, n: usize) -> bool {
+        for _ in 0..n {
+            let (events, done) = {
+                let mut cpu = self.cpu.borrow_mut();
+                cpu.run_frame_now();
+                (cpu.take_events(), cpu.is_done())
+            };
+            if let Some(screen) = &self.js_screen {
+                screen.flush();
+            }
+            for event in events {
+                JsCallbacks::dispatch(&self.callbacks, &self.cpu, event);
+            }
+            if done {
+                return false;
+            }
+        }
+        true
+    }
+    pub fn stop(&mut self) {
+        self.commands.push(Command::Stop);
+    }
+
+    pub fn key_down(&mut self, k: usize) {
+        self.commands.push(Command::KeyDown(k));
+    }
+
+    pub fn key_up(&mut self, k: usize) {
+        self.commands.push(Command::KeyUp(k));
+    }
+
+    /** Stops running instructions and timers until resume(); the run loop keeps going */
+    pub fn pause(&mut self) {
+        self.commands.push(Command::Pause);
+    }
+
+    pub fn resume(&mut self) {
+        self.commands.push(Command::Resume);
+    }
+
+    /** Runs a single frame while paused */
+    pub fn advance_frame(&mut self) {
+        self.commands.push(Command::AdvanceFrame);
+    }
+
+    /** Re-initializes the CPU, reloads fonts and the current ROM */
+    pub fn reset(&mut self) {
+        self.commands.push(Command::Reset);
+    }
+
+    /** Swaps the game, keeping the canvas binding; throws on a file that isn't a ROM */
+    pub fn load_rom(&mut self, program: &[u8]) -> Result<(), JsValue> {
+        self.commands.push(Command::LoadRom(parse_rom(None, program)?));
+        Ok(())
+    }
+
+    /** Same as load_rom; the name's extension (.ch8, .sc8, .xo8) picks the platform */
+    pub fn load_rom_file(&mut self, file_name: &str, program: &[u8]) -> Result<(), JsValue> {
+        self.commands.push(Command::LoadRom(parse_rom(Some(file_name), program)?));
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.cpu.borrow().is_paused()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.cpu.borrow().is_done()
+    }
+
+    /** V0..VF */
+    pub fn registers(&self) -> Vec<u8> {
+        self.cpu.borrow().state.v.iter().map(|v| v.0).collect()
+    }
+
+    pub fn i(&self) -> u16 {
+        self.cpu.borrow().state.i.0.into()
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.cpu.borrow().state.pc.0.into()
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.cpu.borrow().state.sp.0.into()
+    }
+
+    pub fn stack(&self) -> Vec<u16> {
+        self.cpu.borrow().state.stack.iter().map(|a| u16::from(*a)).collect()
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.cpu.borrow().state.dt.0
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.cpu.borrow().state.st.0
+    }
+
+    /** Copy of len bytes of memory from start, cut at the end of memory */
+    pub fn memory(&self, start: usize, len: usize) -> Vec<u8> {
+        let cpu = self.cpu.borrow();
+        let mem = &cpu.state.mem;
+        let start = start.min(mem.len());
+        let end = start.saturating_add(len).min(mem.len());
+        mem[start..end].iter().map(|m| m.0).collect()
+    }
+
+    /** One byte per pixel (0 or 1), row-major, framebuffer_width() x framebuffer_height() */
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.cpu.borrow().state.framebuffer.pixels().to_bytes()
+    }
+
+    pub fn framebuffer_width(&self) -> usize {
+        SCREEN_WIDTH
+    }
+
+    pub fn framebuffer_height(&self) -> usize {
+        SCREEN_HEIGHT
+    }
+
+    /** Pauses before the instruction at addr; resume() or advance_frame() to continue */
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.commands.push(Command::AddBreakpoint(addr));
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.commands.push(Command::RemoveBreakpoint(addr));
+    }
+
+    /**
+     * ROM database as the chip-8-database's programs.json; from the next frame, the current ROM and the ones loaded
+     * later get its quirks, speed, keys and colours when it knows them
+     */
+    pub fn set_rom_database(&mut self, json: &str) -> Result<(), JsValue> {
+        let db = RomDatabase::from_json(json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.commands.push(Command::SetRomDatabase(Arc::new(db)));
+        Ok(())
+    }
+
+    /** From the ROM database, undefined for unknown ROMs */
+    pub fn rom_title(&self) -> Option<String> {
+        self.cpu.borrow().rom_info().map(|info| info.title.clone())
+    }
+
+    /** Comma-separated, from the ROM database */
+    pub fn rom_authors(&self) -> Option<String> {
+        self.cpu.borrow().rom_info().map(|info| info.authors.join(", "))
+    }
+
+    /** Starts counting executed instructions from scratch, for the profile_* functions */
+    pub fn enable_profiler(&mut self) {
+        self.commands.push(Command::EnableProfiler);
+    }
+
+    pub fn disable_profiler(&mut self) {
+        self.commands.push(Command::DisableProfiler);
+    }
+
+    /** Text summary of hotspots, opcode classes, draws and subroutines; undefined without the profiler */
+    pub fn profile_report(&self) -> Option<String> {
+        self.cpu.borrow().profiler().map(|profiler| profiler.report())
+    }
+
+    /** Folded stacks for flame graph tools */
+    pub fn profile_folded(&self) -> Option<String> {
+        self.cpu.borrow().profiler().map(|profiler| profiler.folded())
+    }
+
+    /** The n most executed addresses as [address, opcode, executions, ...], for an overlay */
+    pub fn profile_hotspots(&self, n: usize) -> Vec<u32> {
+        let cpu = self.cpu.borrow();
+        let hotspots = cpu.profiler().map(|profiler| profiler.hotspots(n)).unwrap_or_default();
+        hotspots.into_iter().flat_map(|(pc, opcode, count)| [u32::from(pc), u32::from(opcode), count.min(u64::from(u32::MAX)) as u32]).collect()
+    }
+
+    /** [instructions, draws, sprite rows] of the last frame run, empty without the profiler */
+    pub fn profile_last_frame(&self) -> Vec<u32> {
+        let cpu = self.cpu.borrow();
+        let last = cpu.profiler().and_then(|profiler| profiler.frames().last().copied());
+        last.map(|frame| vec![frame.instructions, frame.draws, frame.sprite_rows]).unwrap_or_default()
+    }
+
+    /** Records executed, read and written bytes from now on, across resets and load_rom */
+    pub fn enable_coverage(&mut self) {
+        self.commands.push(Command::EnableCoverage);
+    }
+
+    /** 64x64 RGBA pixels, one per address, for an ImageData; empty without coverage */
+    pub fn coverage_heatmap(&self) -> Vec<u8> {
+        self.cpu.borrow().coverage().map(|coverage| coverage.heatmap()).unwrap_or_default()
+    }
+
+    /** The program listed with the flags of each byte */
+    pub fn coverage_disassembly(&self) -> Option<String> {
+        let cpu = self.cpu.borrow();
+        cpu.coverage().map(|coverage| coverage.disassembly(cpu.program(), cpu.load_address()))
+    }
+
+    /** To save and merge_coverage() in a later session */
+    pub fn coverage_bytes(&self) -> Option<Vec<u8>> {
+        self.cpu.borrow().coverage().map(|coverage| coverage.to_bytes())
+    }
+
+    pub fn merge_coverage(&mut self, data: &[u8]) -> Result<(), JsValue> {
+        let other = Coverage::from_bytes(data).ok_or_else(|| JsValue::from_str("not coverage data"))?;
+        self.commands.push(Command::MergeCoverage(other));
+        Ok(())
+    }
+
+    /** Records the instruction and frame of every memory write from now on */
+    pub fn enable_provenance(&mut self) {
+        self.commands.push(Command::EnableProvenance);
+    }
+
+    /** [pc, opcode, frame, value] of the last write to addr, empty if there's none */
+    pub fn last_writer(&self, addr: u16) -> Vec<u32> {
+        let write = self.cpu.borrow().last_writer(addr);
+        write.map(|w| vec![u32::from(w.pc), u32::from(w.opcode), w.frame as u32, u32::from(w.value)]).unwrap_or_default()
+    }
+
+    /** Keeps a history of the writes to start..end; enables provenance */
+    pub fn watch_writes(&mut self, start: u16, end: u16) {
+        self.commands.push(Command::WatchWrites(start, end));
+    }
+
+    pub fn unwatch_writes(&mut self, start: u16, end: u16) {
+        self.commands.push(Command::UnwatchWrites(start, end));
+    }
+
+    /** Writes to a watched start..end as [addr, value, pc, opcode, frame, ...], oldest first */
+    pub fn write_history(&self, start: u16, end: u16) -> Vec<u32> {
+        let history = self.cpu.borrow().write_history(start, end);
+        history.into_iter().flat_map(|w| [u32::from(w.addr), u32::from(w.value), u32::from(w.pc), u32::from(w.opcode), w.frame as u32]).collect()
+    }
+
+    /** Replaces the cheats with a cheat file's text, e.g. kept per ROM in localStorage */
+    pub fn set_cheats(&mut self, text: &str) -> Result<(), JsValue> {
+        let cheats = CheatList::parse(text).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        self.commands.push(Command::SetCheats(cheats));
+        Ok(())
+    }
+
+    /** The cheats in the format set_cheats takes */
+    pub fn cheats(&self) -> String {
+        self.cpu.borrow().cheats().to_string()
+    }
+
+    /** Holds addr at value from the next frame on */
+    pub fn freeze(&mut self, addr: u16, value: u8) {
+        self.commands.push(Command::AddCheat(Cheat::freeze(addr, value)));
+    }
+
+    pub fn unfreeze(&mut self, addr: u16) {
+        self.commands.push(Command::RemoveCheats(addr));
+    }
+
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.commands.push(Command::Poke(addr, value));
+    }
+
+    /** Snapshots memory; every address is a candidate until narrow_search */
+    pub fn start_search(&mut self) {
+        self.search = Some(MemorySearch::new(&self.memory(0, usize::MAX)));
+    }
+
+    /**
+     * Keeps the addresses whose byte is "equal" to value, or "changed", "unchanged", "decreased"
+     * or "increased" since the last snapshot, and returns them
+     */
+    pub fn narrow_search(&mut self, comparison: &str, value: u8) -> Result<Vec<u16>, JsValue> {
+        let comparison = Comparison::from_name(comparison, value).ok_or_else(|| JsValue::from_str("unknown comparison"))?;
+        let memory = self.memory(0, usize::MAX);
+        let search = self.search.get_or_insert_with(|| MemorySearch::new(&memory));
+        Ok(search.narrow(&memory, comparison).to_vec())
+    }
+
+    /** cb(framebuffer: Uint8Array) after every emulated frame */
+    pub fn on_frame(&mut self, cb: js_sys::Function) {
+        self.callbacks.borrow_mut().frame = Some(cb);
+    }
+
+    /** cb(on: boolean) when the sound timer starts or stops */
+    pub fn on_sound(&mut self, cb: js_sys::Function) {
+        self.callbacks.borrow_mut().sound = Some(cb);
+    }
+
+    /** cb() when Fx0A starts waiting for a key */
+    pub fn on_waiting_for_key(&mut self, cb: js_sys::Function) {
+        self.callbacks.borrow_mut().waiting_for_key = Some(cb);
+    }
+
+    /** cb(reason: string) when the emulator stops, on request or on an error */
+    pub fn on_stopped(&mut self, cb: js_sys::Function) {
+        self.callbacks.borrow_mut().stopped = Some(cb);
+    }
+
+    /** cb(pc: number) when a breakpoint pauses the emulator */
+    pub fn on_breakpoint(&mut self, cb: js_sys::Function) {
+        self.callbacks.borrow_mut().breakpoint = Some(cb);
+    }
+}
+
+impl WasmProgram {
+    fn new(cpu: CPU) -> Self {
+        let commands = cpu.command_queue();
+        WasmProgram { cpu: Rc::new(RefCell::new(cpu)), commands, callbacks: Rc::new(RefCell::new(JsCallbacks::default())), search: None, js_screen: None }
+    }
+}
+
+// raw bytes or an Octo cartridge GIF, told apart by content
+fn parse_rom(file_name: Option<&str>, program: &[u8]) -> Result<Rom, JsValue> {
+    Rom::parse(file_name, program).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+fn load(cpu: &mut CPU, program: &[u8]) -> Result<(), JsValue> {
+    cpu.load(parse_rom(None, program)?).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+#[wasm_bindgen]
+pub fn init_program(program: &[u8], canvas: JsValue) -> Result<WasmProgram, JsValue> {
+    init_program_with_theme(program, canvas, &Theme::default())
+}
+
+#[wasm_bindgen]
+pub fn init_program_with_theme(program: &[u8], canvas: JsValue, theme: &Theme) -> Result<WasmProgram, JsValue> {
+    init_program_with_options(program, canvas, theme, &DisplayOptions::off())
+}
+
+#[wasm_bindgen]
+pub fn init_program_with_options(program: &[u8], canvas: JsValue, theme: &Theme, display: &DisplayOptions) -> Result<WasmProgram, JsValue> {
+    match canvas.dyn_into::<web_sys::CanvasRenderingContext2d>() {
+        Ok(canvas) => {
+            let mut cpu = CPU::new(Box::new(WasmCanvasScreen::new(canvas, theme.clone(), display.mode())));
+            load(&mut cpu, program)?;
+            Ok(WasmProgram::new(cpu))
+        }
+        Err(_) => Err(JsValue::from_str("canvas argument not a HtmlCanvas")),
+    }
+}
+
+/**
+ * No canvas; read framebuffer() after run_frames(n), or listen with on_frame
+ */
+#[wasm_bindgen]
+pub fn init_headless(program: &[u8]) -> Result<WasmProgram, JsValue> {
+    let mut cpu = CPU::new(Box::new(HeadlessScreen::new()));
+    load(&mut cpu, program)?;
+    Ok(WasmProgram::new(cpu))
+}
+
+/**
+ * Frames go to a JS object with draw(pixels, width, height, dirty), and optionally clear() and present().
+ * They're called between frames, so the object may use the WasmProgram
+ */
+#[wasm_bindgen]
+pub fn init_program_with_screen(program: &[u8], screen: JsValue) -> Result<WasmProgram, JsValue> {
+    let screen = JsScreen::new(screen)?;
+    let target = screen.target();
+    let mut cpu = CPU::new(Box::new(screen));
+    load(&mut cpu, program)?;
+    let mut program = WasmProgram::new(cpu);
+    program.js_screen = Some(target);
+    Ok(program)
+}
@@ -0,0 +1,83 @@
+/* Built and run by tests/capi_smoke.rs against the cdylib */
+#include <stdio.h>
+#include <string.h>
+
+#include "chip8.h"
+
+#define CHECK(cond) do { if (!(cond)) { fprintf(stderr, "%s:%d: %s\n", __FILE__, __LINE__, #cond); return 1; } } while (0)
+
+/* draws the font sprite for 0 at (0, 0), beeps for 5 frames, then loops */
+static const uint8_t ROM[] = {
+    0x60, 0x00, /* LD V0, 0 */
+    0x61, 0x00, /* LD V1, 0 */
+    0xF0, 0x29, /* LD F, V0 */
+    0xD0, 0x15, /* DRW V0, V1, 5 */
+    0x62, 0x05, /* LD V2, 5 */
+    0xF2, 0x18, /* LD ST, V2 */
+    0x12, 0x0C, /* JP 0x20C */
+};
+
+int main(void) {
+    uint8_t too_large[4096] = {0};
+    CHECK(chip8_new(too_large, sizeof too_large) == NULL);
+
+    Chip8 *chip8 = chip8_new(ROM, sizeof ROM);
+    CHECK(chip8 != NULL);
+    chip8_seed(chip8, 42);
+
+    uint32_t events = chip8_run_frame(chip8);
+    CHECK(events & CHIP8_EVENT_SOUND_ON);
+    CHECK(chip8_sound_on(chip8));
+
+    size_t size = chip8_framebuffer_width() * chip8_framebuffer_height();
+    uint8_t pixels[64 * 32];
+    CHECK(size == sizeof pixels);
+    CHECK(chip8_framebuffer(chip8, pixels, sizeof pixels) == size);
+    /* 0xF0, 0x90 */
+    CHECK(memcmp(pixels, "\1\1\1\1\0", 5) == 0);
+    CHECK(memcmp(pixels + 64, "\1\0\0\1\0", 5) == 0);
+
+    Chip8Registers registers;
+    CHECK(chip8_registers(chip8, &registers) == CHIP8_STATUS_OK);
+    CHECK(registers.pc == 0x20C);
+    CHECK(registers.v[2] == 5);
+    CHECK(registers.sound_timer == 4);
+
+    uint8_t state[8192];
+    CHECK(chip8_state_size() <= sizeof state);
+    CHECK(chip8_save_state(chip8, state, 1) == 0);
+    size_t state_size = chip8_save_state(chip8, state, sizeof state);
+    CHECK(state_size == chip8_state_size());
+
+    for (int i = 0; i < 4; i++) {
+        events |= chip8_run_frame(chip8);
+    }
+    CHECK(events & CHIP8_EVENT_SOUND_OFF);
+    CHECK(!chip8_sound_on(chip8));
+
+    CHECK(chip8_load_state(chip8, state, state_size) == CHIP8_STATUS_OK);
+    CHECK(chip8_sound_on(chip8));
+    CHECK(chip8_load_state(chip8, state, state_size - 1) == CHIP8_STATUS_BAD_STATE);
+
+    /* Fx0A waits until a key goes down */
+    static const uint8_t WAIT[] = { 0xF3, 0x0A, 0x12, 0x02 };
+    CHECK(chip8_load_rom(chip8, WAIT, sizeof WAIT) == CHIP8_STATUS_OK);
+    CHECK(chip8_run_frame(chip8) & CHIP8_EVENT_WAITING_FOR_KEY);
+    chip8_key_down(chip8, 0xA);
+    chip8_key_up(chip8, 0xA);
+    CHECK(chip8_step(chip8) == CHIP8_STATUS_OK);
+    CHECK(chip8_registers(chip8, &registers) == CHIP8_STATUS_OK);
+    CHECK(registers.v[3] == 0xA);
+    CHECK(registers.pc == 0x202);
+
+    static const uint8_t BAD[] = { 0xF0, 0xFF };
+    CHECK(chip8_load_rom(chip8, BAD, sizeof BAD) == CHIP8_STATUS_OK);
+    CHECK(chip8_step(chip8) == CHIP8_STATUS_DECODE_ERROR);
+
+    CHECK(chip8_load_rom(chip8, too_large, sizeof too_large) == CHIP8_STATUS_ROM_TOO_LARGE);
+    CHECK(chip8_registers(NULL, &registers) == CHIP8_STATUS_NULL_POINTER);
+
+    chip8_free(chip8);
+    puts("ok");
+    return 0;
+}
@@ -0,0 +1,58 @@
+//! Compiles tests/c/smoke.c against include/chip8.h and the built cdylib, then runs it.
+//! cargo test --no-default-features --features capi
+// with `python` the cdylib is a Python extension module that only links inside an interpreter
+#![cfg(all(feature = "capi", not(feature = "python"), target_os = "linux"))]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// cargo puts the cdylib next to the test binary's deps/ directory
+fn library_dir() -> PathBuf {
+    let deps = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    [deps.clone(), deps.parent().unwrap().to_path_buf()]
+        .into_iter()
+        .find(|dir| dir.join("librust_wasm_chip8.so").exists())
+        .expect("cdylib not built")
+}
+
+// build.rs generates the header into OUT_DIR; UPDATE_HEADER=1 copies it over the checked-in one
+#[test]
+fn header_is_up_to_date() {
+    let generated = include_str!(concat!(env!("OUT_DIR"), "/chip8.h"));
+    let header = Path::new(env!("CARGO_MANIFEST_DIR")).join("include/chip8.h");
+    if std::env::var_os("UPDATE_HEADER").is_some() {
+        std::fs::write(&header, generated).unwrap();
+    }
+    assert!(std::fs::read_to_string(&header).unwrap() == generated, "include/chip8.h is stale, rerun with UPDATE_HEADER=1");
+}
+
+#[test]
+fn c_smoke_test() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let lib = library_dir();
+    let exe = std::env::temp_dir().join(format!("chip8_capi_smoke_{}", std::process::id()));
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg("-std=c99")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg(root.join("tests/c/smoke.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg("-L")
+        .arg(&lib)
+        .arg(format!("-Wl,-rpath,{}", lib.display()))
+        .arg("-lrust_wasm_chip8")
+        .arg("-o")
+        .arg(&exe)
+        .status()
+        .expect("can't run the C compiler");
+    assert!(status.success(), "smoke.c doesn't compile");
+    // cargo test puts target/debug on LD_LIBRARY_PATH, which wins over the rpath and may hold a stale copy
+    let output = Command::new(&exe)
+        .env("LD_LIBRARY_PATH", &lib)
+        .output()
+        .expect("can't run the smoke test");
+    let _ = std::fs::remove_file(&exe);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "ok");
+}
@@ -0,0 +1,327 @@
+//! Written only against the public embedding API
+use std::sync::{Arc, Mutex};
+
+use rust_wasm_chip8::analysis::Variant;
+use rust_wasm_chip8::cheats::CheatList;
+use rust_wasm_chip8::command_queue::CommandQueue;
+use rust_wasm_chip8::coverage::{EXECUTED, READ};
+use rust_wasm_chip8::provenance::MemoryWrite;
+use rust_wasm_chip8::rom::{ETI_660_LOAD_ADDRESS, HIRES_LOAD_ADDRESS};
+use rust_wasm_chip8::sanitizer::{Finding, Report, SanitizerOptions};
+use rust_wasm_chip8::{Audio, Chip8, Chip8Error, EmulatorEvent, LoadError, Platform, Rom, RomFormat, StepError};
+
+// draws the font sprite for 0 at (0, 0), then loops forever
+const DRAW_ZERO: [u8; 10] = [
+    0x60, 0x00, // LD V0, 0
+    0x61, 0x00, // LD V1, 0
+    0xF0, 0x29, // LD F, V0
+    0xD0, 0x15, // DRW V0, V1, 5
+    0x12, 0x08, // JP 0x208
+];
+
+#[test]
+fn draws_a_sprite() {
+    let mut chip8 = Chip8::builder().rom(&DRAW_ZERO).build().unwrap();
+    let events = chip8.run_frame();
+    assert!(events.contains(&EmulatorEvent::FrameCompleted));
+    let pixels = chip8.pixels();
+    // 0xF0, 0x90
+    assert_eq!((0..5).map(|x| pixels.get(x, 0)).collect::<Vec<_>>(), vec![true, true, true, true, false]);
+    assert_eq!((0..5).map(|x| pixels.get(x, 1)).collect::<Vec<_>>(), vec![true, false, false, true, false]);
+    assert_eq!(chip8.pc(), 0x208);
+    assert_eq!(chip8.i(), 0);
+}
+
+#[test]
+fn waits_for_a_key() {
+    let rom = [
+        0xF3, 0x0A, // LD V3, K
+        0x12, 0x02, // JP 0x202
+    ];
+    let mut chip8 = Chip8::builder().rom(&rom).build().unwrap();
+    assert!(chip8.run_frame().contains(&EmulatorEvent::WaitingForKey));
+    assert!(chip8.is_waiting_for_key());
+    chip8.key_down(0xB);
+    assert!(chip8.is_key_pressed(0xB));
+    assert!(!chip8.is_waiting_for_key());
+    assert_eq!(chip8.registers()[3], 0xB);
+    assert_eq!(chip8.pc(), 0x202);
+    chip8.key_up(0xB);
+    assert!(!chip8.is_key_pressed(0xB));
+}
+
+#[test]
+fn same_seed_same_numbers() {
+    let rom = [
+        0xC0, 0xFF, // RND V0, 0xFF
+        0xC1, 0xFF, // RND V1, 0xFF
+        0x12, 0x04, // JP 0x204
+    ];
+    let run = |seed| {
+        let mut chip8 = Chip8::builder().seed(seed).rom(&rom).build().unwrap();
+        chip8.run_frame();
+        chip8.registers()
+    };
+    assert_eq!(run(42), run(42));
+}
+
+#[test]
+fn clock_sets_instructions_per_frame() {
+    let rom = [
+        0x70, 0x01, // ADD V0, 1
+        0x12, 0x00, // JP 0x200
+    ];
+    let v0_after_a_frame = |hz| {
+        let mut chip8 = Chip8::builder().clock(hz).rom(&rom).build().unwrap();
+        chip8.run_frame();
+        chip8.registers()[0]
+    };
+    assert_eq!(v0_after_a_frame(600), 5);
+    assert_eq!(v0_after_a_frame(1200), 10);
+}
+
+#[test]
+fn step_runs_one_instruction() {
+    let mut chip8 = Chip8::builder().rom(&DRAW_ZERO).build().unwrap();
+    chip8.step().unwrap();
+    assert_eq!(chip8.pc(), 0x202);
+    assert_eq!(chip8.peek(0x200), 0x60);
+    assert_eq!(chip8.memory()[0x201], 0x00);
+}
+
+#[test]
+fn platform_quirks() {
+    let rom = [
+        0x60, 0x02, // LD V0, 2
+        0x61, 0x08, // LD V1, 8
+        0x80, 0x16, // SHR V0, V1
+        0x12, 0x06, // JP 0x206
+    ];
+    let v0 = |platform| {
+        let mut chip8 = Chip8::builder().platform(platform).rom(&rom).build().unwrap();
+        chip8.run_frame();
+        chip8.registers()[0]
+    };
+    assert_eq!(v0(Platform::Chip8), 4);
+    assert_eq!(v0(Platform::SuperChip), 1);
+}
+
+#[test]
+fn command_queue_is_send() {
+    fn assert_send<T: Send>() {}
+    assert_send::<CommandQueue>();
+}
+
+struct Recorder(Arc<Mutex<Vec<bool>>>);
+
+impl Audio for Recorder {
+    fn set_tone(&mut self, on: bool) {
+        self.0.lock().unwrap().push(on);
+    }
+}
+
+#[test]
+fn tone_follows_the_sound_timer() {
+    let rom = [
+        0x60, 0x03, // LD V0, 3
+        0xF0, 0x18, // LD ST, V0
+        0x12, 0x04, // JP 0x204
+    ];
+    let tones = Arc::new(Mutex::new(vec![]));
+    let mut chip8 = Chip8::builder().audio(Box::new(Recorder(tones.clone()))).rom(&rom).build().unwrap();
+    assert!(chip8.run_frame().contains(&EmulatorEvent::SoundOn));
+    assert!(chip8.is_sound_on());
+    chip8.run_frame();
+    assert!(chip8.run_frame().contains(&EmulatorEvent::SoundOff));
+    assert_eq!(*tones.lock().unwrap(), vec![true, false]);
+}
+
+#[test]
+fn rejects_oversized_roms() {
+    let rom = vec![0; 4096];
+    match Chip8::builder().rom(&rom).build() {
+        Err(Chip8Error::RomTooLarge { size, max }) => assert_eq!((size, max), (4096, 3584)),
+        _ => panic!("expected RomTooLarge"),
+    }
+    let mut chip8 = Chip8::builder().rom(&DRAW_ZERO).build().unwrap();
+    assert!(chip8.load_rom(&rom).is_err());
+    assert!(chip8.load_rom(&[0x12, 0x00]).is_ok());
+    assert_eq!(chip8.peek(0x200), 0x12);
+}
+
+#[test]
+fn loads_at_other_addresses() {
+    // JP 0x600
+    let mut chip8 = Chip8::builder().rom(&[0x16, 0x00]).load_address(ETI_660_LOAD_ADDRESS).build().unwrap();
+    assert_eq!((chip8.pc(), chip8.peek(0x600), chip8.peek(0x200)), (0x600, 0x16, 0));
+    chip8.run_frame();
+    assert!(!chip8.is_stopped());
+    chip8.reset();
+    assert_eq!(chip8.pc(), 0x600);
+    // raw bytes go where the last program went
+    chip8.load_rom(&[0x16, 0x02, 0x16, 0x02]).unwrap();
+    assert_eq!((chip8.pc(), chip8.peek(0x602)), (0x600, 0x16));
+    match Chip8::builder().load_address(0x1FF).build() {
+        Err(Chip8Error::InvalidRom(LoadError::BadLoadAddress(0x1FF))) => {}
+        _ => panic!("expected BadLoadAddress"),
+    }
+
+    let rom = Rom::parse(Some("spin.sc8"), &[0x12, 0x00]).unwrap();
+    assert_eq!(rom.format, RomFormat::SuperChip);
+    let chip8 = Chip8::builder().rom_file(rom.at(HIRES_LOAD_ADDRESS).unwrap()).build().unwrap();
+    assert_eq!(chip8.pc(), 0x2C0);
+}
+
+#[test]
+fn picks_the_platform_from_the_file() {
+    let rom = [
+        0x60, 0x03, // LD V0, 3
+        0x61, 0x08, // LD V1, 8
+        0x80, 0x16, // SHR V0, V1
+        0x12, 0x06, // JP 0x206
+    ];
+    // SUPER-CHIP shifts V0 in place, CHIP-8 shifts V1 into it
+    let mut chip8 = Chip8::builder().rom_file(Rom::parse(Some("shift.sc8"), &rom).unwrap()).build().unwrap();
+    chip8.run_frame();
+    assert_eq!(chip8.registers()[0], 1);
+    // swapping in plain bytes switches back
+    chip8.load_rom(&rom).unwrap();
+    chip8.run_frame();
+    assert_eq!(chip8.registers()[0], 4);
+}
+
+#[test]
+fn refuses_superchip_code() {
+    let rom = [
+        0x00, 0xFF, // HIGH, SUPER-CHIP only
+        0x12, 0x02, // JP 0x202
+    ];
+    let unsupported = LoadError::UnsupportedVariant { variant: Variant::SuperChip, addr: 0x200, opcode: 0x00FF };
+    assert_eq!(Chip8::builder().rom(&rom).build().err(), Some(Chip8Error::InvalidRom(unsupported.clone())));
+    assert_eq!(Chip8::builder().rom(&rom).platform(Platform::SuperChip).build().err(), Some(Chip8Error::InvalidRom(unsupported)));
+    let mut chip8 = Chip8::builder().rom(&[0x12, 0x00]).build().unwrap();
+    assert!(chip8.load_rom(&rom).is_err());
+    assert_eq!(chip8.pc(), 0x200);
+}
+
+#[test]
+fn sanitizer_reports_rom_bugs() {
+    let rom = [
+        0xA1, 0x00, // LD I, 0x100
+        0xF0, 0x55, // LD [I], V0
+        0x22, 0x0A, // CALL 0x20A
+        0x12, 0x06, // JP 0x206
+        0x00, 0x00,
+        0xA3, 0x00, // LD I, 0x300
+        0xD0, 0x15, // DRW V0, V1, 5
+        0x00, 0xEE, // RET
+    ];
+    let mut chip8 = Chip8::builder().sanitizer(SanitizerOptions::default()).rom(&rom).build().unwrap();
+    let reports: Vec<Report> = chip8.run_frame().into_iter().filter_map(|event| match event {
+        EmulatorEvent::Sanitizer(report) => Some(report),
+        _ => None,
+    }).collect();
+    assert_eq!(reports, vec![
+        Report { pc: 0x202, opcode: 0xF055, finding: Finding::WriteBelowProgram(0x100), call_stack: vec![] },
+        Report { pc: 0x20C, opcode: 0xD015, finding: Finding::UninitialisedRead(0x300), call_stack: vec![0x204] },
+    ]);
+    assert!(!chip8.is_stopped());
+
+    let mut chip8 = Chip8::builder().sanitizer(SanitizerOptions::default()).rom(&[0x00, 0xEE]).build().unwrap();
+    let underflow = Report { pc: 0x200, opcode: 0x00EE, finding: Finding::StackUnderflow, call_stack: vec![] };
+    assert_eq!(chip8.step(), Err(StepError::Sanitizer(underflow)));
+
+    let randomized = |seed| {
+        let options = SanitizerOptions { randomize: true, stop_on_finding: false };
+        let chip8 = Chip8::builder().seed(seed).sanitizer(options).rom(&rom).build().unwrap();
+        (chip8.registers(), chip8.memory())
+    };
+    let (registers, memory) = randomized(7);
+    assert_eq!((registers, memory.clone()), randomized(7));
+    assert_eq!(&memory[0x200..0x210], &rom[..]);
+    assert!(memory[0x300..0x340].iter().any(|byte| *byte != 0));
+}
+
+#[test]
+fn profiles_a_frame() {
+    let mut chip8 = Chip8::builder().rom(&DRAW_ZERO).build().unwrap();
+    chip8.enable_profiler();
+    chip8.run_frame();
+    let profiler = chip8.profiler().unwrap();
+    assert_eq!(profiler.instructions(), 10);
+    // the JP loop takes the rest of the frame
+    assert_eq!(profiler.hotspots(1), vec![(0x208, 0x1208, 6)]);
+    let frame = profiler.frames().next().unwrap();
+    assert_eq!((frame.draws, frame.sprite_rows), (1, 5));
+    assert_eq!(profiler.folded(), "main 10\n");
+}
+
+#[test]
+fn merges_coverage_of_runs() {
+    let run = |key: Option<u8>| {
+        let rom = [
+            0xE0, 0x9E, // SKP V0
+            0x12, 0x00, // JP 0x200
+            0xF0, 0x29, // LD F, V0
+            0xD0, 0x05, // DRW V0, V0, 5
+            0x12, 0x08, // JP 0x208
+        ];
+        let mut chip8 = Chip8::builder().rom(&rom).build().unwrap();
+        chip8.enable_coverage();
+        if let Some(key) = key {
+            chip8.key_down(key);
+        }
+        chip8.run_frame();
+        chip8.take_coverage().unwrap()
+    };
+    let mut coverage = run(None);
+    assert_eq!(coverage.count(0x200, 0x20A, EXECUTED), 4);
+    coverage.merge(&run(Some(0)));
+    assert_eq!(coverage.count(0x200, 0x20A, EXECUTED), 10);
+    // the font sprite for 0
+    assert_eq!(coverage.count(0, 0x200, READ), 5);
+    assert!(coverage.disassembly(&[0xE0, 0x9E], 0x200).contains("x-- 0x200  E09E  SKP V0"));
+}
+
+#[test]
+fn tells_who_wrote_memory() {
+    let rom = [
+        0xA3, 0x00, // LD I, 0x300
+        0x60, 0x7B, // LD V0, 123
+        0xF0, 0x33, // LD B, V0
+        0xF0, 0x55, // LD [I], V0
+        0x12, 0x08, // JP 0x208
+    ];
+    let mut chip8 = Chip8::builder().rom(&rom).build().unwrap();
+    chip8.enable_provenance();
+    chip8.watch_writes(0x300, 0x301);
+    chip8.run_frame();
+    assert_eq!(chip8.last_writer(0x300), Some(MemoryWrite { addr: 0x300, value: 123, pc: 0x206, opcode: 0xF055, frame: 0 }));
+    assert_eq!(chip8.last_writer(0x302).map(|write| (write.pc, write.value)), Some((0x204, 3)));
+    assert_eq!(chip8.last_writer(0x208), None);
+    assert_eq!(chip8.write_history(0x300, 0x301).iter().map(|write| write.value).collect::<Vec<_>>(), vec![1, 123]);
+}
+
+#[test]
+fn cheats_freeze_and_poke() {
+    let rom = [
+        0xA3, 0x00, // LD I, 0x300
+        0xF0, 0x65, // LD V0, [I]
+        0x70, 0xFF, // ADD V0, 0xFF
+        0xA3, 0x00, // LD I, 0x300; the CHIP-8 load moved I past it
+        0xF0, 0x55, // LD [I], V0
+        0x12, 0x0A, // JP 0x20A
+    ];
+    let mut chip8 = Chip8::builder().rom(&rom).build().unwrap();
+    chip8.set_cheats(CheatList::parse("300=05 lives\n301:2A").unwrap());
+    assert_eq!(chip8.peek(0x301), 0x2A);
+    // the freeze is written before the frame, the ROM takes a life during it
+    chip8.run_frame();
+    assert_eq!(chip8.peek(0x300), 4);
+    chip8.run_frame();
+    assert_eq!(chip8.peek(0x300), 5);
+    chip8.remove_cheats(0x300);
+    chip8.poke(0x300, 9);
+    assert_eq!(chip8.peek(0x300), 9);
+    assert_eq!(chip8.cheats().to_string(), "301:2A\n");
+}
@@ -0,0 +1,39 @@
+//! wasm-pack test --node -- --features wasm
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use rust_wasm_chip8::init_headless;
+use wasm_bindgen_test::*;
+
+// draws the font sprite for 0 at (0, 0), then loops forever
+const DRAW_ZERO: [u8; 10] = [
+    0x60, 0x00, // LD V0, 0
+    0x61, 0x00, // LD V1, 0
+    0xF0, 0x29, // LD F, V0
+    0xD0, 0x15, // DRW V0, V1, 5
+    0x12, 0x08, // JP 0x208
+];
+
+#[wasm_bindgen_test]
+fn headless_draws_after_a_frame() {
+    let mut program = init_headless(&DRAW_ZERO).unwrap();
+    assert!(program.run_frames(1));
+    let width = program.framebuffer_width();
+    let pixels = program.framebuffer();
+    assert_eq!(pixels.len(), width * program.framebuffer_height());
+    // 0xF0, 0x90
+    assert_eq!(&pixels[0..5], &[1, 1, 1, 1, 0]);
+    assert_eq!(&pixels[width..width + 5], &[1, 0, 0, 1, 0]);
+    assert_eq!(program.pc(), 0x208);
+}
+
+#[wasm_bindgen_test]
+fn headless_stop_and_reset() {
+    let mut program = init_headless(&DRAW_ZERO).unwrap();
+    program.run_frames(3);
+    program.reset();
+    assert!(program.run_frames(1));
+    assert_eq!(program.framebuffer()[0], 1);
+    program.stop();
+    assert!(!program.run_frames(1));
+    assert!(program.is_stopped());
+}
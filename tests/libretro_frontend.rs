@@ -0,0 +1,358 @@
+//! A minimal libretro front-end: dlopens the built core and drives it like RetroArch would.
+//! cargo test --no-default-features --features libretro
+// with `python` the cdylib is a Python extension module that only loads inside an interpreter
+#![cfg(all(feature = "libretro", not(feature = "python"), target_os = "linux"))]
+
+use std::cell::{Cell, RefCell};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::path::PathBuf;
+
+#[link(name = "dl")]
+extern "C" {
+    fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+    fn dlerror() -> *const c_char;
+}
+
+const RTLD_NOW: c_int = 2;
+
+#[repr(C)]
+struct SystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct SystemAvInfo {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[repr(C)]
+struct Variable {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+#[repr(C)]
+struct KeyboardCallback {
+    callback: extern "C" fn(down: bool, keycode: c_uint, character: u32, key_modifiers: u16),
+}
+
+const JOYPAD_UP: c_uint = 4;
+
+thread_local! {
+    static OPTIONS: RefCell<Vec<(String, String)>> = const { RefCell::new(vec![]) };
+    static OVERRIDES: RefCell<Vec<(CString, CString)>> = const { RefCell::new(vec![]) };
+    static OPTIONS_UPDATED: Cell<bool> = const { Cell::new(false) };
+    static PIXEL_FORMAT: Cell<Option<c_uint>> = const { Cell::new(None) };
+    static KEYBOARD: Cell<Option<KeyboardCallback>> = const { Cell::new(None) };
+    static FRAME: RefCell<(Vec<u32>, usize, usize)> = const { RefCell::new((vec![], 0, 0)) };
+    static AUDIO: RefCell<Vec<i16>> = const { RefCell::new(vec![]) };
+    static BUTTONS: Cell<u16> = const { Cell::new(0) };
+}
+
+extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    unsafe {
+        match cmd {
+            // SET_PIXEL_FORMAT
+            10 => {
+                PIXEL_FORMAT.with(|f| f.set(Some(*(data as *const c_uint))));
+                true
+            }
+            // SET_KEYBOARD_CALLBACK
+            12 => {
+                let callback = &*(data as *const KeyboardCallback);
+                KEYBOARD.with(|k| k.set(Some(KeyboardCallback { callback: callback.callback })));
+                true
+            }
+            // GET_VARIABLE: overrides, else the first listed value
+            15 => {
+                let variable = &mut *(data as *mut Variable);
+                let key = CStr::from_ptr(variable.key).to_str().unwrap();
+                OVERRIDES.with(|o| {
+                    match o.borrow().iter().find(|(k, _)| k.to_str().unwrap() == key) {
+                        Some((_, value)) => {
+                            variable.value = value.as_ptr();
+                            true
+                        }
+                        None => false,
+                    }
+                })
+            }
+            // SET_VARIABLES
+            16 => {
+                let mut variable = data as *const Variable;
+                while !(*variable).key.is_null() {
+                    let key = CStr::from_ptr((*variable).key).to_string_lossy().into_owned();
+                    let value = CStr::from_ptr((*variable).value).to_string_lossy().into_owned();
+                    OPTIONS.with(|o| o.borrow_mut().push((key, value)));
+                    variable = variable.add(1);
+                }
+                true
+            }
+            // GET_VARIABLE_UPDATE
+            17 => {
+                *(data as *mut bool) = OPTIONS_UPDATED.with(|u| u.replace(false));
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = vec![0; width * height];
+    for y in 0..height {
+        let row = unsafe { std::slice::from_raw_parts((data as *const u8).add(y * pitch) as *const u32, width) };
+        pixels[y * width..(y + 1) * width].copy_from_slice(row);
+    }
+    FRAME.with(|f| *f.borrow_mut() = (pixels, width, height));
+}
+
+extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = unsafe { std::slice::from_raw_parts(data, frames * 2) };
+    AUDIO.with(|a| a.borrow_mut().extend_from_slice(samples));
+    frames
+}
+
+extern "C" fn input_poll() {}
+
+extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    // RETRO_DEVICE_JOYPAD
+    if port != 0 || device != 1 {
+        return 0;
+    }
+    BUTTONS.with(|b| (b.get() >> id & 1) as i16)
+}
+
+struct Core {
+    handle: *mut c_void,
+}
+
+impl Core {
+    fn load() -> Core {
+        let path = core_path();
+        let name = CString::new(path.to_str().unwrap()).unwrap();
+        let handle = unsafe { dlopen(name.as_ptr(), RTLD_NOW) };
+        if handle.is_null() {
+            panic!("dlopen {:?}: {:?}", path, unsafe { CStr::from_ptr(dlerror()) });
+        }
+        let core = Core { handle };
+        unsafe {
+            core.sym::<extern "C" fn(extern "C" fn(c_uint, *mut c_void) -> bool)>("retro_set_environment")(environment);
+            core.sym::<extern "C" fn(extern "C" fn(*const c_void, c_uint, c_uint, usize))>("retro_set_video_refresh")(video_refresh);
+            core.sym::<extern "C" fn(extern "C" fn(i16, i16))>("retro_set_audio_sample")(audio_sample);
+            core.sym::<extern "C" fn(extern "C" fn(*const i16, usize) -> usize)>("retro_set_audio_sample_batch")(audio_sample_batch);
+            core.sym::<extern "C" fn(extern "C" fn())>("retro_set_input_poll")(input_poll);
+            core.sym::<extern "C" fn(extern "C" fn(c_uint, c_uint, c_uint, c_uint) -> i16)>("retro_set_input_state")(input_state);
+            core.sym::<extern "C" fn()>("retro_init")();
+        }
+        core
+    }
+
+    unsafe fn sym<F: Copy>(&self, name: &str) -> F {
+        let name = CString::new(name).unwrap();
+        let symbol = dlsym(self.handle, name.as_ptr());
+        assert!(!symbol.is_null(), "missing {:?}", name);
+        std::mem::transmute_copy(&symbol)
+    }
+
+    fn load_game(&self, rom: &[u8]) -> bool {
+        let game = GameInfo { path: std::ptr::null(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: std::ptr::null() };
+        unsafe { self.sym::<extern "C" fn(*const GameInfo) -> bool>("retro_load_game")(&game) }
+    }
+
+    fn run(&self, frames: usize) {
+        for _ in 0..frames {
+            unsafe { self.sym::<extern "C" fn()>("retro_run")() };
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        unsafe {
+            let size = self.sym::<extern "C" fn() -> usize>("retro_serialize_size")();
+            let mut data = vec![0u8; size];
+            assert!(self.sym::<extern "C" fn(*mut c_void, usize) -> bool>("retro_serialize")(data.as_mut_ptr() as *mut c_void, size));
+            data
+        }
+    }
+
+    fn unserialize(&self, data: &[u8]) -> bool {
+        unsafe { self.sym::<extern "C" fn(*const c_void, usize) -> bool>("retro_unserialize")(data.as_ptr() as *const c_void, data.len()) }
+    }
+}
+
+impl Drop for Core {
+    fn drop(&mut self) {
+        unsafe {
+            self.sym::<extern "C" fn()>("retro_unload_game")();
+            self.sym::<extern "C" fn()>("retro_deinit")();
+        }
+    }
+}
+
+// cargo puts the cdylib next to the test binary's deps/ directory
+fn core_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("CHIP8_LIBRETRO_CORE") {
+        return path.into();
+    }
+    let deps = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    [deps.join("librust_wasm_chip8.so"), deps.parent().unwrap().join("librust_wasm_chip8.so")]
+        .into_iter()
+        .find(|p| p.exists())
+        .expect("build the core first, or set CHIP8_LIBRETRO_CORE")
+}
+
+fn frame() -> (Vec<u32>, usize, usize) {
+    FRAME.with(|f| f.borrow().clone())
+}
+
+// waits for a key in V0, draws its font sprite at (0, 0), beeps and loops
+const DRAW_KEY: [u8; 14] = [
+    0xF0, 0x0A, // LD V0, K
+    0xF0, 0x29, // LD F, V0
+    0x61, 0x00, // LD V1, 0
+    0xD1, 0x15, // DRW V1, V1, 5
+    0x62, 0x10, // LD V2, 16
+    0xF2, 0x18, // LD ST, V2
+    0x12, 0x0C, // JP 0x20C
+];
+
+#[test]
+fn system_info_and_options() {
+    let core = Core::load();
+    unsafe {
+        assert_eq!(core.sym::<extern "C" fn() -> c_uint>("retro_api_version")(), 1);
+        let mut info = std::mem::zeroed::<SystemInfo>();
+        core.sym::<extern "C" fn(*mut SystemInfo)>("retro_get_system_info")(&mut info);
+        assert_eq!(CStr::from_ptr(info.library_name).to_str().unwrap(), "rust-wasm-chip8");
+        assert!(!info.need_fullpath);
+        let extensions = CStr::from_ptr(info.valid_extensions).to_str().unwrap();
+        assert!(["ch8", "sc8", "xo8", "gif"].iter().all(|e| extensions.split('|').any(|x| x == *e)));
+        let mut av = SystemAvInfo::default();
+        core.sym::<extern "C" fn(*mut SystemAvInfo)>("retro_get_system_av_info")(&mut av);
+        assert_eq!((av.base_width, av.base_height), (64, 32));
+        assert_eq!(av.fps, 60.0);
+    }
+    let keys: Vec<String> = OPTIONS.with(|o| o.borrow().iter().map(|(k, _)| k.clone()).collect());
+    assert_eq!(keys, vec!["chip8_clock", "chip8_shift_quirk", "chip8_load_store_quirk"]);
+}
+
+#[test]
+fn joypad_draws_video_and_audio() {
+    let core = Core::load();
+    assert!(core.load_game(&DRAW_KEY));
+    assert_eq!(PIXEL_FORMAT.with(|f| f.get()), Some(1));
+    core.run(2);
+    let (pixels, width, height) = frame();
+    assert_eq!((width, height), (64, 32));
+    let background = pixels[0];
+    assert!(pixels.iter().all(|p| *p == background));
+    assert!(AUDIO.with(|a| a.borrow().iter().all(|s| *s == 0)));
+
+    // Up is keypad 2: F0 10 F0 80 F0
+    BUTTONS.with(|b| b.set(1 << JOYPAD_UP));
+    core.run(1);
+    let (pixels, _, _) = frame();
+    assert_ne!(pixels[0], background);
+    assert_eq!(pixels[64], background);
+    assert_ne!(pixels[64 + 3], background);
+    // the tone is on; 735 stereo samples a frame
+    AUDIO.with(|a| {
+        let audio = a.borrow();
+        assert_eq!(audio.len(), 3 * 735 * 2);
+        assert!(audio[2 * 735 * 2..].iter().any(|s| *s != 0));
+    });
+}
+
+#[test]
+fn keyboard_callback() {
+    let core = Core::load();
+    assert!(core.load_game(&DRAW_KEY));
+    core.run(1);
+    let keyboard = KEYBOARD.with(|k| k.take()).expect("keyboard callback registered");
+    // RETROK_w is keypad 5: F0 80 F0 10 F0
+    (keyboard.callback)(true, 'w' as c_uint, 'w' as u32, 0);
+    (keyboard.callback)(false, 'w' as c_uint, 'w' as u32, 0);
+    core.run(1);
+    let (pixels, _, _) = frame();
+    let background = pixels[63];
+    assert_ne!(pixels[64], background);
+    assert_eq!(pixels[64 + 3], background);
+}
+
+#[test]
+fn serialize_roundtrip() {
+    let core = Core::load();
+    assert!(core.load_game(&DRAW_KEY));
+    core.run(1);
+    let blank = core.serialize();
+    BUTTONS.with(|b| b.set(1 << JOYPAD_UP));
+    core.run(1);
+    let drawn = frame().0;
+    assert!(core.unserialize(&blank));
+    BUTTONS.with(|b| b.set(0));
+    core.run(1);
+    assert!(frame().0.iter().all(|p| *p == frame().0[0]));
+    assert!(core.unserialize(&blank));
+    BUTTONS.with(|b| b.set(1 << JOYPAD_UP));
+    core.run(1);
+    assert_eq!(frame().0, drawn);
+    assert!(!core.unserialize(&blank[1..]));
+}
+
+#[test]
+fn clock_option() {
+    // counts instructions: V0 += 1 every other one
+    let rom = [0x70, 0x01, 0x12, 0x00];
+    let core = Core::load();
+    OVERRIDES.with(|o| o.borrow_mut().push((CString::new("chip8_clock").unwrap(), CString::new("1200").unwrap())));
+    assert!(core.load_game(&rom));
+    core.run(1);
+    let state = core.serialize();
+    // header, 4 KiB memory, then V0
+    assert_eq!(state[4 + 4096], 10);
+    OVERRIDES.with(|o| o.borrow_mut()[0].1 = CString::new("600").unwrap());
+    OPTIONS_UPDATED.with(|u| u.set(true));
+    core.run(1);
+    assert_eq!(core.serialize()[4 + 4096], 15);
+    // auto goes back to the ROM's own clock, 600 by default
+    OVERRIDES.with(|o| o.borrow_mut()[0].1 = CString::new("1200").unwrap());
+    OPTIONS_UPDATED.with(|u| u.set(true));
+    core.run(1);
+    OVERRIDES.with(|o| o.borrow_mut()[0].1 = CString::new("auto").unwrap());
+    OPTIONS_UPDATED.with(|u| u.set(true));
+    core.run(1);
+    assert_eq!(core.serialize()[4 + 4096], 30);
+}
+
+#[test]
+fn null_state() {
+    let core = Core::load();
+    assert!(core.load_game(&DRAW_KEY));
+    unsafe {
+        assert!(!core.sym::<extern "C" fn(*const c_void, usize) -> bool>("retro_unserialize")(std::ptr::null(), 0));
+    }
+}
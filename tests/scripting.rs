@@ -0,0 +1,47 @@
+//! Runs the scripts in scripts/ headlessly against CATCH.
+//! cargo test --features scripting
+#![cfg(feature = "scripting")]
+
+use rust_wasm_chip8::scripting::{Script, ScriptError};
+use rust_wasm_chip8::{Chip8, Platform};
+
+// frames until the script stopped
+fn run(source: &str) -> Result<u64, ScriptError> {
+    let mut chip8 = Chip8::builder().rom(include_bytes!("../CATCH")).platform(Platform::Chip8).clock(600).seed(7).build().unwrap();
+    let mut script = Script::new(source)?;
+    for frame in 1..=10_000 {
+        chip8.run_script_frame(&mut script)?;
+        if script.is_done() {
+            assert!(chip8.is_stopped());
+            return Ok(frame);
+        }
+    }
+    panic!("the script never stopped");
+}
+
+#[test]
+fn example_scripts_pass() {
+    assert_eq!(run(include_str!("../scripts/catch_autoplay.rhai")), Ok(1200));
+    assert_eq!(run(include_str!("../scripts/catch_infinite_lives.rhai")), Ok(600));
+}
+
+#[test]
+fn scripts_change_the_machine() {
+    let script = r#"
+        poke(0x300, 7);
+        on_pc(0x202, |pc| { set_v(3, 42); });
+        on_frame(|frame| {
+            assert(peek(0x300) == 7);
+            assert(v(3) == 42, `V3 is ${v(3)}`);
+            stop();
+        });
+    "#;
+    assert_eq!(run(script), Ok(1));
+}
+
+#[test]
+fn failures_are_reported() {
+    assert_eq!(run(r#"on_frame(|frame| assert(frame < 3, "too late"));"#), Err(ScriptError::Assertion("too late".into())));
+    assert!(matches!(run("on_frame(|frame| v(16));"), Err(ScriptError::Runtime(_))));
+    assert!(matches!(Script::new("on_frame(|frame| "), Err(ScriptError::Compile(_))));
+}